@@ -0,0 +1,95 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+use crate::protocol::AuditEntry;
+
+/// The maximum number of the most recent entries kept in memory.
+const RETAINED_ENTRIES: usize = 10000;
+
+/// Represents the append-only log of administrative actions that can be shared among all the clients.
+///
+/// Every entry is written to the `audit` log target, while only the most recent entries are kept in memory
+/// for querying.
+#[derive(Clone)]
+pub struct SharedAuditLog {
+    entries: Arc<RwLock<VecDeque<AuditEntry>>>,
+    retained_entries: usize,
+}
+impl SharedAuditLog {
+    pub fn new() -> Self {
+        Self::with_retained_entries(RETAINED_ENTRIES)
+    }
+
+    fn with_retained_entries(retained_entries: usize) -> Self {
+        SharedAuditLog {
+            entries: Arc::default(),
+            retained_entries,
+        }
+    }
+
+    pub async fn append(&self, entry: AuditEntry) {
+        match serde_json::to_string(&entry) {
+            Ok(json) => info!(target: "audit", "{}", json),
+            Err(e) => error!(target: "audit", "Failed to serialize audit entry {:?}: {}", entry, e),
+        }
+        let mut entries = self.entries.write().await;
+        if entries.len() == self.retained_entries {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    /// Returns up to the given number of the most recent entries, oldest first.
+    pub async fn read_recent(&self, limit: usize) -> Vec<AuditEntry> {
+        let entries = self.entries.read().await;
+        entries
+            .iter()
+            .skip(entries.len().saturating_sub(limit))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::protocol::{test_data, AuditEntry};
+
+    use super::SharedAuditLog;
+
+    fn entry(seconds: u64) -> AuditEntry {
+        AuditEntry {
+            timestamp: test_data::timestamp(seconds),
+            ..test_data::audit_entry()
+        }
+    }
+
+    #[tokio::test]
+    async fn read_recent_entries_oldest_first() {
+        let audit_log = SharedAuditLog::new();
+        audit_log.append(entry(1)).await;
+        audit_log.append(entry(2)).await;
+        audit_log.append(entry(3)).await;
+
+        // when
+        let entries = audit_log.read_recent(2).await;
+
+        // then
+        assert_eq!(entries, vec![entry(2), entry(3)]);
+    }
+
+    #[tokio::test]
+    async fn retain_only_most_recent_entries() {
+        let audit_log = SharedAuditLog::with_retained_entries(2);
+        audit_log.append(entry(1)).await;
+        audit_log.append(entry(2)).await;
+        audit_log.append(entry(3)).await;
+
+        // when
+        let entries = audit_log.read_recent(10).await;
+
+        // then
+        assert_eq!(entries, vec![entry(2), entry(3)]);
+    }
+}
@@ -0,0 +1,467 @@
+use futures_util::stream::{self, BoxStream};
+use futures_util::{SinkExt, StreamExt};
+use std::time::Duration;
+use tokio::sync::{mpsc, watch};
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::protocol::{Input, LobbyName, Output, Password, Table, TableFilter, TableId, TablePage, Username};
+
+/// Represents the configuration of the Lobby API client.
+#[derive(Clone, Debug)]
+pub struct ClientConfig {
+    /// The WebSocket URL of the Lobby API, e.g. `ws://localhost:9000/lobby_api`.
+    pub url: String,
+    pub username: Username,
+    pub password: Password,
+    /// The lobby, whose tables are mirrored by the client.
+    pub lobby: LobbyName,
+    pub filter: TableFilter,
+    pub page: Option<TablePage>,
+    /// The delay before reconnecting once the connection is lost or cannot be established.
+    pub reconnect_delay: Duration,
+}
+impl ClientConfig {
+    pub fn new(url: impl Into<String>, username: Username, password: Password) -> Self {
+        ClientConfig {
+            url: url.into(),
+            username,
+            password,
+            lobby: LobbyName::default(),
+            filter: TableFilter::default(),
+            page: None,
+            reconnect_delay: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Represents the outcome of applying the output message to the mirrored tables.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MirrorUpdate {
+    Changed,
+    Unchanged,
+    /// The output message refers to the table missing from the mirror, so that the mirror differs from the server
+    /// and should be replaced with the fresh table list.
+    Diverged,
+}
+
+/// Represents the status of the background connection of the client.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ClientStatus {
+    Connecting,
+    LoggedIn,
+    /// The server has rejected the credentials, so that the client has stopped without reconnecting.
+    LoginFailed,
+}
+
+/// Represents the local mirror of the ordered table list, which is kept up to date by applying output messages.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TableMirror {
+    tables: Vec<Table>,
+}
+impl TableMirror {
+    pub fn tables(&self) -> &[Table] {
+        &self.tables
+    }
+
+    /// Applies the output message to the mirrored tables and returns whether they have changed or diverged.
+    pub fn apply(&mut self, output: &Output) -> MirrorUpdate {
+        match output {
+            Output::TableList { tables, .. } => {
+                self.tables = tables.iter().map(|table| Table::clone(table)).collect();
+                MirrorUpdate::Changed
+            }
+            Output::TableAdded { after_id, table, .. } => match self.index_after(*after_id) {
+                Some(index) => {
+                    self.tables.insert(index, table.clone());
+                    MirrorUpdate::Changed
+                }
+                None => MirrorUpdate::Diverged,
+            },
+            Output::TableUpdated { table, .. } => match self.tables.iter_mut().find(|other| other.id == table.id) {
+                Some(other) => {
+                    *other = table.clone();
+                    MirrorUpdate::Changed
+                }
+                None => MirrorUpdate::Diverged,
+            },
+            Output::TablePatched { id, changes, .. } => match self.tables.iter_mut().find(|table| table.id == *id) {
+                Some(table) => {
+                    table.patch_with(changes.clone());
+                    MirrorUpdate::Changed
+                }
+                None => MirrorUpdate::Diverged,
+            },
+            Output::TableMoved { id, after_id, .. } => match self.tables.iter().position(|table| table.id == *id) {
+                Some(index) => {
+                    let table = self.tables.remove(index);
+                    match self.index_after(*after_id) {
+                        Some(new_index) => {
+                            self.tables.insert(new_index, table);
+                            MirrorUpdate::Changed
+                        }
+                        None => {
+                            self.tables.insert(index, table);
+                            MirrorUpdate::Diverged
+                        }
+                    }
+                }
+                None => MirrorUpdate::Diverged,
+            },
+            Output::TableRemoved { id, .. } => {
+                let len = self.tables.len();
+                self.tables.retain(|table| table.id != *id);
+                if self.tables.len() != len {
+                    MirrorUpdate::Changed
+                } else {
+                    MirrorUpdate::Unchanged
+                }
+            }
+            Output::BatchApplied { outputs, .. } => {
+                let mut update = MirrorUpdate::Unchanged;
+                for output in outputs {
+                    match self.apply(output) {
+                        MirrorUpdate::Diverged => return MirrorUpdate::Diverged,
+                        MirrorUpdate::Changed => update = MirrorUpdate::Changed,
+                        MirrorUpdate::Unchanged => {}
+                    }
+                }
+                update
+            }
+            Output::LobbyDeleted { .. } => {
+                self.tables.clear();
+                MirrorUpdate::Changed
+            }
+            _ => MirrorUpdate::Unchanged,
+        }
+    }
+
+    fn index_after(&self, after_id: TableId) -> Option<usize> {
+        if after_id == TableId::ABSENT {
+            Some(0)
+        } else {
+            self.tables
+                .iter()
+                .position(|table| table.id == after_id)
+                .map(|index| index + 1)
+        }
+    }
+}
+
+/// Represents the Lobby API client, which keeps the connection to the server in the background.
+///
+/// The client logs in, subscribes to tables of the configured lobby and mirrors them locally. Once the
+/// connection is lost or the mirror diverges from the server, the client subscribes again, receiving the fresh
+/// table list. The background connection is closed once the client is dropped.
+pub struct LobbyClient {
+    input_sender: mpsc::UnboundedSender<Input>,
+    output_sender: mpsc::UnboundedSender<mpsc::UnboundedSender<Output>>,
+    tables_receiver: watch::Receiver<Vec<Table>>,
+    status_receiver: watch::Receiver<ClientStatus>,
+}
+impl LobbyClient {
+    /// Starts connecting to the server in the background and returns immediately.
+    pub fn connect(config: ClientConfig) -> Self {
+        let (input_sender, input_receiver) = mpsc::unbounded_channel();
+        let (output_sender, output_receiver) = mpsc::unbounded_channel();
+        let (tables_sender, tables_receiver) = watch::channel(vec![]);
+        let (status_sender, status_receiver) = watch::channel(ClientStatus::Connecting);
+        tokio::task::spawn(run(config, input_receiver, output_receiver, tables_sender, status_sender));
+        LobbyClient {
+            input_sender,
+            output_sender,
+            tables_receiver,
+            status_receiver,
+        }
+    }
+
+    /// Sends the input message to the server, it is sent as soon as the client is connected.
+    pub fn send(&self, input: Input) -> Result<(), String> {
+        self.input_sender.send(input).map_err(|e| e.to_string())
+    }
+
+    /// Returns the current snapshot of mirrored tables.
+    pub fn tables(&self) -> Vec<Table> {
+        self.tables_receiver.borrow().clone()
+    }
+
+    /// Returns the stream of snapshots of mirrored tables, which yields each time they change.
+    pub fn snapshots(&self) -> BoxStream<'static, Vec<Table>> {
+        stream::unfold(self.tables_receiver.clone(), |mut tables_receiver| async move {
+            tables_receiver.changed().await.ok()?;
+            let tables = tables_receiver.borrow_and_update().clone();
+            Some((tables, tables_receiver))
+        })
+        .boxed()
+    }
+
+    /// Returns the current status of the background connection.
+    pub fn status(&self) -> ClientStatus {
+        *self.status_receiver.borrow()
+    }
+
+    /// Returns the stream of statuses of the background connection, which yields each time it changes.
+    pub fn statuses(&self) -> BoxStream<'static, ClientStatus> {
+        stream::unfold(self.status_receiver.clone(), |mut status_receiver| async move {
+            status_receiver.changed().await.ok()?;
+            let status = *status_receiver.borrow_and_update();
+            Some((status, status_receiver))
+        })
+        .boxed()
+    }
+
+    /// Returns the stream of all output messages received from the server from now on.
+    pub fn outputs(&self) -> BoxStream<'static, Output> {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        // The stream is simply empty once the background connection has stopped
+        let _ = self.output_sender.send(sender);
+        stream::poll_fn(move |context| receiver.poll_recv(context)).boxed()
+    }
+}
+
+async fn run(
+    config: ClientConfig,
+    mut input_receiver: mpsc::UnboundedReceiver<Input>,
+    mut output_receiver: mpsc::UnboundedReceiver<mpsc::UnboundedSender<Output>>,
+    tables_sender: watch::Sender<Vec<Table>>,
+    status_sender: watch::Sender<ClientStatus>,
+) {
+    let mut output_senders: Vec<mpsc::UnboundedSender<Output>> = vec![];
+    let mut mirror = TableMirror::default();
+    // Stop reconnecting once nobody is interested in tables anymore
+    while !tables_sender.is_closed() {
+        let (mut ws_sender, mut ws_receiver) = match tokio_tungstenite::connect_async(&config.url).await {
+            Ok((ws, _)) => ws.split(),
+            Err(e) => {
+                debug!("Failed to connect to {}: {}", config.url, e);
+                tokio::time::sleep(config.reconnect_delay).await;
+                continue;
+            }
+        };
+        debug!("Connected to {}", config.url);
+
+        let inputs = [
+            Input::Login {
+                username: config.username.clone(),
+                password: config.password.clone(),
+            },
+            subscribe_tables(&config),
+        ];
+        for input in inputs {
+            if let Err(e) = send(&mut ws_sender, &input).await {
+                debug!("Failed to send WebSocket message to {}: {}", config.url, e);
+            }
+        }
+
+        loop {
+            tokio::select! {
+                input = input_receiver.recv() => match input {
+                    Some(input) => {
+                        if let Err(e) = send(&mut ws_sender, &input).await {
+                            debug!("Failed to send WebSocket message to {}: {}", config.url, e);
+                            break;
+                        }
+                    }
+                    // The client has been dropped, so stop without reconnecting
+                    None => {
+                        let _ = ws_sender.close().await;
+                        return;
+                    }
+                },
+                sender = output_receiver.recv() => match sender {
+                    Some(sender) => output_senders.push(sender),
+                    None => {
+                        let _ = ws_sender.close().await;
+                        return;
+                    }
+                },
+                message = ws_receiver.next() => match message {
+                    Some(Ok(Message::Text(text))) => match serde_json::from_str::<Output>(&text) {
+                        Ok(output) => {
+                            if output.lobby() == Some(&config.lobby) {
+                                match mirror.apply(&output) {
+                                    MirrorUpdate::Changed => {
+                                        tables_sender.send_replace(mirror.tables().to_vec());
+                                    }
+                                    MirrorUpdate::Unchanged => {}
+                                    MirrorUpdate::Diverged => {
+                                        warn!("Tables mirrored from {} have diverged, subscribing again", config.url);
+                                        if let Err(e) = send(&mut ws_sender, &subscribe_tables(&config)).await {
+                                            debug!("Failed to send WebSocket message to {}: {}", config.url, e);
+                                            break;
+                                        }
+                                    }
+                                }
+                            }
+                            match output {
+                                Output::LoginSuccessful { .. } => {
+                                    status_sender.send_replace(ClientStatus::LoggedIn);
+                                }
+                                Output::LoginFailed => {
+                                    error!("Failed to log in to {} as {:?}", config.url, config.username);
+                                    status_sender.send_replace(ClientStatus::LoginFailed);
+                                    output_senders.retain(|sender| sender.send(output.clone()).is_ok());
+                                    return;
+                                }
+                                _ => {}
+                            }
+                            output_senders.retain(|sender| sender.send(output.clone()).is_ok());
+                        }
+                        Err(e) => {
+                            error!("Failed to deserialize WebSocket message from {}: {}", config.url, e);
+                        }
+                    },
+                    Some(Ok(_)) => {}
+                    Some(Err(e)) => {
+                        debug!("Failed to receive WebSocket message from {}: {}", config.url, e);
+                        break;
+                    }
+                    None => break,
+                },
+            }
+        }
+        debug!("Disconnected from {}, reconnecting", config.url);
+        status_sender.send_replace(ClientStatus::Connecting);
+        tokio::time::sleep(config.reconnect_delay).await;
+    }
+}
+
+fn subscribe_tables(config: &ClientConfig) -> Input {
+    Input::SubscribeTables {
+        lobby: config.lobby.clone(),
+        filter: config.filter.clone(),
+        page: config.page,
+    }
+}
+
+async fn send<S>(ws_sender: &mut S, input: &Input) -> Result<(), String>
+where
+    S: SinkExt<Message> + Unpin,
+    S::Error: ToString,
+{
+    let text = serde_json::to_string(input).map_err(|e| e.to_string())?;
+    ws_sender.send(Message::Text(text)).await.map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::protocol::{test_data, LobbyName, Output, TableId};
+
+    use super::{MirrorUpdate, TableMirror};
+
+    fn mirror() -> TableMirror {
+        let mut mirror = TableMirror::default();
+        mirror.apply(&test_data::table_list());
+        mirror
+    }
+
+    #[test]
+    fn apply_table_added() {
+        let mut mirror = mirror();
+
+        // when
+        let update = mirror.apply(&Output::TableAdded {
+            lobby: LobbyName::default(),
+            after_id: test_data::table_james_bond().id,
+            table: test_data::table_foo_fighters(),
+        });
+
+        // then
+        assert_eq!(update, MirrorUpdate::Changed);
+        assert_eq!(
+            mirror.tables(),
+            [
+                test_data::table_james_bond(),
+                test_data::table_foo_fighters(),
+                test_data::table_mission_impossible()
+            ]
+        );
+    }
+
+    #[test]
+    fn apply_table_moved_in_front() {
+        let mut mirror = mirror();
+
+        // when
+        let update = mirror.apply(&Output::TableMoved {
+            lobby: LobbyName::default(),
+            id: test_data::table_mission_impossible().id,
+            after_id: TableId::ABSENT,
+        });
+
+        // then
+        assert_eq!(update, MirrorUpdate::Changed);
+        assert_eq!(mirror.tables(), [test_data::table_mission_impossible(), test_data::table_james_bond()]);
+    }
+
+    #[test]
+    fn report_divergence_when_table_moved_after_missing_table() {
+        let mut mirror = mirror();
+
+        // when
+        let update = mirror.apply(&Output::TableMoved {
+            lobby: LobbyName::default(),
+            id: test_data::table_james_bond().id,
+            after_id: test_data::table_foo_fighters().id,
+        });
+
+        // then
+        assert_eq!(update, MirrorUpdate::Diverged);
+        assert_eq!(mirror, self::mirror());
+    }
+
+    #[test]
+    fn apply_batch_applied() {
+        let mut mirror = mirror();
+
+        // when
+        let update = mirror.apply(&test_data::batch_applied());
+
+        // then
+        assert_eq!(update, MirrorUpdate::Changed);
+        assert_eq!(mirror.tables(), [test_data::table_james_bond(), test_data::table_mission_impossible()]);
+    }
+
+    #[test]
+    fn report_divergence_when_missing_table_is_updated() {
+        let mut mirror = mirror();
+
+        // when
+        let update = mirror.apply(&test_data::table_updated());
+
+        // then
+        assert_eq!(update, MirrorUpdate::Diverged);
+        assert_eq!(mirror, self::mirror());
+    }
+
+    #[test]
+    fn report_divergence_when_missing_table_is_patched() {
+        let mut mirror = mirror();
+
+        // when
+        let update = mirror.apply(&Output::TablePatched {
+            lobby: LobbyName::default(),
+            id: test_data::table_foo_fighters().id,
+            changes: test_data::table_changes_capacity(),
+        });
+
+        // then
+        assert_eq!(update, MirrorUpdate::Diverged);
+        assert_eq!(mirror, self::mirror());
+    }
+
+    #[test]
+    fn not_apply_removal_of_missing_table() {
+        let mut mirror = mirror();
+
+        // when
+        let update = mirror.apply(&Output::TableRemoved {
+            lobby: LobbyName::default(),
+            id: test_data::table_foo_fighters().id,
+        });
+
+        // then
+        assert_eq!(update, MirrorUpdate::Unchanged);
+        assert_eq!(mirror, self::mirror());
+    }
+}
@@ -0,0 +1,349 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use crate::ordered_tables::OrderedTables;
+use crate::protocol::{Output, Table, TableId, TableRevision, Timestamp, Username};
+
+/// The maximum number of the most recent changes kept for each lobby.
+const RETAINED_CHANGES: usize = 100000;
+
+/// Represents the change to tables of the lobby along with the time and the user, who has made it.
+struct LobbyChange {
+    revision: u64,
+    timestamp: Timestamp,
+    username: Option<Username>,
+    /// The change as it has been broadcasted to subscribers, but never the batch, which is split into changes.
+    output: Output,
+}
+
+/// Represents the log of changes to tables of the lobby, which allows to find versions of each table and
+/// to reconstruct all the tables in their order as of any time since the log is retained.
+///
+/// Only the most recent changes are kept along with tables as of the oldest of them. Older changes are
+/// folded into these tables, so that the log takes limited memory.
+pub struct LobbyHistory {
+    /// Tables as of the time, since which changes are retained, shared with replays in progress.
+    tables: Arc<OrderedTables>,
+    since: Timestamp,
+    changes: VecDeque<Arc<LobbyChange>>,
+    next_revision: u64,
+    retained_changes: usize,
+}
+impl LobbyHistory {
+    /// Starts the history of the lobby with the given tables as of the given time.
+    pub fn new(tables: OrderedTables, since: Timestamp) -> Self {
+        Self::with_retained_changes(tables, since, RETAINED_CHANGES)
+    }
+
+    fn with_retained_changes(tables: OrderedTables, since: Timestamp, retained_changes: usize) -> Self {
+        LobbyHistory {
+            tables: Arc::new(tables),
+            since,
+            changes: VecDeque::new(),
+            next_revision: 1,
+            retained_changes,
+        }
+    }
+
+    /// Records the change made by the given user at the given time, splitting the batch into its changes.
+    pub fn record(&mut self, timestamp: Timestamp, username: Option<Username>, output: Output) {
+        if let Output::BatchApplied { outputs, .. } = output {
+            for output in outputs {
+                self.record(timestamp, username.clone(), output);
+            }
+            return;
+        }
+        if output.table_ids().is_empty() {
+            return;
+        }
+        if self.changes.len() == self.retained_changes {
+            if let Some(change) = self.changes.pop_front() {
+                apply(Arc::make_mut(&mut self.tables), &change.output);
+                self.since = change.timestamp;
+            }
+        }
+        self.changes.push_back(Arc::new(LobbyChange {
+            revision: self.next_revision,
+            timestamp,
+            username,
+            output,
+        }));
+        self.next_revision += 1;
+    }
+
+    /// Returns versions of the table oldest first, starting from the version as of the time, since which
+    /// changes are retained, if the table has existed back then.
+    pub fn table_revisions(&self, id: TableId) -> Vec<TableRevision> {
+        let mut table = self.tables.get(id).cloned();
+        let oldest_revision = table.clone().map(|table| TableRevision {
+            revision: 0,
+            timestamp: self.since,
+            username: None,
+            table: Some(table),
+        });
+        let revisions = self
+            .changes
+            .iter()
+            .filter(|change| change.output.table_ids().contains(&id))
+            .map(|change| {
+                table = revise(table.take(), &change.output);
+                TableRevision {
+                    revision: change.revision,
+                    timestamp: change.timestamp,
+                    username: change.username.clone(),
+                    table: table.clone(),
+                }
+            });
+        oldest_revision.into_iter().chain(revisions).collect()
+    }
+
+    /// Returns the replay of tables as of the given time, including all the changes made at that time. The replay
+    /// only shares the retained tables and changes, so that it can be run once the history is no longer locked.
+    pub fn replay_until(&self, timestamp: Timestamp) -> Result<TablesReplay, String> {
+        if timestamp < self.since {
+            return Err(format!(
+                "Cannot find tables as of {:?}, changes are retained since {:?}",
+                timestamp, self.since
+            ));
+        }
+        Ok(TablesReplay {
+            timestamp,
+            tables: self.tables.clone(),
+            changes: self
+                .changes
+                .iter()
+                .take_while(|change| change.timestamp <= timestamp)
+                .cloned()
+                .collect(),
+        })
+    }
+}
+
+/// Represents tables as of the time, since which changes are retained, along with changes to replay on them.
+pub struct TablesReplay {
+    /// The time, as of which tables are replayed.
+    timestamp: Timestamp,
+    tables: Arc<OrderedTables>,
+    changes: Vec<Arc<LobbyChange>>,
+}
+impl TablesReplay {
+    /// Returns tables in their order once all the changes are applied. Statuses are changed by the server rather
+    /// than recorded, so they are found from schedules of tables as of the replayed time instead.
+    pub fn tables(self) -> Vec<Table> {
+        let mut tables = Arc::unwrap_or_clone(self.tables);
+        for change in &self.changes {
+            apply(&mut tables, &change.output);
+        }
+        tables
+            .iter()
+            .map(|table| Table {
+                status: table.status_at(self.timestamp),
+                ..table.clone()
+            })
+            .collect()
+    }
+}
+
+/// Applies the change to tables the same way it has been applied to the lobby.
+fn apply(tables: &mut OrderedTables, output: &Output) {
+    match output {
+        Output::TableAdded { after_id, table, .. } => tables.insert_after(*after_id, table.clone()),
+        Output::TableMoved { id, after_id, .. } => {
+            tables.move_after(*id, *after_id);
+        }
+        Output::TableRemoved { id, .. } => {
+            tables.remove(*id);
+        }
+        _ => {
+            for id in output.table_ids() {
+                if let Some(table) = revise(tables.get(id).cloned(), output) {
+                    tables.replace(table);
+                }
+            }
+        }
+    }
+}
+
+/// Returns the version of the table resulting from the change, absent once the table has been removed.
+fn revise(table: Option<Table>, output: &Output) -> Option<Table> {
+    match output {
+        Output::TableAdded { table, .. } | Output::TableUpdated { table, .. } => Some(table.clone()),
+        Output::TablePatched { changes, .. } => table.map(|mut table| {
+            table.patch_with(changes.clone());
+            table
+        }),
+        Output::TableRemoved { .. } => None,
+        _ => table,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::ordered_tables::OrderedTables;
+    use crate::protocol::{test_data, LobbyName, Output, Table, TableChanges, TableId, TableName, TableStatus};
+
+    use super::{LobbyHistory, TablesReplay};
+
+    fn history(retained_changes: usize) -> LobbyHistory {
+        let mut tables = OrderedTables::default();
+        tables.insert_after(TableId::ABSENT, test_data::table_james_bond());
+        LobbyHistory::with_retained_changes(tables, test_data::timestamp(100), retained_changes)
+    }
+
+    fn renamed_foo_fighters() -> Table {
+        Table {
+            name: TableName::new(String::from("Pink Floyd")),
+            ..test_data::table_foo_fighters()
+        }
+    }
+
+    fn record_changes(history: &mut LobbyHistory) {
+        let admin = Some(test_data::username_admin());
+        history.record(test_data::timestamp(200), admin.clone(), test_data::table_added());
+        history.record(
+            test_data::timestamp(300),
+            admin.clone(),
+            Output::TablePatched {
+                lobby: LobbyName::default(),
+                id: test_data::table_foo_fighters().id,
+                changes: TableChanges {
+                    name: Some(renamed_foo_fighters().name),
+                    ..TableChanges::default()
+                },
+            },
+        );
+        history.record(test_data::timestamp(400), admin, test_data::table_removed());
+    }
+
+    #[test]
+    fn find_table_revisions() {
+        let mut history = history(10);
+        record_changes(&mut history);
+
+        // when
+        let revisions = history.table_revisions(test_data::table_foo_fighters().id);
+
+        // then
+        let tables: Vec<Option<Table>> = revisions.iter().map(|revision| revision.table.clone()).collect();
+        assert_eq!(
+            tables,
+            vec![
+                Some(test_data::table_foo_fighters()),
+                Some(renamed_foo_fighters()),
+                None
+            ]
+        );
+        let numbers: Vec<u64> = revisions.iter().map(|revision| revision.revision).collect();
+        assert_eq!(numbers, vec![1, 2, 3]);
+        assert_eq!(revisions[1].timestamp, test_data::timestamp(300));
+        assert_eq!(revisions[1].username, Some(test_data::username_admin()));
+    }
+
+    #[test]
+    fn reconstruct_tables_at_given_time() {
+        let mut history = history(10);
+        record_changes(&mut history);
+
+        // when
+        let tables = [100, 250, 300, 400].map(|seconds| {
+            history
+                .replay_until(test_data::timestamp(seconds))
+                .map(TablesReplay::tables)
+        });
+
+        // then
+        assert_eq!(tables[0], Ok(vec![test_data::table_james_bond()]));
+        assert_eq!(tables[1], Ok(vec![test_data::table_foo_fighters(), test_data::table_james_bond()]));
+        assert_eq!(tables[2], Ok(vec![renamed_foo_fighters(), test_data::table_james_bond()]));
+        assert_eq!(tables[3], Ok(vec![test_data::table_james_bond()]));
+    }
+
+    #[test]
+    fn find_status_of_tables_as_of_given_time() {
+        let mut history = history(10);
+        let scheduled_foo_fighters = Table {
+            opens_at: Some(test_data::timestamp(300)),
+            closes_at: Some(test_data::timestamp(500)),
+            status: TableStatus::Scheduled,
+            ..test_data::table_foo_fighters()
+        };
+        history.record(
+            test_data::timestamp(200),
+            Some(test_data::username_admin()),
+            Output::TableAdded {
+                lobby: LobbyName::default(),
+                after_id: TableId::ABSENT,
+                table: scheduled_foo_fighters,
+            },
+        );
+
+        // when
+        let statuses = [200, 300, 500].map(|seconds| {
+            history
+                .replay_until(test_data::timestamp(seconds))
+                .map(|replay| replay.tables()[0].status)
+        });
+
+        // then
+        assert_eq!(
+            statuses,
+            [
+                Ok(TableStatus::Scheduled),
+                Ok(TableStatus::Open),
+                Ok(TableStatus::Closed)
+            ]
+        );
+    }
+
+    #[test]
+    fn fold_changes_beyond_retained_ones() {
+        let mut history = history(2);
+        record_changes(&mut history);
+
+        // when
+        let tables_before = history
+            .replay_until(test_data::timestamp(150))
+            .map(TablesReplay::tables);
+        let tables_after = history
+            .replay_until(test_data::timestamp(300))
+            .map(TablesReplay::tables);
+        let revisions = history.table_revisions(test_data::table_foo_fighters().id);
+
+        // then
+        assert!(tables_before.is_err(), "Tables before the oldest retained change should not be found");
+        assert_eq!(tables_after, Ok(vec![renamed_foo_fighters(), test_data::table_james_bond()]));
+        let numbers: Vec<u64> = revisions.iter().map(|revision| revision.revision).collect();
+        assert_eq!(numbers, vec![0, 2, 3]);
+    }
+
+    #[test]
+    fn replay_tables_once_history_has_changed() {
+        let mut history = history(1);
+        history.record(test_data::timestamp(200), Some(test_data::username_admin()), test_data::table_added());
+        let replay = history.replay_until(test_data::timestamp(200));
+
+        // when
+        history.record(test_data::timestamp(400), Some(test_data::username_admin()), test_data::table_removed());
+
+        // then
+        let tables = replay.map(TablesReplay::tables);
+        assert_eq!(tables, Ok(vec![test_data::table_foo_fighters(), test_data::table_james_bond()]));
+    }
+
+    #[test]
+    fn split_batch_into_changes() {
+        let mut history = history(10);
+
+        // when
+        history.record(test_data::timestamp(200), None, test_data::batch_applied());
+
+        // then
+        let numbers: Vec<u64> = history
+            .table_revisions(test_data::table_foo_fighters().id)
+            .iter()
+            .map(|revision| revision.revision)
+            .collect();
+        assert_eq!(numbers, vec![1, 2]);
+    }
+}
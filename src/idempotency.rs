@@ -0,0 +1,301 @@
+use std::collections::hash_map::{DefaultHasher, Entry};
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+use crate::protocol::{IdempotencyKey, Input, Output, Username};
+
+/// The time idempotency keys are remembered for, unless configured otherwise.
+pub const IDEMPOTENCY_WINDOW: Duration = Duration::from_secs(600);
+
+/// Represents the idempotency key of the particular user, so that keys of different users never clash.
+type ScopedKey = (Option<Username>, IdempotencyKey);
+
+/// Represents the hash of the request made with the idempotency key, so that the key reused with another request
+/// is told apart from the retry.
+type Fingerprint = u64;
+
+fn fingerprint(request: &Input) -> Fingerprint {
+    let mut hasher = DefaultHasher::new();
+    request.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Represents the change made with the idempotency key, which is either still being applied or already applied.
+enum RememberedChange {
+    /// The change is being applied, while retries wait until the receiver sees the reservation released.
+    InFlight {
+        fingerprint: Fingerprint,
+        released: watch::Receiver<()>,
+    },
+    Applied {
+        fingerprint: Fingerprint,
+        output: Output,
+    },
+}
+
+#[derive(Default)]
+struct RememberedOutputs {
+    changes: HashMap<ScopedKey, RememberedChange>,
+    /// Keys in the order they expire in, as they are all remembered for the same time once applied.
+    expirations: VecDeque<(Instant, ScopedKey)>,
+}
+impl RememberedOutputs {
+    fn forget_expired(&mut self, now: Instant) {
+        while let Some((expires_at, _)) = self.expirations.front() {
+            if *expires_at > now {
+                break;
+            }
+            if let Some((_, key)) = self.expirations.pop_front() {
+                self.changes.remove(&key);
+            }
+        }
+    }
+}
+
+/// Represents the outcome of reserving the idempotency key for the change.
+pub enum Reservation {
+    /// The key is reserved, so that the change should be applied and its outcome completed.
+    Reserved(KeyReservation),
+    /// The change has already been applied with the key, so that the client should receive the original output.
+    Replayed(Output),
+    /// The key has been used with another request, so that the change should be rejected.
+    Mismatched,
+}
+
+/// Represents the idempotency key reserved for the change being applied.
+///
+/// Retries with the same key wait until the reservation is completed. Once dropped without being completed, the key
+/// is released, so that the change can be retried.
+pub struct KeyReservation {
+    remembered: Arc<Mutex<RememberedOutputs>>,
+    key: Option<ScopedKey>,
+    fingerprint: Fingerprint,
+    window: Duration,
+    _released: watch::Sender<()>,
+}
+impl KeyReservation {
+    /// Remembers the output of the applied change until the window passes, or releases the key if the change failed.
+    pub fn complete(mut self, output: Option<Output>) {
+        let Some(key) = self.key.take() else {
+            return;
+        };
+        let now = Instant::now();
+        let mut remembered = self.remembered.lock().unwrap();
+        remembered.forget_expired(now);
+        match output {
+            Some(output) => {
+                let fingerprint = self.fingerprint;
+                remembered
+                    .changes
+                    .insert(key.clone(), RememberedChange::Applied { fingerprint, output });
+                remembered.expirations.push_back((now + self.window, key));
+            }
+            None => {
+                remembered.changes.remove(&key);
+            }
+        }
+    }
+}
+impl Drop for KeyReservation {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.remembered.lock().unwrap().changes.remove(&key);
+        }
+    }
+}
+
+/// Represents outputs of changes made with idempotency keys, which can be shared among all the clients.
+///
+/// Once the client retries the change with the same key within the window, it receives the original output
+/// instead of applying the change twice. Only applied changes are remembered, so that failed changes can be
+/// retried with the same key.
+#[derive(Clone)]
+pub struct SharedIdempotencyKeys {
+    remembered: Arc<Mutex<RememberedOutputs>>,
+    window: Duration,
+}
+impl SharedIdempotencyKeys {
+    pub fn new(window: Duration) -> Self {
+        SharedIdempotencyKeys {
+            remembered: Arc::default(),
+            window,
+        }
+    }
+
+    /// Reserves the given key of the given user for the given request, unless the change has already been made with
+    /// it. While the change made with the same key is still being applied, waits for its outcome.
+    pub async fn reserve(&self, username: &Option<Username>, key: &IdempotencyKey, request: &Input) -> Reservation {
+        let scoped_key = (username.clone(), key.clone());
+        let requested = fingerprint(request);
+        loop {
+            let mut released = {
+                let mut remembered = self.remembered.lock().unwrap();
+                remembered.forget_expired(Instant::now());
+                match remembered.changes.entry(scoped_key.clone()) {
+                    Entry::Vacant(entry) => {
+                        let (sender, receiver) = watch::channel(());
+                        entry.insert(RememberedChange::InFlight {
+                            fingerprint: requested,
+                            released: receiver,
+                        });
+                        return Reservation::Reserved(KeyReservation {
+                            remembered: self.remembered.clone(),
+                            key: Some(scoped_key),
+                            fingerprint: requested,
+                            window: self.window,
+                            _released: sender,
+                        });
+                    }
+                    Entry::Occupied(entry) => match entry.get() {
+                        RememberedChange::InFlight { fingerprint, .. }
+                        | RememberedChange::Applied { fingerprint, .. }
+                            if *fingerprint != requested =>
+                        {
+                            return Reservation::Mismatched;
+                        }
+                        RememberedChange::Applied { output, .. } => return Reservation::Replayed(output.clone()),
+                        RememberedChange::InFlight { released, .. } => released.clone(),
+                    },
+                }
+            };
+            // The sender is dropped once the reservation is completed or released, after the change is remembered
+            let _ = released.changed().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::time::Duration;
+
+    use crate::protocol::{test_data, IdempotencyKey, Username};
+
+    use super::{Reservation, SharedIdempotencyKeys, IDEMPOTENCY_WINDOW};
+
+    fn key() -> IdempotencyKey {
+        IdempotencyKey::new(String::from("add-foo-fighters"))
+    }
+
+    async fn remember(idempotency_keys: &SharedIdempotencyKeys, username: &Option<Username>) {
+        match idempotency_keys
+            .reserve(username, &key(), &test_data::add_table_idempotent())
+            .await
+        {
+            Reservation::Reserved(reservation) => reservation.complete(Some(test_data::table_added())),
+            _ => panic!("Key should be reserved"),
+        }
+    }
+
+    #[tokio::test]
+    async fn replay_remembered_output() {
+        let idempotency_keys = SharedIdempotencyKeys::new(IDEMPOTENCY_WINDOW);
+        let username = Some(test_data::username_admin());
+        remember(&idempotency_keys, &username).await;
+
+        // when
+        let reservation = idempotency_keys
+            .reserve(&username, &key(), &test_data::add_table_idempotent())
+            .await;
+
+        // then
+        assert!(matches!(reservation, Reservation::Replayed(output) if output == test_data::table_added()));
+    }
+
+    #[tokio::test]
+    async fn not_replay_output_of_another_user() {
+        let idempotency_keys = SharedIdempotencyKeys::new(IDEMPOTENCY_WINDOW);
+        remember(&idempotency_keys, &Some(test_data::username_admin())).await;
+
+        // when
+        let reservation = idempotency_keys
+            .reserve(&Some(Username::new(String::from("manager"))), &key(), &test_data::add_table_idempotent())
+            .await;
+
+        // then
+        assert!(matches!(reservation, Reservation::Reserved(_)));
+    }
+
+    #[tokio::test]
+    async fn forget_output_once_window_passes() {
+        let idempotency_keys = SharedIdempotencyKeys::new(Duration::ZERO);
+        let username = Some(test_data::username_admin());
+        remember(&idempotency_keys, &username).await;
+
+        // when
+        let reservation = idempotency_keys
+            .reserve(&username, &key(), &test_data::add_table_idempotent())
+            .await;
+
+        // then
+        assert!(matches!(reservation, Reservation::Reserved(_)));
+    }
+
+    #[tokio::test]
+    async fn reject_key_reused_with_another_request() {
+        let idempotency_keys = SharedIdempotencyKeys::new(IDEMPOTENCY_WINDOW);
+        let username = Some(test_data::username_admin());
+        remember(&idempotency_keys, &username).await;
+
+        // when
+        let reservation = idempotency_keys
+            .reserve(&username, &key(), &test_data::update_table())
+            .await;
+
+        // then
+        assert!(matches!(reservation, Reservation::Mismatched));
+    }
+
+    #[tokio::test]
+    async fn replay_output_to_retry_waiting_for_change_in_flight() {
+        let idempotency_keys = SharedIdempotencyKeys::new(IDEMPOTENCY_WINDOW);
+        let username = Some(test_data::username_admin());
+        let Reservation::Reserved(reservation) = idempotency_keys
+            .reserve(&username, &key(), &test_data::add_table_idempotent())
+            .await
+        else {
+            panic!("Key should be reserved");
+        };
+        let retry = tokio::spawn({
+            let idempotency_keys = idempotency_keys.clone();
+            let username = username.clone();
+            async move {
+                idempotency_keys
+                    .reserve(&username, &key(), &test_data::add_table_idempotent())
+                    .await
+            }
+        });
+        tokio::task::yield_now().await;
+
+        // when
+        reservation.complete(Some(test_data::table_added()));
+
+        // then
+        let reservation = retry.await.unwrap();
+        assert!(matches!(reservation, Reservation::Replayed(output) if output == test_data::table_added()));
+    }
+
+    #[tokio::test]
+    async fn release_key_once_change_fails() {
+        let idempotency_keys = SharedIdempotencyKeys::new(IDEMPOTENCY_WINDOW);
+        let username = Some(test_data::username_admin());
+        let Reservation::Reserved(reservation) = idempotency_keys
+            .reserve(&username, &key(), &test_data::add_table_idempotent())
+            .await
+        else {
+            panic!("Key should be reserved");
+        };
+        reservation.complete(None);
+
+        // when
+        let reservation = idempotency_keys
+            .reserve(&username, &key(), &test_data::add_table_idempotent())
+            .await;
+
+        // then
+        assert!(matches!(reservation, Reservation::Reserved(_)));
+    }
+}
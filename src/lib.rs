@@ -1,7 +1,16 @@
-mod lobby;
-mod protocol;
+mod audit;
+pub mod client;
+mod history;
+pub mod idempotency;
+pub mod lobby;
+mod ordered_tables;
+pub mod permission;
+pub mod protocol;
 mod service;
 mod session;
+mod subscription;
+pub mod table_id;
+pub mod validation;
 pub mod web_socket;
 
 #[macro_use]
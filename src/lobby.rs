@@ -1,117 +1,1071 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::{self, Display, Formatter};
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::time::Duration;
+use tokio::sync::{Mutex, OwnedMutexGuard, RwLock};
 
-use crate::protocol::{Table, TableId, TableName, TableToAdd};
+use crate::history::LobbyHistory;
+use crate::ordered_tables::{OrderedTables, TableSnapshot};
+use crate::protocol::{
+    ClientId, FieldError, LobbyName, Output, Placement, Table, TableChanges, TableId, TableName, TableOperation,
+    TableRevision, TableStatus, TableToAdd, Timestamp, Username,
+};
+use crate::table_id::SharedTableIdGenerator;
+use crate::validation::TableRules;
+
+/// Represents the reason why the lobby operation has failed.
+#[derive(Debug)]
+pub enum LobbyError {
+    /// The operation cannot be performed given the current tables.
+    Rejected(String),
+    /// The operation would result in the table with invalid fields.
+    Invalid(Vec<FieldError>),
+}
+impl LobbyError {
+    /// Returns field errors, if any, which should be reported to the client.
+    pub fn into_field_errors(self) -> Vec<FieldError> {
+        match self {
+            LobbyError::Rejected(_) => vec![],
+            LobbyError::Invalid(errors) => errors,
+        }
+    }
+}
+impl Display for LobbyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            LobbyError::Rejected(message) => write!(f, "{}", message),
+            LobbyError::Invalid(errors) => write!(f, "Invalid table fields {:?}", errors),
+        }
+    }
+}
+
+/// Represents the change to seats of the particular table.
+#[derive(Debug)]
+pub struct Seating {
+    pub id: TableId,
+    /// The table with updated participants, absent if only the waitlist has changed.
+    pub table: Option<Table>,
+    /// The waiting clients, which have been seated at the freed or added seats in their order.
+    pub promoted_client_ids: Vec<ClientId>,
+    /// The clients, whose seats have been freed by the server, as the table is no longer open.
+    pub unseated_client_ids: Vec<ClientId>,
+    /// The clients, which have been removed from the waitlist by the server, as the table is no longer open.
+    pub unlisted_client_ids: Vec<ClientId>,
+    /// The clients remaining in the waitlist in their order.
+    pub waitlist: Vec<ClientId>,
+}
+
+/// The time removed tables are kept for, so that they can be restored, unless configured otherwise.
+pub const TRASH_RETENTION: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Represents the removed table along with its neighbours, which can be restored until it expires.
+#[derive(Clone)]
+struct RemovedTable {
+    table: Table,
+    /// The table, which has been right before the removed table, absent if it has been the first one.
+    before_id: TableId,
+    /// The table, which has been right after the removed table, absent if it has been the last one.
+    after_id: TableId,
+    expires_at: Timestamp,
+}
+
+/// Represents the step, which reverts the operation applied within the batch, once any later operation fails.
+enum Undo {
+    /// Removes the added table.
+    Add { id: TableId },
+    /// Puts back the table along with its seats and waitlist as they have been before the table has changed.
+    Replace {
+        table: Table,
+        seats: Option<HashSet<ClientId>>,
+        waitlist: Option<VecDeque<ClientId>>,
+    },
+    /// Moves the table back after the table it has been after, or in front, if the id is absent.
+    Move { id: TableId, after_id: TableId },
+    /// Puts back the removed table after the table it has been after along with its seats and waitlist,
+    /// taking it out of the trash.
+    Remove {
+        table: Table,
+        after_id: TableId,
+        seats: Option<HashSet<ClientId>>,
+        waitlist: Option<VecDeque<ClientId>>,
+    },
+}
 
 /// Represents the lobby that contains ordered tables.
 struct Lobby {
-    tables: Vec<Table>,
+    name: LobbyName,
+    tables: OrderedTables,
+    rules: TableRules,
+    table_ids: SharedTableIdGenerator,
+    /// The time removed tables are kept for.
+    trash_retention: Duration,
+    /// Clients seated at each table.
+    seats: HashMap<TableId, HashSet<ClientId>>,
+    /// Clients waiting for a seat at each table in their order.
+    waitlists: HashMap<TableId, VecDeque<ClientId>>,
+    /// Tables, where each client is seated or waiting, so that seats of the client are found without scanning
+    /// all the tables.
+    client_tables: HashMap<ClientId, HashSet<TableId>>,
+    /// Removed tables, which can still be restored.
+    trash: HashMap<TableId, RemovedTable>,
 }
 impl Lobby {
-    fn prepopulated() -> Self {
+    fn empty(name: LobbyName, rules: TableRules, table_ids: SharedTableIdGenerator, trash_retention: Duration) -> Self {
         Lobby {
-            tables: vec![
-                Table {
-                    id: TableId::new(),
-                    name: TableName::new(String::from("James Bond")),
-                    participants: 7,
-                },
-                Table {
-                    id: TableId::new(),
-                    name: TableName::new(String::from("Mission Impossible")),
-                    participants: 9,
-                },
-            ],
-        }
-    }
-
-    fn add_table(&mut self, after_id: TableId, table_to_add: TableToAdd) -> Result<Table, String> {
-        let table = table_to_add.into_table(TableId::new());
-        if after_id == TableId::ABSENT {
-            self.tables.insert(0, table.clone());
-            Ok(table)
-        } else {
-            match self.tables.iter().position(|table| table.id == after_id) {
-                Some(index) => {
-                    self.tables.insert(index + 1, table.clone());
-                    Ok(table)
-                }
-                None => Err(format!("Cannot find table {:?}, after which another table should be added", after_id)),
+            name,
+            tables: OrderedTables::default(),
+            rules,
+            table_ids,
+            trash_retention,
+            seats: HashMap::new(),
+            waitlists: HashMap::new(),
+            client_tables: HashMap::new(),
+            trash: HashMap::new(),
+        }
+    }
+
+    fn prepopulated(
+        name: LobbyName,
+        rules: TableRules,
+        table_ids: SharedTableIdGenerator,
+        trash_retention: Duration,
+    ) -> Self {
+        let mut lobby = Self::empty(name, rules, table_ids, trash_retention);
+        let tables = [
+            TableToAdd {
+                name: TableName::new(String::from("James Bond")),
+                capacity: None,
+                opens_at: None,
+                closes_at: None,
+            },
+            TableToAdd {
+                name: TableName::new(String::from("Mission Impossible")),
+                capacity: None,
+                opens_at: None,
+                closes_at: None,
+            },
+        ];
+        for table_to_add in tables {
+            if let Err(e) = lobby.add_table(Placement::Last, table_to_add) {
+                error!("Failed to prepopulate lobby {:?}: {}", lobby.name, e);
             }
         }
+        lobby
+    }
+
+    /// Adds the table at the given place, returning the id of the table it has been added after along with
+    /// the added table.
+    fn add_table(&mut self, placement: Placement, table_to_add: TableToAdd) -> Result<(TableId, Table), LobbyError> {
+        let after_id = match self.tables.resolve(placement, TableId::ABSENT) {
+            Some(after_id) => after_id,
+            None => {
+                return Err(LobbyError::Rejected(format!(
+                    "Cannot find place {:?}, where another table should be added",
+                    placement
+                )))
+            }
+        };
+        // Validate before generating the id, so that rejected tables never use ids up
+        let mut table = table_to_add.into_table(TableId::ABSENT);
+        table.status = table.status_at(Timestamp::now());
+        self.validate(&table)?;
+        table.id = self.table_ids.generate().map_err(LobbyError::Rejected)?;
+        self.tables.insert_after(after_id, table.clone());
+        Ok((after_id, table))
     }
 
-    fn update_table(&mut self, table_to_update: Table) -> Result<Table, String> {
-        match self.tables.iter_mut().find(|table| table.id == table_to_update.id) {
+    /// Updates the table, keeping its participants, which are maintained by the server. Returns the updated table
+    /// along with the change to its seats, once waiting clients are seated or the table is no longer open.
+    fn update_table(&mut self, table_to_update: Table) -> Result<(Table, Option<Seating>), LobbyError> {
+        match self.tables.get(table_to_update.id) {
             Some(table) => {
+                let mut table = table.clone();
                 table.update_with(table_to_update);
-                Ok(table.clone())
+                table.status = table.status_at(Timestamp::now());
+                self.validate(&table)?;
+                let seating = self.reseat(&mut table);
+                self.tables.replace(table.clone());
+                Ok((table, seating))
+            }
+            None => Err(LobbyError::Rejected(format!(
+                "Cannot find table {:?}, which should be updated",
+                table_to_update.id
+            ))),
+        }
+    }
+
+    /// Patches the table, returning only those changes that have actually changed the table, including
+    /// its participants and status maintained by the server, along with the change to its seats, once waiting
+    /// clients are seated or the table is no longer open.
+    fn patch_table(
+        &mut self,
+        id: TableId,
+        changes: TableChanges,
+    ) -> Result<(TableChanges, Option<Seating>), LobbyError> {
+        let table = match self.tables.get(id) {
+            Some(table) => table.clone(),
+            None => return Err(LobbyError::Rejected(format!("Cannot find table {:?}, which should be patched", id))),
+        };
+        let mut patched_table = table.clone();
+        patched_table.patch_with(TableChanges {
+            participants: None,
+            status: None,
+            ..changes
+        });
+        patched_table.status = patched_table.status_at(Timestamp::now());
+        self.validate(&patched_table)?;
+        let seating = self.reseat(&mut patched_table);
+        let changes = patched_table.changes_from(&table);
+        self.tables.replace(patched_table);
+        Ok((changes, seating))
+    }
+
+    /// Seats waiting clients at free seats of the open table, or frees all the seats of the table, which is no longer
+    /// open. Returns the change to seats, if anybody has been seated or unseated.
+    fn reseat(&mut self, table: &mut Table) -> Option<Seating> {
+        if !table.status.is_open() {
+            return self.release_seats(table);
+        }
+        let waitlist = self.waitlists.get_mut(&table.id)?;
+        let mut promoted_client_ids = vec![];
+        while !table.is_full() {
+            match waitlist.pop_front() {
+                Some(client_id) => {
+                    self.seats.entry(table.id).or_default().insert(client_id);
+                    table.participants += 1;
+                    promoted_client_ids.push(client_id);
+                }
+                None => break,
+            }
+        }
+        let waitlist: Vec<ClientId> = waitlist.iter().copied().collect();
+        if waitlist.is_empty() {
+            self.waitlists.remove(&table.id);
+        }
+        if promoted_client_ids.is_empty() {
+            return None;
+        }
+        Some(Seating {
+            id: table.id,
+            table: Some(table.clone()),
+            promoted_client_ids,
+            unseated_client_ids: vec![],
+            unlisted_client_ids: vec![],
+            waitlist,
+        })
+    }
+
+    /// Frees all the seats at the table and clears its waitlist, unless the table is open, as nobody can play at
+    /// the table, which is closed or not open yet. Returns the change to seats, if anybody has been seated
+    /// or waiting.
+    fn release_seats(&mut self, table: &mut Table) -> Option<Seating> {
+        if table.status.is_open() {
+            return None;
+        }
+        let mut seating = self.take_seats(table.id)?;
+        table.participants = 0;
+        seating.table = Some(table.clone());
+        Some(seating)
+    }
+
+    /// Frees all the seats at the table and clears its waitlist, returning the change to seats without the table,
+    /// if anybody has been seated or waiting.
+    fn take_seats(&mut self, id: TableId) -> Option<Seating> {
+        let unseated_client_ids: Vec<ClientId> = self.seats.remove(&id).into_iter().flatten().collect();
+        let unlisted_client_ids: Vec<ClientId> = self.waitlists.remove(&id).into_iter().flatten().collect();
+        for client_id in unseated_client_ids.iter().chain(&unlisted_client_ids) {
+            self.reindex(*client_id, id);
+        }
+        if unseated_client_ids.is_empty() && unlisted_client_ids.is_empty() {
+            return None;
+        }
+        Some(Seating {
+            id,
+            table: None,
+            promoted_client_ids: vec![],
+            unseated_client_ids,
+            unlisted_client_ids,
+            waitlist: vec![],
+        })
+    }
+
+    /// Frees seats at all the tables and clears all the waitlists, as the lobby is deleted.
+    fn take_all_seats(&mut self) -> Vec<Seating> {
+        let ids: HashSet<TableId> = self.seats.keys().chain(self.waitlists.keys()).copied().collect();
+        ids.into_iter().filter_map(|id| self.take_seats(id)).collect()
+    }
+
+    /// Keeps the table among tables of the client, as long as the client is seated or waiting there.
+    fn reindex(&mut self, client_id: ClientId, id: TableId) {
+        let seated = self
+            .seats
+            .get(&id)
+            .is_some_and(|client_ids| client_ids.contains(&client_id));
+        let waiting = self
+            .waitlists
+            .get(&id)
+            .is_some_and(|client_ids| client_ids.contains(&client_id));
+        if seated || waiting {
+            self.client_tables.entry(client_id).or_default().insert(id);
+        } else if let Some(ids) = self.client_tables.get_mut(&client_id) {
+            ids.remove(&id);
+            if ids.is_empty() {
+                self.client_tables.remove(&client_id);
+            }
+        }
+    }
+
+    /// Moves the table to the given place, returning the id of the table it has been moved after.
+    fn move_table(&mut self, id: TableId, placement: Placement) -> Result<TableId, LobbyError> {
+        if placement == Placement::After(id) || placement == Placement::Before(id) {
+            return Err(LobbyError::Rejected(format!("Cannot move table {:?} next to itself", id)));
+        }
+        if !self.tables.contains(id) {
+            return Err(LobbyError::Rejected(format!("Cannot find table {:?}, which should be moved", id)));
+        }
+        match self.tables.resolve(placement, id) {
+            Some(after_id) => {
+                self.tables.move_after(id, after_id);
+                Ok(after_id)
+            }
+            None => Err(LobbyError::Rejected(format!(
+                "Cannot find place {:?}, where table {:?} should be moved",
+                placement, id
+            ))),
+        }
+    }
+
+    /// Removes the table into the trash, so that it can be restored at its former place until it expires. Returns
+    /// the change to seats, once anybody has been seated at or waiting for the table.
+    fn remove_table(&mut self, id: TableId) -> Result<(TableId, Option<Seating>), LobbyError> {
+        let (before_id, after_id) = match self.tables.neighbours(id) {
+            Some(neighbours) => neighbours,
+            None => return Err(LobbyError::Rejected(format!("Cannot find table {:?}, which should be removed", id))),
+        };
+        let seating = self.take_seats(id);
+        if let Some(table) = self.tables.remove(id) {
+            let removed_table = RemovedTable {
+                table,
+                before_id,
+                after_id,
+                expires_at: Timestamp::now() + self.trash_retention,
+            };
+            self.trash.insert(id, removed_table);
+        }
+        Ok((id, seating))
+    }
+
+    /// Puts the removed table back right after the table it has been after, or right before the table it has
+    /// been before, once the former has been removed too, or at the end otherwise. Returns the id of the table
+    /// it has been put after along with the restored table.
+    fn restore_table(&mut self, id: TableId) -> Result<(TableId, Table), LobbyError> {
+        let removed_table = match self.trash.get(&id) {
+            Some(removed_table) if removed_table.expires_at > Timestamp::now() => removed_table,
+            _ => {
+                return Err(LobbyError::Rejected(format!(
+                    "Cannot find removed table {:?}, which should be restored",
+                    id
+                )))
+            }
+        };
+        let placement = if removed_table.before_id == TableId::ABSENT {
+            Placement::First
+        } else if self.tables.contains(removed_table.before_id) {
+            Placement::After(removed_table.before_id)
+        } else if self.tables.contains(removed_table.after_id) {
+            Placement::Before(removed_table.after_id)
+        } else {
+            Placement::Last
+        };
+        let after_id = self
+            .tables
+            .resolve(placement, TableId::ABSENT)
+            .unwrap_or(TableId::ABSENT);
+        let mut table = removed_table.table.clone();
+        // Seats have been freed once the table has been removed
+        table.participants = 0;
+        table.status = table.status_at(Timestamp::now());
+        self.validate(&table)?;
+        self.trash.remove(&id);
+        self.tables.insert_after(after_id, table.clone());
+        Ok((after_id, table))
+    }
+
+    /// Forgets removed tables, which have expired by the given time.
+    fn purge_trash(&mut self, now: Timestamp) {
+        self.trash.retain(|_, removed_table| removed_table.expires_at > now);
+    }
+
+    fn validate(&self, table: &Table) -> Result<(), LobbyError> {
+        self.rules
+            .validate(table, self.tables.is_name_taken(table))
+            .map_err(LobbyError::Invalid)
+    }
+
+    /// Seats the client at the table, unless the table is full, and increments its participants.
+    fn join_table(&mut self, id: TableId, client_id: ClientId) -> Result<Table, LobbyError> {
+        let table = match self.tables.get_mut(id) {
+            Some(table) => table,
+            None => return Err(LobbyError::Rejected(format!("Cannot find table {:?}, which should be joined", id))),
+        };
+        if !table.status.is_open() {
+            return Err(LobbyError::Rejected(format!("Cannot join table {:?}, which is not open", id)));
+        }
+        if table.is_full() {
+            return Err(LobbyError::Rejected(format!("Cannot join table {:?}, which is full", id)));
+        }
+        if !self.seats.entry(id).or_default().insert(client_id) {
+            return Err(LobbyError::Rejected(format!(
+                "Cannot join table {:?}, client {:?} is already seated",
+                id, client_id
+            )));
+        }
+        table.participants += 1;
+        let table = table.clone();
+        self.reindex(client_id, id);
+        Ok(table)
+    }
+
+    /// Frees the seat of the client at the table and seats the first waiting client, if any.
+    fn leave_table(&mut self, id: TableId, client_id: ClientId) -> Result<Seating, LobbyError> {
+        let seated = self
+            .seats
+            .get_mut(&id)
+            .is_some_and(|client_ids| client_ids.remove(&client_id));
+        if !seated {
+            return Err(LobbyError::Rejected(format!(
+                "Cannot leave table {:?}, client {:?} is not seated",
+                id, client_id
+            )));
+        }
+        if self.seats.get(&id).is_some_and(|client_ids| client_ids.is_empty()) {
+            self.seats.remove(&id);
+        }
+        self.reindex(client_id, id);
+        let mut table = match self.tables.get(id) {
+            Some(table) => table.clone(),
+            None => return Err(LobbyError::Rejected(format!("Cannot find table {:?}, which should be left", id))),
+        };
+        table.participants = table.participants.saturating_sub(1);
+
+        let seating = self.reseat(&mut table).unwrap_or(Seating {
+            id,
+            table: Some(table.clone()),
+            promoted_client_ids: vec![],
+            unseated_client_ids: vec![],
+            unlisted_client_ids: vec![],
+            waitlist: vec![],
+        });
+        self.tables.replace(table);
+        Ok(seating)
+    }
+
+    /// Puts the client into the waitlist of the full table, returning the position in the waitlist starting from 1.
+    fn join_waitlist(&mut self, id: TableId, client_id: ClientId) -> Result<usize, LobbyError> {
+        match self.tables.get(id) {
+            Some(table) if table.is_full() => {}
+            Some(_) => return Err(LobbyError::Rejected(format!("Cannot wait for table {:?}, which is not full", id))),
+            None => {
+                return Err(LobbyError::Rejected(format!("Cannot find table {:?}, which should be waited for", id)))
+            }
+        }
+        if self
+            .seats
+            .get(&id)
+            .is_some_and(|client_ids| client_ids.contains(&client_id))
+        {
+            return Err(LobbyError::Rejected(format!(
+                "Cannot wait for table {:?}, client {:?} is already seated",
+                id, client_id
+            )));
+        }
+        let waitlist = self.waitlists.entry(id).or_default();
+        if waitlist.contains(&client_id) {
+            return Err(LobbyError::Rejected(format!(
+                "Cannot wait for table {:?}, client {:?} is already waiting",
+                id, client_id
+            )));
+        }
+        waitlist.push_back(client_id);
+        let position = waitlist.len();
+        self.reindex(client_id, id);
+        Ok(position)
+    }
+
+    /// Removes the client from the waitlist of the table.
+    fn leave_waitlist(&mut self, id: TableId, client_id: ClientId) -> Result<Seating, LobbyError> {
+        let index = self.waitlists.get(&id).and_then(|waitlist| {
+            waitlist
+                .iter()
+                .position(|waiting_client_id| *waiting_client_id == client_id)
+        });
+        match (self.waitlists.get_mut(&id), index) {
+            (Some(waitlist), Some(index)) => {
+                waitlist.remove(index);
+                let waitlist: Vec<ClientId> = waitlist.iter().copied().collect();
+                if waitlist.is_empty() {
+                    self.waitlists.remove(&id);
+                }
+                self.reindex(client_id, id);
+                Ok(Seating {
+                    id,
+                    table: None,
+                    promoted_client_ids: vec![],
+                    unseated_client_ids: vec![],
+                    unlisted_client_ids: vec![],
+                    waitlist,
+                })
+            }
+            _ => Err(LobbyError::Rejected(format!(
+                "Cannot stop waiting for table {:?}, client {:?} is not waiting",
+                id, client_id
+            ))),
+        }
+    }
+
+    /// Frees all the seats of the client and removes it from all the waitlists, visiting only tables of the client.
+    fn leave_all_tables(&mut self, client_id: ClientId) -> Vec<Seating> {
+        let ids: Vec<TableId> = self
+            .client_tables
+            .get(&client_id)
+            .into_iter()
+            .flatten()
+            .copied()
+            .collect();
+        let waitlist_seatings = ids
+            .iter()
+            .filter_map(|id| self.leave_waitlist(*id, client_id).ok())
+            .collect::<Vec<Seating>>();
+        let seat_seatings = ids
+            .iter()
+            .filter_map(|id| self.leave_table(*id, client_id).ok())
+            .collect::<Vec<Seating>>();
+        waitlist_seatings.into_iter().chain(seat_seatings).collect()
+    }
+
+    /// Updates statuses of tables, which should open or close at the given time, returning changes to seats
+    /// of the updated tables, which are freed once tables close. Only tables, which status has changed, are copied.
+    fn update_statuses(&mut self, now: Timestamp) -> Vec<Seating> {
+        let due_statuses: Vec<(TableId, TableStatus)> = self
+            .tables
+            .iter()
+            .map(|table| (table.id, table.status, table.status_at(now)))
+            .filter(|(_, status, due_status)| status != due_status)
+            .map(|(id, _, due_status)| (id, due_status))
+            .collect();
+        due_statuses
+            .into_iter()
+            .filter_map(|(id, status)| {
+                let mut table = self.tables.get(id)?.clone();
+                table.status = status;
+                let seating = self.reseat(&mut table).unwrap_or(Seating {
+                    id,
+                    table: Some(table.clone()),
+                    promoted_client_ids: vec![],
+                    unseated_client_ids: vec![],
+                    unlisted_client_ids: vec![],
+                    waitlist: vec![],
+                });
+                self.tables.replace(table);
+                Some(seating)
+            })
+            .collect()
+    }
+
+    /// Applies all the operations or none of them, returning either the outputs of all the operations along with
+    /// changes to seats they have made or the index of the first failed operation. Operations are applied
+    /// in place, while applied ones are reverted step by step once any operation fails, as failed operations
+    /// never change the lobby.
+    fn apply_batch(
+        &mut self,
+        operations: Vec<TableOperation>,
+    ) -> Result<(Vec<Output>, Vec<Seating>), (usize, LobbyError)> {
+        let mut undo_log = vec![];
+        let mut outputs = vec![];
+        let mut seatings = vec![];
+        for (index, operation) in operations.into_iter().enumerate() {
+            let undo = self.undo_of(&operation);
+            match self.apply(operation) {
+                Ok((output, seating)) => {
+                    undo_log.extend(undo.or(match &output {
+                        Output::TableAdded { table, .. } => Some(Undo::Add { id: table.id }),
+                        _ => None,
+                    }));
+                    outputs.push(output);
+                    seatings.extend(seating);
+                }
+                Err(e) => {
+                    for undo in undo_log.into_iter().rev() {
+                        self.undo(undo);
+                    }
+                    return Err((index, e));
+                }
+            }
+        }
+        Ok((outputs, seatings))
+    }
+
+    /// Returns the step, which reverts the given operation once applied, unless the operation adds the table,
+    /// which is reverted by its id known only once added, or targets the table, which does not exist.
+    fn undo_of(&self, operation: &TableOperation) -> Option<Undo> {
+        match operation {
+            TableOperation::AddTable { .. } => None,
+            TableOperation::UpdateTable {
+                table: Table { id, .. },
             }
-            None => Err(format!("Cannot find table {:?}, which should be updated", table_to_update.id)),
+            | TableOperation::PatchTable { id, .. } => Some(Undo::Replace {
+                table: self.tables.get(*id)?.clone(),
+                seats: self.seats.get(id).cloned(),
+                waitlist: self.waitlists.get(id).cloned(),
+            }),
+            TableOperation::MoveTable { id, .. } => Some(Undo::Move {
+                id: *id,
+                after_id: self.tables.neighbours(*id)?.0,
+            }),
+            TableOperation::RemoveTable { id } => Some(Undo::Remove {
+                table: self.tables.get(*id)?.clone(),
+                after_id: self.tables.neighbours(*id)?.0,
+                seats: self.seats.get(id).cloned(),
+                waitlist: self.waitlists.get(id).cloned(),
+            }),
         }
     }
 
-    fn remove_table(&mut self, id: TableId) -> Result<TableId, String> {
-        match self.tables.iter().position(|table| table.id == id) {
-            Some(index) => {
-                self.tables.remove(index);
-                Ok(id)
+    fn undo(&mut self, undo: Undo) {
+        match undo {
+            Undo::Add { id } => {
+                self.tables.remove(id);
+            }
+            Undo::Replace { table, seats, waitlist } => {
+                self.restore_seats(table.id, seats, waitlist);
+                self.tables.replace(table);
+            }
+            Undo::Move { id, after_id } => {
+                self.tables.move_after(id, after_id);
             }
-            None => Err(format!("Cannot find table {:?}, which should be removed", id)),
+            Undo::Remove {
+                table,
+                after_id,
+                seats,
+                waitlist,
+            } => {
+                self.trash.remove(&table.id);
+                self.restore_seats(table.id, seats, waitlist);
+                self.tables.insert_after(after_id, table);
+            }
+        }
+    }
+
+    fn restore_seats(&mut self, id: TableId, seats: Option<HashSet<ClientId>>, waitlist: Option<VecDeque<ClientId>>) {
+        let replaced_seats = match seats {
+            Some(seats) => self.seats.insert(id, seats),
+            None => self.seats.remove(&id),
+        };
+        let replaced_waitlist = match waitlist {
+            Some(waitlist) => self.waitlists.insert(id, waitlist),
+            None => self.waitlists.remove(&id),
+        };
+        let client_ids: Vec<ClientId> = replaced_seats
+            .into_iter()
+            .flatten()
+            .chain(replaced_waitlist.into_iter().flatten())
+            .chain(self.seats.get(&id).into_iter().flatten().copied())
+            .chain(self.waitlists.get(&id).into_iter().flatten().copied())
+            .collect();
+        for client_id in client_ids {
+            self.reindex(client_id, id);
+        }
+    }
+
+    fn apply(&mut self, operation: TableOperation) -> Result<(Output, Option<Seating>), LobbyError> {
+        let lobby = self.name.clone();
+        match operation {
+            TableOperation::AddTable { placement, table } => self
+                .add_table(placement, table)
+                .map(|(after_id, table)| (Output::TableAdded { lobby, after_id, table }, None)),
+            TableOperation::UpdateTable { table } => self
+                .update_table(table)
+                .map(|(table, seating)| (Output::TableUpdated { lobby, table }, seating)),
+            TableOperation::PatchTable { id, changes } => self
+                .patch_table(id, changes)
+                .map(|(changes, seating)| (Output::TablePatched { lobby, id, changes }, seating)),
+            TableOperation::MoveTable { id, placement } => self
+                .move_table(id, placement)
+                .map(|after_id| (Output::TableMoved { lobby, id, after_id }, None)),
+            TableOperation::RemoveTable { id } => self
+                .remove_table(id)
+                .map(|(id, seating)| (Output::TableRemoved { lobby, id }, seating)),
         }
     }
 }
 
 /// Represents the lobby that can be shared among all the clients.
+///
+/// Every change to tables made by admins is recorded in the history of the lobby along with the user, who has
+/// made it. Changes to seats and statuses of tables made by the server itself are not recorded, so that they never
+/// push changes made by admins out of the history. This way tables reconstructed as of the given time carry only
+/// fields edited by admins, along with statuses found from their schedules, while participants remain as of
+/// the latest change made by admins.
 #[derive(Clone)]
 pub struct SharedLobby {
     lobby: Arc<RwLock<Lobby>>,
+    history: Arc<RwLock<LobbyHistory>>,
+    /// Orders table snapshots of new subscriptions against changes to tables along with their broadcasts.
+    changes: Arc<Mutex<()>>,
 }
 impl SharedLobby {
-    pub fn prepopulated() -> Self {
+    pub fn prepopulated(rules: TableRules, table_ids: SharedTableIdGenerator, trash_retention: Duration) -> Self {
+        Self::new(Lobby::prepopulated(LobbyName::default(), rules, table_ids, trash_retention))
+    }
+
+    fn empty(name: LobbyName, rules: TableRules, table_ids: SharedTableIdGenerator, trash_retention: Duration) -> Self {
+        Self::new(Lobby::empty(name, rules, table_ids, trash_retention))
+    }
+
+    fn new(lobby: Lobby) -> Self {
+        let history = LobbyHistory::new(lobby.tables.clone(), Timestamp::now());
         SharedLobby {
-            lobby: Arc::from(RwLock::from(Lobby::prepopulated())),
+            lobby: Arc::from(RwLock::from(lobby)),
+            history: Arc::from(RwLock::from(history)),
+            changes: Arc::default(),
+        }
+    }
+
+    /// Locks the lobby for the change to its tables or for the snapshot of them, which should be held until
+    /// the change is broadcasted or the subscription is registered. This way every change is either included
+    /// into the snapshot or broadcasted to the new subscriber, while other lobbies remain available.
+    pub async fn lock_changes(&self) -> OwnedMutexGuard<()> {
+        self.changes.clone().lock_owned().await
+    }
+
+    /// Records changes made by the given user. Should be called while the lobby is still locked for the change,
+    /// so that changes are recorded in the order they have been made.
+    async fn record(&self, username: Option<Username>, outputs: impl IntoIterator<Item = Output>) {
+        let timestamp = Timestamp::now();
+        let mut history = self.history.write().await;
+        for output in outputs {
+            history.record(timestamp, username.clone(), output);
+        }
+    }
+
+    /// Returns the snapshot of tables, which is cheap to take and remains unchanged once tables change.
+    pub async fn read_tables(&self) -> TableSnapshot {
+        self.lobby.read().await.tables.snapshot()
+    }
+
+    /// Returns versions of the table oldest first, including the one after the table has been removed.
+    pub async fn read_table_history(&self, id: TableId) -> Result<Vec<TableRevision>, LobbyError> {
+        let revisions = self.history.read().await.table_revisions(id);
+        if revisions.is_empty() {
+            Err(LobbyError::Rejected(format!("Cannot find history of table {:?}", id)))
+        } else {
+            Ok(revisions)
+        }
+    }
+
+    /// Reconstructs tables as of the given time, copying only what is needed out of the history, so that changes
+    /// are replayed without holding the history locked.
+    pub async fn read_tables_at(&self, timestamp: Timestamp) -> Result<Vec<Table>, LobbyError> {
+        let replay = self
+            .history
+            .read()
+            .await
+            .replay_until(timestamp)
+            .map_err(LobbyError::Rejected)?;
+        Ok(replay.tables())
+    }
+
+    pub async fn add_table(
+        &self,
+        username: Option<Username>,
+        placement: Placement,
+        table_to_add: TableToAdd,
+    ) -> Result<(TableId, Table), LobbyError> {
+        let mut lobby = self.lobby.write().await;
+        let (after_id, table) = lobby.add_table(placement, table_to_add)?;
+        let output = Output::TableAdded {
+            lobby: lobby.name.clone(),
+            after_id,
+            table: table.clone(),
+        };
+        self.record(username, [output]).await;
+        Ok((after_id, table))
+    }
+
+    pub async fn update_table(
+        &self,
+        username: Option<Username>,
+        table_to_update: Table,
+    ) -> Result<(Table, Option<Seating>), LobbyError> {
+        let mut lobby = self.lobby.write().await;
+        let (table, seating) = lobby.update_table(table_to_update)?;
+        let output = Output::TableUpdated {
+            lobby: lobby.name.clone(),
+            table: table.clone(),
+        };
+        self.record(username, [output]).await;
+        Ok((table, seating))
+    }
+
+    pub async fn patch_table(
+        &self,
+        username: Option<Username>,
+        id: TableId,
+        changes: TableChanges,
+    ) -> Result<(TableChanges, Option<Seating>), LobbyError> {
+        let mut lobby = self.lobby.write().await;
+        let (changes, seating) = lobby.patch_table(id, changes)?;
+        if !changes.is_empty() {
+            let output = Output::TablePatched {
+                lobby: lobby.name.clone(),
+                id,
+                changes: changes.clone(),
+            };
+            self.record(username, [output]).await;
+        }
+        Ok((changes, seating))
+    }
+
+    pub async fn move_table(
+        &self,
+        username: Option<Username>,
+        id: TableId,
+        placement: Placement,
+    ) -> Result<TableId, LobbyError> {
+        let mut lobby = self.lobby.write().await;
+        let after_id = lobby.move_table(id, placement)?;
+        let output = Output::TableMoved {
+            lobby: lobby.name.clone(),
+            id,
+            after_id,
+        };
+        self.record(username, [output]).await;
+        Ok(after_id)
+    }
+
+    pub async fn restore_table(&self, username: Option<Username>, id: TableId) -> Result<(TableId, Table), LobbyError> {
+        let mut lobby = self.lobby.write().await;
+        let (after_id, table) = lobby.restore_table(id)?;
+        let output = Output::TableAdded {
+            lobby: lobby.name.clone(),
+            after_id,
+            table: table.clone(),
+        };
+        self.record(username, [output]).await;
+        Ok((after_id, table))
+    }
+
+    pub async fn purge_trash(&self, now: Timestamp) {
+        self.lobby.write().await.purge_trash(now)
+    }
+
+    pub async fn remove_table(
+        &self,
+        username: Option<Username>,
+        id: TableId,
+    ) -> Result<(TableId, Option<Seating>), LobbyError> {
+        let mut lobby = self.lobby.write().await;
+        let (id, seating) = lobby.remove_table(id)?;
+        let output = Output::TableRemoved {
+            lobby: lobby.name.clone(),
+            id,
+        };
+        self.record(username, [output]).await;
+        Ok((id, seating))
+    }
+
+    pub async fn apply_batch(
+        &self,
+        username: Option<Username>,
+        operations: Vec<TableOperation>,
+    ) -> Result<(Vec<Output>, Vec<Seating>), (usize, LobbyError)> {
+        let mut lobby = self.lobby.write().await;
+        let (outputs, seatings) = lobby.apply_batch(operations)?;
+        self.record(username, outputs.clone()).await;
+        Ok((outputs, seatings))
+    }
+
+    pub async fn update_statuses(&self, now: Timestamp) -> Vec<Seating> {
+        let mut lobby = self.lobby.write().await;
+        lobby.update_statuses(now)
+    }
+
+    pub async fn join_table(&self, id: TableId, client_id: ClientId) -> Result<Table, LobbyError> {
+        self.lobby.write().await.join_table(id, client_id)
+    }
+
+    pub async fn leave_table(&self, id: TableId, client_id: ClientId) -> Result<Seating, LobbyError> {
+        self.lobby.write().await.leave_table(id, client_id)
+    }
+
+    pub async fn join_waitlist(&self, id: TableId, client_id: ClientId) -> Result<usize, LobbyError> {
+        self.lobby.write().await.join_waitlist(id, client_id)
+    }
+
+    pub async fn leave_waitlist(&self, id: TableId, client_id: ClientId) -> Result<Seating, LobbyError> {
+        self.lobby.write().await.leave_waitlist(id, client_id)
+    }
+
+    pub async fn leave_all_tables(&self, client_id: ClientId) -> Vec<Seating> {
+        self.lobby.write().await.leave_all_tables(client_id)
+    }
+}
+
+/// Represents all the named lobbies that can be shared among all the clients.
+#[derive(Clone)]
+pub struct SharedLobbies {
+    lobbies: Arc<RwLock<HashMap<LobbyName, SharedLobby>>>,
+    rules: TableRules,
+    /// Generates ids of tables in all the lobbies, so that they are unique across lobbies.
+    table_ids: SharedTableIdGenerator,
+    trash_retention: Duration,
+    /// Lobbies, where each client has been seated or waiting, so that only they are visited once the client
+    /// leaves. Lobbies are forgotten only once the client leaves all of them.
+    client_lobbies: Arc<Mutex<HashMap<ClientId, HashSet<LobbyName>>>>,
+}
+impl SharedLobbies {
+    /// Creates lobbies, which contain only the prepopulated default lobby. Tables of all the lobbies should
+    /// satisfy the given rules, while removed tables are kept for the given time.
+    pub fn prepopulated(rules: TableRules, table_ids: SharedTableIdGenerator, trash_retention: Duration) -> Self {
+        let lobby = SharedLobby::prepopulated(rules.clone(), table_ids.clone(), trash_retention);
+        SharedLobbies {
+            lobbies: Arc::from(RwLock::from(HashMap::from([(LobbyName::default(), lobby)]))),
+            rules,
+            table_ids,
+            trash_retention,
+            client_lobbies: Arc::default(),
+        }
+    }
+
+    /// Locks the lobby with the given name for the change to its tables or for the snapshot of them, unless
+    /// the lobby does not exist.
+    pub async fn lock_changes(&self, name: &LobbyName) -> Option<OwnedMutexGuard<()>> {
+        let lobby = self.get(name).await.ok()?;
+        Some(lobby.lock_changes().await)
+    }
+
+    pub async fn get(&self, name: &LobbyName) -> Result<SharedLobby, LobbyError> {
+        match self.lobbies.read().await.get(name) {
+            Some(lobby) => Ok(lobby.clone()),
+            None => Err(LobbyError::Rejected(format!("Cannot find lobby {:?}", name))),
+        }
+    }
+
+    pub async fn create(&self, name: LobbyName) -> Result<LobbyName, LobbyError> {
+        let mut lobbies = self.lobbies.write().await;
+        if lobbies.contains_key(&name) {
+            Err(LobbyError::Rejected(format!("Lobby {:?} already exists", name)))
+        } else {
+            lobbies.insert(
+                name.clone(),
+                SharedLobby::empty(name.clone(), self.rules.clone(), self.table_ids.clone(), self.trash_retention),
+            );
+            Ok(name)
         }
     }
 
-    pub async fn read_tables(&self) -> Vec<Table> {
-        self.lobby.read().await.tables.clone()
+    /// Seats the client at the table of the lobby, remembering the lobby among lobbies of the client.
+    pub async fn join_table(&self, name: &LobbyName, id: TableId, client_id: ClientId) -> Result<Table, LobbyError> {
+        let table = self.get(name).await?.join_table(id, client_id).await?;
+        self.remember_client_lobby(client_id, name).await;
+        Ok(table)
+    }
+
+    /// Puts the client into the waitlist of the table of the lobby, remembering the lobby among lobbies
+    /// of the client.
+    pub async fn join_waitlist(&self, name: &LobbyName, id: TableId, client_id: ClientId) -> Result<usize, LobbyError> {
+        let position = self.get(name).await?.join_waitlist(id, client_id).await?;
+        self.remember_client_lobby(client_id, name).await;
+        Ok(position)
+    }
+
+    async fn remember_client_lobby(&self, client_id: ClientId, name: &LobbyName) {
+        self.client_lobbies
+            .lock()
+            .await
+            .entry(client_id)
+            .or_default()
+            .insert(name.clone());
+    }
+
+    /// Returns lobbies, where the client has been seated or waiting, and forgets them, as the client is about
+    /// to leave all of them. Lobbies, which have been deleted since, are skipped.
+    pub async fn take_client_lobbies(&self, client_id: ClientId) -> Vec<(LobbyName, SharedLobby)> {
+        let names = self.client_lobbies.lock().await.remove(&client_id).unwrap_or_default();
+        let lobbies = self.lobbies.read().await;
+        names
+            .into_iter()
+            .filter_map(|name| lobbies.get(&name).map(|lobby| (name, lobby.clone())))
+            .collect()
     }
 
-    pub async fn add_table(&self, after_id: TableId, table_to_add: TableToAdd) -> Result<Table, String> {
-        self.lobby.write().await.add_table(after_id, table_to_add)
+    /// Forgets removed tables, which have expired by the given time, in all the lobbies.
+    pub async fn purge_trash(&self, now: Timestamp) {
+        for (_, lobby) in self.read_all().await {
+            lobby.purge_trash(now).await;
+        }
     }
 
-    pub async fn update_table(&self, table_to_update: Table) -> Result<Table, String> {
-        self.lobby.write().await.update_table(table_to_update)
+    /// Returns all the lobbies without holding the lock, so that each of them can be locked separately.
+    pub async fn read_all(&self) -> Vec<(LobbyName, SharedLobby)> {
+        self.lobbies
+            .read()
+            .await
+            .iter()
+            .map(|(name, lobby)| (name.clone(), lobby.clone()))
+            .collect()
     }
 
-    pub async fn remove_table(&self, id: TableId) -> Result<TableId, String> {
-        self.lobby.write().await.remove_table(id)
+    /// Deletes the lobby, returning changes to seats of its tables, as all the seats are freed along with it.
+    pub async fn delete(&self, name: LobbyName) -> Result<(LobbyName, Vec<Seating>), LobbyError> {
+        if name.is_default() {
+            return Err(LobbyError::Rejected(format!("Cannot delete default lobby {:?}", name)));
+        }
+        let removed_lobby = self.lobbies.write().await.remove(&name);
+        match removed_lobby {
+            Some(lobby) => {
+                let seatings = lobby.lobby.write().await.take_all_seats();
+                Ok((name, seatings))
+            }
+            None => Err(LobbyError::Rejected(format!("Cannot find lobby {:?}, which should be deleted", name))),
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
 
-    use crate::protocol::{test_data, Table, TableId, TableName};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::protocol::{
+        test_data, ClientId, LobbyName, Output, Placement, Table, TableChanges, TableId, TableName, TableOperation,
+        TableStatus, TableToAdd, Timestamp,
+    };
+    use crate::table_id::{SequentialIds, SharedTableIdGenerator};
+    use crate::validation::TableRules;
 
-    use super::SharedLobby;
+    use super::{SharedLobbies, SharedLobby, TRASH_RETENTION};
+
+    fn table_ids() -> SharedTableIdGenerator {
+        Arc::new(SequentialIds::new())
+    }
 
     #[tokio::test]
     async fn add_table_in_front() {
-        let shared_lobby = SharedLobby::prepopulated();
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
         let len_before = shared_lobby.len().await;
 
         // when
         let result = shared_lobby
-            .add_table(TableId::ABSENT, test_data::table_to_add_foo_fighters())
+            .add_table(None, Placement::First, test_data::table_to_add_foo_fighters())
             .await;
 
         // then
-        let added_table = result.expect("Table should be added");
+        let (after_id, added_table) = result.expect("Table should be added");
         let first_table = shared_lobby.read_table(0).await;
         assert_eq!(added_table, first_table);
+        assert_eq!(after_id, TableId::ABSENT);
 
         let len_after = shared_lobby.len().await;
         assert_eq!(len_after, len_before + 1, "Number of tables should increase by one");
@@ -119,134 +1073,1260 @@ mod tests {
 
     #[tokio::test]
     async fn add_table_after_another_table() {
-        let shared_lobby = SharedLobby::prepopulated();
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
         let len_before = shared_lobby.len().await;
         let first_table = shared_lobby.read_table(0).await;
 
         // when
         let result = shared_lobby
-            .add_table(first_table.id, test_data::table_to_add_foo_fighters())
+            .add_table(None, Placement::After(first_table.id), test_data::table_to_add_foo_fighters())
             .await;
 
         // then
-        let added_table = result.expect("Table should be added");
+        let (after_id, added_table) = result.expect("Table should be added");
         let second_table = shared_lobby.read_table(1).await;
         assert_eq!(added_table, second_table);
+        assert_eq!(after_id, first_table.id);
 
         let len_after = shared_lobby.len().await;
         assert_eq!(len_after, len_before + 1, "Number of tables should increase by one");
     }
 
     #[tokio::test]
-    async fn not_add_table_when_after_id_does_not_exist() {
-        let shared_lobby = SharedLobby::prepopulated();
-        let len_before = shared_lobby.len().await;
+    async fn add_table_at_end() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let last_table = shared_lobby.read_table(1).await;
 
         // when
         let result = shared_lobby
-            .add_table(test_data::TABLE_ID_INVALID, test_data::table_to_add_foo_fighters())
+            .add_table(None, Placement::Last, test_data::table_to_add_foo_fighters())
             .await;
 
         // then
-        assert!(result.is_err(), "Table should not be added");
-
-        let len_after = shared_lobby.len().await;
-        assert_eq!(len_after, len_before, "Number of tables should remain the same");
+        let (after_id, added_table) = result.expect("Table should be added");
+        assert_eq!(added_table, shared_lobby.read_table(2).await);
+        assert_eq!(after_id, last_table.id);
     }
 
     #[tokio::test]
-    async fn update_table() {
-        let shared_lobby = SharedLobby::prepopulated();
-        let len_before = shared_lobby.len().await;
-
-        let index = 0;
-        let prepopulated_table = shared_lobby.read_table(index).await;
-        let table_to_update = Table {
-            name: TableName::new(String::from("Updated")),
-            ..prepopulated_table
-        };
+    async fn add_table_before_another_table() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let first_table = shared_lobby.read_table(0).await;
+        let second_table = shared_lobby.read_table(1).await;
 
         // when
-        let result = shared_lobby.update_table(table_to_update.clone()).await;
+        let result = shared_lobby
+            .add_table(None, Placement::Before(second_table.id), test_data::table_to_add_foo_fighters())
+            .await;
 
         // then
-        let updated_table = result.expect("Table should be updated");
-        assert_eq!(updated_table, table_to_update);
+        let (after_id, added_table) = result.expect("Table should be added");
+        assert_eq!(added_table, shared_lobby.read_table(1).await);
+        assert_eq!(after_id, first_table.id);
+    }
 
-        let len_after = shared_lobby.len().await;
-        assert_eq!(len_after, len_before, "Number of tables should remain the same");
+    #[tokio::test]
+    async fn add_table_at_index() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let first_table = shared_lobby.read_table(0).await;
 
-        let updated_table = shared_lobby.read_table(index).await;
-        assert_eq!(updated_table, table_to_update);
+        // when
+        let result = shared_lobby
+            .add_table(None, Placement::Index(1), test_data::table_to_add_foo_fighters())
+            .await;
+
+        // then
+        let (after_id, added_table) = result.expect("Table should be added");
+        assert_eq!(added_table, shared_lobby.read_table(1).await);
+        assert_eq!(after_id, first_table.id);
     }
 
     #[tokio::test]
-    async fn not_update_table_when_table_id_does_not_exist() {
-        let shared_lobby = SharedLobby::prepopulated();
+    async fn not_add_table_when_index_is_out_of_range() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
         let len_before = shared_lobby.len().await;
 
-        let table_to_update = Table {
-            id: test_data::TABLE_ID_INVALID,
-            ..test_data::table_james_bond()
-        };
-
         // when
-        let result = shared_lobby.update_table(table_to_update).await;
+        let result = shared_lobby
+            .add_table(None, Placement::Index(len_before + 1), test_data::table_to_add_foo_fighters())
+            .await;
 
         // then
-        assert!(result.is_err(), "Table should not be updated");
-
-        let len_after = shared_lobby.len().await;
-        assert_eq!(len_after, len_before, "Number of tables should remain the same");
+        assert!(result.is_err(), "Table should not be added");
+        assert_eq!(shared_lobby.len().await, len_before, "Number of tables should remain the same");
     }
 
     #[tokio::test]
-    async fn remove_table() {
-        let shared_lobby = SharedLobby::prepopulated();
+    async fn not_add_table_when_after_id_does_not_exist() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
         let len_before = shared_lobby.len().await;
 
-        let index = 0;
-        let prepopulated_table = shared_lobby.read_table(index).await;
-
         // when
-        let result = shared_lobby.remove_table(prepopulated_table.id).await;
+        let result = shared_lobby
+            .add_table(None, Placement::After(test_data::TABLE_ID_INVALID), test_data::table_to_add_foo_fighters())
+            .await;
 
         // then
-        let removed_table_id = result.expect("Table should be removed");
-        assert_eq!(removed_table_id, prepopulated_table.id);
+        assert!(result.is_err(), "Table should not be added");
 
         let len_after = shared_lobby.len().await;
-        assert_eq!(len_after, len_before - 1, "Number of tables should decrease by one");
+        assert_eq!(len_after, len_before, "Number of tables should remain the same");
     }
 
     #[tokio::test]
-    async fn not_remove_table_when_table_id_does_not_exist() {
-        let shared_lobby = SharedLobby::prepopulated();
+    async fn not_add_table_when_table_is_invalid() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
         let len_before = shared_lobby.len().await;
+        let table_to_add = TableToAdd {
+            name: shared_lobby.read_table(0).await.name,
+            capacity: None,
+            opens_at: None,
+            closes_at: None,
+        };
 
         // when
-        let result = shared_lobby.remove_table(test_data::TABLE_ID_INVALID).await;
+        let result = shared_lobby.add_table(None, Placement::First, table_to_add).await;
 
         // then
-        assert!(result.is_err(), "Table should not be removed");
+        let error = result.expect_err("Table should not be added");
+        assert_eq!(error.into_field_errors().len(), 1, "Duplicate name should be reported");
 
         let len_after = shared_lobby.len().await;
         assert_eq!(len_after, len_before, "Number of tables should remain the same");
     }
 
+    #[tokio::test]
+    async fn not_use_id_up_when_table_is_invalid() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let table_to_add = TableToAdd {
+            name: shared_lobby.read_table(0).await.name,
+            ..test_data::table_to_add_foo_fighters()
+        };
+        let _ = shared_lobby.add_table(None, Placement::Last, table_to_add).await;
+
+        // when
+        let result = shared_lobby
+            .add_table(None, Placement::Last, test_data::table_to_add_foo_fighters())
+            .await;
+
+        // then
+        let (_, added_table) = result.expect("Table should be added");
+        assert_eq!(added_table.id, TableId::new(3), "Rejected table should not use id up");
+    }
+
+    #[tokio::test]
+    async fn update_table() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let len_before = shared_lobby.len().await;
+
+        let index = 0;
+        let prepopulated_table = shared_lobby.read_table(index).await;
+        let table_to_update = Table {
+            name: TableName::new(String::from("Updated")),
+            ..prepopulated_table
+        };
+
+        // when
+        let result = shared_lobby.update_table(None, table_to_update.clone()).await;
+
+        // then
+        let (updated_table, _) = result.expect("Table should be updated");
+        assert_eq!(updated_table, table_to_update);
+
+        let len_after = shared_lobby.len().await;
+        assert_eq!(len_after, len_before, "Number of tables should remain the same");
+
+        let updated_table = shared_lobby.read_table(index).await;
+        assert_eq!(updated_table, table_to_update);
+    }
+
+    #[tokio::test]
+    async fn keep_participants_when_updating_table() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let prepopulated_table = shared_lobby.read_table(0).await;
+        shared_lobby
+            .join_table(prepopulated_table.id, ClientId::new())
+            .await
+            .expect("Table should be joined");
+        let table_to_update = Table {
+            participants: 42,
+            ..prepopulated_table
+        };
+
+        // when
+        let result = shared_lobby.update_table(None, table_to_update).await;
+
+        // then
+        let (updated_table, _) = result.expect("Table should be updated");
+        assert_eq!(updated_table.participants, 1, "Participants should count seated clients");
+    }
+
+    #[tokio::test]
+    async fn not_update_table_when_table_id_does_not_exist() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let len_before = shared_lobby.len().await;
+
+        let table_to_update = Table {
+            id: test_data::TABLE_ID_INVALID,
+            ..test_data::table_james_bond()
+        };
+
+        // when
+        let result = shared_lobby.update_table(None, table_to_update).await;
+
+        // then
+        assert!(result.is_err(), "Table should not be updated");
+
+        let len_after = shared_lobby.len().await;
+        assert_eq!(len_after, len_before, "Number of tables should remain the same");
+    }
+
+    #[tokio::test]
+    async fn clear_optional_fields_with_patch() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let prepopulated_table = shared_lobby.read_table(0).await;
+        let changes = TableChanges {
+            capacity: Some(Some(10)),
+            opens_at: Some(Some(test_data::timestamp(100))),
+            closes_at: Some(Some(Timestamp::now() + Duration::from_secs(60))),
+            ..TableChanges::default()
+        };
+        shared_lobby
+            .patch_table(None, prepopulated_table.id, changes)
+            .await
+            .expect("Table should be patched");
+        let changes = TableChanges {
+            capacity: Some(None),
+            opens_at: Some(None),
+            closes_at: Some(None),
+            ..TableChanges::default()
+        };
+
+        // when
+        let result = shared_lobby
+            .patch_table(None, prepopulated_table.id, changes.clone())
+            .await;
+
+        // then
+        let (patched_changes, _) = result.expect("Table should be patched");
+        assert_eq!(patched_changes, changes);
+        assert_eq!(shared_lobby.read_table(0).await, prepopulated_table);
+    }
+
+    #[tokio::test]
+    async fn patch_table() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let len_before = shared_lobby.len().await;
+
+        let index = 0;
+        let prepopulated_table = shared_lobby.read_table(index).await;
+        let changes = test_data::table_changes_capacity();
+
+        // when
+        let result = shared_lobby
+            .patch_table(None, prepopulated_table.id, changes.clone())
+            .await;
+
+        // then
+        let (patched_changes, _) = result.expect("Table should be patched");
+        assert_eq!(patched_changes, changes);
+
+        let len_after = shared_lobby.len().await;
+        assert_eq!(len_after, len_before, "Number of tables should remain the same");
+
+        let patched_table = shared_lobby.read_table(index).await;
+        assert_eq!(patched_table.name, prepopulated_table.name, "Name should remain the same");
+        assert_eq!(Some(patched_table.capacity), changes.capacity);
+    }
+
+    #[tokio::test]
+    async fn patch_table_returning_only_actual_changes() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let prepopulated_table = shared_lobby.read_table(0).await;
+        let changes = TableChanges {
+            name: Some(prepopulated_table.name.clone()),
+            ..test_data::table_changes_capacity()
+        };
+
+        // when
+        let result = shared_lobby.patch_table(None, prepopulated_table.id, changes).await;
+
+        // then
+        let (patched_changes, _) = result.expect("Table should be patched");
+        assert_eq!(patched_changes, test_data::table_changes_capacity());
+    }
+
+    #[tokio::test]
+    async fn free_seats_when_patched_table_closes() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let prepopulated_table = shared_lobby.read_table(0).await;
+        let seated_client_id = ClientId::new();
+        let waiting_client_id = ClientId::new();
+        let changes = TableChanges {
+            capacity: Some(Some(1)),
+            ..TableChanges::default()
+        };
+        shared_lobby
+            .patch_table(None, prepopulated_table.id, changes)
+            .await
+            .expect("Table should be patched");
+        shared_lobby
+            .join_table(prepopulated_table.id, seated_client_id)
+            .await
+            .expect("Table should be joined");
+        shared_lobby
+            .join_waitlist(prepopulated_table.id, waiting_client_id)
+            .await
+            .expect("Waitlist should be joined");
+        let changes = TableChanges {
+            closes_at: Some(Some(Timestamp::now())),
+            ..TableChanges::default()
+        };
+
+        // when
+        let result = shared_lobby.patch_table(None, prepopulated_table.id, changes).await;
+
+        // then
+        let (patched_changes, seating) = result.expect("Table should be patched");
+        assert_eq!(patched_changes.status, Some(TableStatus::Closed), "Status should be recomputed");
+        assert_eq!(patched_changes.participants, Some(0), "Seats should be freed");
+        let seating = seating.expect("Seats should change");
+        assert_eq!(seating.unseated_client_ids, vec![seated_client_id]);
+        assert_eq!(seating.unlisted_client_ids, vec![waiting_client_id]);
+
+        let result = shared_lobby.leave_table(prepopulated_table.id, seated_client_id).await;
+        assert!(result.is_err(), "Client should no longer be seated");
+    }
+
+    #[tokio::test]
+    async fn not_patch_table_when_table_id_does_not_exist() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+
+        // when
+        let result = shared_lobby
+            .patch_table(None, test_data::TABLE_ID_INVALID, TableChanges::default())
+            .await;
+
+        // then
+        assert!(result.is_err(), "Table should not be patched");
+    }
+
+    #[tokio::test]
+    async fn move_table_in_front() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let len_before = shared_lobby.len().await;
+        let second_table = shared_lobby.read_table(1).await;
+
+        // when
+        let result = shared_lobby.move_table(None, second_table.id, Placement::First).await;
+
+        // then
+        let after_id = result.expect("Table should be moved");
+        assert_eq!(after_id, TableId::ABSENT);
+
+        let first_table = shared_lobby.read_table(0).await;
+        assert_eq!(first_table, second_table);
+
+        let len_after = shared_lobby.len().await;
+        assert_eq!(len_after, len_before, "Number of tables should remain the same");
+    }
+
+    #[tokio::test]
+    async fn move_table_after_another_table() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let first_table = shared_lobby.read_table(0).await;
+        let second_table = shared_lobby.read_table(1).await;
+
+        // when
+        let result = shared_lobby
+            .move_table(None, first_table.id, Placement::After(second_table.id))
+            .await;
+
+        // then
+        result.expect("Table should be moved");
+        assert_eq!(shared_lobby.read_table(0).await, second_table);
+        assert_eq!(shared_lobby.read_table(1).await, first_table);
+    }
+
+    #[tokio::test]
+    async fn move_table_at_end() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let first_table = shared_lobby.read_table(0).await;
+        let second_table = shared_lobby.read_table(1).await;
+
+        // when
+        let result = shared_lobby.move_table(None, first_table.id, Placement::Last).await;
+
+        // then
+        let after_id = result.expect("Table should be moved");
+        assert_eq!(after_id, second_table.id, "Moved table should be skipped");
+        assert_eq!(shared_lobby.read_table(1).await, first_table);
+    }
+
+    #[tokio::test]
+    async fn not_move_table_before_itself() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let first_table = shared_lobby.read_table(0).await;
+
+        // when
+        let result = shared_lobby
+            .move_table(None, first_table.id, Placement::Before(first_table.id))
+            .await;
+
+        // then
+        assert!(result.is_err(), "Table should not be moved");
+    }
+
+    #[tokio::test]
+    async fn not_move_table_when_after_id_does_not_exist() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let first_table = shared_lobby.read_table(0).await;
+
+        // when
+        let result = shared_lobby
+            .move_table(None, first_table.id, Placement::After(test_data::TABLE_ID_INVALID))
+            .await;
+
+        // then
+        assert!(result.is_err(), "Table should not be moved");
+        assert_eq!(shared_lobby.read_table(0).await, first_table);
+    }
+
+    #[tokio::test]
+    async fn not_move_table_when_table_id_does_not_exist() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+
+        // when
+        let result = shared_lobby
+            .move_table(None, test_data::TABLE_ID_INVALID, Placement::First)
+            .await;
+
+        // then
+        assert!(result.is_err(), "Table should not be moved");
+    }
+
+    #[tokio::test]
+    async fn remove_table() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let len_before = shared_lobby.len().await;
+
+        let index = 0;
+        let prepopulated_table = shared_lobby.read_table(index).await;
+
+        // when
+        let result = shared_lobby.remove_table(None, prepopulated_table.id).await;
+
+        // then
+        let (removed_table_id, seating) = result.expect("Table should be removed");
+        assert_eq!(removed_table_id, prepopulated_table.id);
+        assert!(seating.is_none(), "Nobody should be unseated");
+
+        let len_after = shared_lobby.len().await;
+        assert_eq!(len_after, len_before - 1, "Number of tables should decrease by one");
+    }
+
+    #[tokio::test]
+    async fn not_remove_table_when_table_id_does_not_exist() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let len_before = shared_lobby.len().await;
+
+        // when
+        let result = shared_lobby.remove_table(None, test_data::TABLE_ID_INVALID).await;
+
+        // then
+        assert!(result.is_err(), "Table should not be removed");
+
+        let len_after = shared_lobby.len().await;
+        assert_eq!(len_after, len_before, "Number of tables should remain the same");
+    }
+
+    #[tokio::test]
+    async fn restore_table_at_former_place() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let first_table = shared_lobby.read_table(0).await;
+        let (_, added_table) = shared_lobby
+            .add_table(None, Placement::After(first_table.id), test_data::table_to_add_foo_fighters())
+            .await
+            .expect("Table should be added");
+        shared_lobby
+            .remove_table(None, added_table.id)
+            .await
+            .expect("Table should be removed");
+
+        // when
+        let result = shared_lobby.restore_table(None, added_table.id).await;
+
+        // then
+        let (after_id, restored_table) = result.expect("Table should be restored");
+        assert_eq!(after_id, first_table.id);
+        assert_eq!(restored_table, added_table);
+        assert_eq!(shared_lobby.read_table(1).await, added_table);
+    }
+
+    #[tokio::test]
+    async fn restore_table_without_participants() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let first_table = shared_lobby.read_table(0).await;
+        shared_lobby
+            .join_table(first_table.id, ClientId::new())
+            .await
+            .expect("Table should be joined");
+        shared_lobby
+            .remove_table(None, first_table.id)
+            .await
+            .expect("Table should be removed");
+
+        // when
+        let result = shared_lobby.restore_table(None, first_table.id).await;
+
+        // then
+        let (_, restored_table) = result.expect("Table should be restored");
+        assert_eq!(restored_table.participants, 0);
+        assert_eq!(shared_lobby.read_table(0).await.participants, 0);
+    }
+
+    #[tokio::test]
+    async fn unseat_clients_of_removed_table() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let first_table = shared_lobby.read_table(0).await;
+        let client_id = ClientId::new();
+        shared_lobby
+            .join_table(first_table.id, client_id)
+            .await
+            .expect("Table should be joined");
+
+        // when
+        let result = shared_lobby.remove_table(None, first_table.id).await;
+
+        // then
+        let (_, seating) = result.expect("Table should be removed");
+        let seating = seating.expect("Client should be unseated");
+        assert_eq!(seating.unseated_client_ids, vec![client_id]);
+        assert!(seating.table.is_none(), "Removed table should not be broadcasted as updated");
+        assert!(shared_lobby.leave_all_tables(client_id).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn not_restore_table_once_trash_retention_passes() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), Duration::ZERO);
+        let first_table = shared_lobby.read_table(0).await;
+        shared_lobby
+            .remove_table(None, first_table.id)
+            .await
+            .expect("Table should be removed");
+
+        // when
+        let result = shared_lobby.restore_table(None, first_table.id).await;
+
+        // then
+        assert!(result.is_err(), "Table should not be restored");
+    }
+
+    #[tokio::test]
+    async fn restore_table_before_next_table_once_previous_table_is_removed() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let first_table = shared_lobby.read_table(0).await;
+        let second_table = shared_lobby.read_table(1).await;
+        shared_lobby
+            .add_table(None, Placement::Last, test_data::table_to_add_foo_fighters())
+            .await
+            .expect("Table should be added");
+        for id in [second_table.id, first_table.id] {
+            shared_lobby
+                .remove_table(None, id)
+                .await
+                .expect("Table should be removed");
+        }
+
+        // when
+        let result = shared_lobby.restore_table(None, second_table.id).await;
+
+        // then
+        let (after_id, _) = result.expect("Table should be restored");
+        assert_eq!(after_id, TableId::ABSENT);
+        assert_eq!(shared_lobby.read_table(0).await, second_table);
+    }
+
+    #[tokio::test]
+    async fn not_restore_table_once_it_expires() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let len_before = shared_lobby.len().await;
+        let first_table = shared_lobby.read_table(0).await;
+        shared_lobby
+            .remove_table(None, first_table.id)
+            .await
+            .expect("Table should be removed");
+        shared_lobby.purge_trash(Timestamp::now() + TRASH_RETENTION).await;
+
+        // when
+        let result = shared_lobby.restore_table(None, first_table.id).await;
+
+        // then
+        assert!(result.is_err(), "Table should not be restored");
+        assert_eq!(shared_lobby.len().await, len_before - 1, "Number of tables should remain the same");
+    }
+
+    #[tokio::test]
+    async fn not_restore_table_when_its_name_is_taken() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let first_table = shared_lobby.read_table(0).await;
+        shared_lobby
+            .remove_table(None, first_table.id)
+            .await
+            .expect("Table should be removed");
+        let table_to_add = TableToAdd {
+            name: first_table.name.clone(),
+            ..test_data::table_to_add_foo_fighters()
+        };
+        shared_lobby
+            .add_table(None, Placement::Last, table_to_add)
+            .await
+            .expect("Table should be added");
+
+        // when
+        let result = shared_lobby.restore_table(None, first_table.id).await;
+
+        // then
+        assert!(result.is_err(), "Table should not be restored");
+    }
+
+    #[tokio::test]
+    async fn not_restore_table_twice() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let first_table = shared_lobby.read_table(0).await;
+        shared_lobby
+            .remove_table(None, first_table.id)
+            .await
+            .expect("Table should be removed");
+        shared_lobby
+            .restore_table(None, first_table.id)
+            .await
+            .expect("Table should be restored");
+
+        // when
+        let result = shared_lobby.restore_table(None, first_table.id).await;
+
+        // then
+        assert!(result.is_err(), "Table should not be restored again");
+    }
+
+    #[tokio::test]
+    async fn apply_batch() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let len_before = shared_lobby.len().await;
+        let first_table = shared_lobby.read_table(0).await;
+        let operations = vec![
+            TableOperation::AddTable {
+                placement: Placement::First,
+                table: test_data::table_to_add_foo_fighters(),
+            },
+            TableOperation::RemoveTable { id: first_table.id },
+        ];
+
+        // when
+        let result = shared_lobby.apply_batch(None, operations).await;
+
+        // then
+        let (outputs, _) = result.expect("Batch should be applied");
+        assert_eq!(outputs.len(), 2);
+        let expected_output = Output::TableRemoved {
+            lobby: LobbyName::default(),
+            id: first_table.id,
+        };
+        assert_eq!(outputs[1], expected_output);
+
+        let len_after = shared_lobby.len().await;
+        assert_eq!(len_after, len_before, "Number of tables should remain the same");
+    }
+
+    #[tokio::test]
+    async fn not_apply_batch_when_any_operation_fails() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let len_before = shared_lobby.len().await;
+        let operations = vec![
+            TableOperation::AddTable {
+                placement: Placement::First,
+                table: test_data::table_to_add_foo_fighters(),
+            },
+            TableOperation::RemoveTable {
+                id: test_data::TABLE_ID_INVALID,
+            },
+        ];
+
+        // when
+        let result = shared_lobby.apply_batch(None, operations).await;
+
+        // then
+        let (index, _) = result.expect_err("Batch should not be applied");
+        assert_eq!(index, 1);
+
+        let len_after = shared_lobby.len().await;
+        assert_eq!(len_after, len_before, "Number of tables should remain the same");
+    }
+
+    #[tokio::test]
+    async fn revert_applied_operations_when_any_operation_fails() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let first_table = shared_lobby.read_table(0).await;
+        let second_table = shared_lobby.read_table(1).await;
+        let seated_client_id = ClientId::new();
+        shared_lobby
+            .join_table(second_table.id, seated_client_id)
+            .await
+            .expect("Table should be joined");
+        let tables_before: Vec<Table> = shared_lobby
+            .read_tables()
+            .await
+            .iter()
+            .map(|table| Table::clone(table))
+            .collect();
+        let operations = vec![
+            TableOperation::AddTable {
+                placement: Placement::First,
+                table: test_data::table_to_add_foo_fighters(),
+            },
+            TableOperation::PatchTable {
+                id: first_table.id,
+                changes: test_data::table_changes_capacity(),
+            },
+            TableOperation::MoveTable {
+                id: first_table.id,
+                placement: Placement::Last,
+            },
+            TableOperation::RemoveTable { id: second_table.id },
+            TableOperation::RemoveTable {
+                id: test_data::TABLE_ID_INVALID,
+            },
+        ];
+
+        // when
+        let result = shared_lobby.apply_batch(None, operations).await;
+
+        // then
+        let (index, _) = result.expect_err("Batch should not be applied");
+        assert_eq!(index, 4);
+
+        let tables_after: Vec<Table> = shared_lobby
+            .read_tables()
+            .await
+            .iter()
+            .map(|table| Table::clone(table))
+            .collect();
+        assert_eq!(tables_after, tables_before, "Tables should remain the same");
+        let restore_result = shared_lobby.restore_table(None, second_table.id).await;
+        assert!(restore_result.is_err(), "Table should not be in the trash");
+        shared_lobby
+            .leave_table(second_table.id, seated_client_id)
+            .await
+            .expect("Client should remain seated");
+    }
+
+    #[tokio::test]
+    async fn join_and_leave_table() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let prepopulated_table = shared_lobby.read_table(0).await;
+        let client_id = ClientId::new();
+
+        // when
+        let joined_table = shared_lobby
+            .join_table(prepopulated_table.id, client_id)
+            .await
+            .expect("Table should be joined");
+        let left_table = shared_lobby
+            .leave_table(prepopulated_table.id, client_id)
+            .await
+            .expect("Table should be left")
+            .table
+            .expect("Table should be updated");
+
+        // then
+        assert_eq!(joined_table.participants, prepopulated_table.participants + 1);
+        assert_eq!(left_table.participants, prepopulated_table.participants);
+    }
+
+    #[tokio::test]
+    async fn not_join_table_twice() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let prepopulated_table = shared_lobby.read_table(0).await;
+        let client_id = ClientId::new();
+        shared_lobby
+            .join_table(prepopulated_table.id, client_id)
+            .await
+            .expect("Table should be joined");
+
+        // when
+        let result = shared_lobby.join_table(prepopulated_table.id, client_id).await;
+
+        // then
+        assert!(result.is_err(), "Table should not be joined");
+        let table = shared_lobby.read_table(0).await;
+        assert_eq!(table.participants, prepopulated_table.participants + 1);
+    }
+
+    #[tokio::test]
+    async fn not_join_table_when_table_is_full() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let prepopulated_table = shared_lobby.read_table(0).await;
+        let changes = TableChanges {
+            capacity: Some(Some(prepopulated_table.participants)),
+            ..TableChanges::default()
+        };
+        shared_lobby
+            .patch_table(None, prepopulated_table.id, changes)
+            .await
+            .expect("Table should be patched");
+
+        // when
+        let result = shared_lobby.join_table(prepopulated_table.id, ClientId::new()).await;
+
+        // then
+        assert!(result.is_err(), "Table should not be joined");
+    }
+
+    #[tokio::test]
+    async fn not_leave_table_when_not_seated() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let prepopulated_table = shared_lobby.read_table(0).await;
+
+        // when
+        let result = shared_lobby.leave_table(prepopulated_table.id, ClientId::new()).await;
+
+        // then
+        assert!(result.is_err(), "Table should not be left");
+    }
+
+    #[tokio::test]
+    async fn promote_waiting_client_when_seat_is_freed() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let prepopulated_table = shared_lobby.read_table(0).await;
+        let seated_client_id = ClientId::new();
+        let waiting_client_id_1 = ClientId::new();
+        let waiting_client_id_2 = ClientId::new();
+        let changes = TableChanges {
+            capacity: Some(Some(prepopulated_table.participants + 1)),
+            ..TableChanges::default()
+        };
+        shared_lobby
+            .patch_table(None, prepopulated_table.id, changes)
+            .await
+            .expect("Table should be patched");
+        shared_lobby
+            .join_table(prepopulated_table.id, seated_client_id)
+            .await
+            .expect("Table should be joined");
+        for (waiting_client_id, expected_position) in [(waiting_client_id_1, 1), (waiting_client_id_2, 2)] {
+            let position = shared_lobby
+                .join_waitlist(prepopulated_table.id, waiting_client_id)
+                .await
+                .expect("Waitlist should be joined");
+            assert_eq!(position, expected_position);
+        }
+
+        // when
+        let seating = shared_lobby
+            .leave_table(prepopulated_table.id, seated_client_id)
+            .await
+            .expect("Table should be left");
+
+        // then
+        assert_eq!(seating.promoted_client_ids, vec![waiting_client_id_1]);
+        assert_eq!(seating.waitlist, vec![waiting_client_id_2]);
+        let table = seating.table.expect("Table should be updated");
+        assert_eq!(table.participants, prepopulated_table.participants + 1);
+    }
+
+    #[tokio::test]
+    async fn promote_waiting_clients_when_capacity_is_raised() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let prepopulated_table = shared_lobby.read_table(0).await;
+        let seated_client_id = ClientId::new();
+        let waiting_client_id_1 = ClientId::new();
+        let waiting_client_id_2 = ClientId::new();
+        let waiting_client_id_3 = ClientId::new();
+        let changes = TableChanges {
+            capacity: Some(Some(1)),
+            ..TableChanges::default()
+        };
+        shared_lobby
+            .patch_table(None, prepopulated_table.id, changes)
+            .await
+            .expect("Table should be patched");
+        shared_lobby
+            .join_table(prepopulated_table.id, seated_client_id)
+            .await
+            .expect("Table should be joined");
+        for waiting_client_id in [waiting_client_id_1, waiting_client_id_2, waiting_client_id_3] {
+            shared_lobby
+                .join_waitlist(prepopulated_table.id, waiting_client_id)
+                .await
+                .expect("Waitlist should be joined");
+        }
+        let changes = TableChanges {
+            capacity: Some(Some(3)),
+            ..TableChanges::default()
+        };
+
+        // when
+        let (changes, seating) = shared_lobby
+            .patch_table(None, prepopulated_table.id, changes)
+            .await
+            .expect("Table should be patched");
+
+        // then
+        assert_eq!(changes.participants, Some(3));
+        let seating = seating.expect("Waiting clients should be seated");
+        assert_eq!(seating.promoted_client_ids, vec![waiting_client_id_1, waiting_client_id_2]);
+        assert_eq!(seating.waitlist, vec![waiting_client_id_3]);
+    }
+
+    #[tokio::test]
+    async fn not_leave_waitlist_when_not_waiting() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let prepopulated_table = shared_lobby.read_table(0).await;
+
+        // when
+        let result = shared_lobby
+            .leave_waitlist(prepopulated_table.id, ClientId::new())
+            .await;
+
+        // then
+        assert!(result.is_err(), "Waitlist should not be left");
+        assert!(shared_lobby.lobby.read().await.waitlists.is_empty());
+    }
+
+    #[tokio::test]
+    async fn not_join_waitlist_when_table_is_not_full() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let prepopulated_table = shared_lobby.read_table(0).await;
+
+        // when
+        let result = shared_lobby.join_waitlist(prepopulated_table.id, ClientId::new()).await;
+
+        // then
+        assert!(result.is_err(), "Waitlist should not be joined");
+    }
+
+    #[tokio::test]
+    async fn leave_all_tables_of_lobby() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let client_id = ClientId::new();
+        for index in 0..2 {
+            let table = shared_lobby.read_table(index).await;
+            shared_lobby
+                .join_table(table.id, client_id)
+                .await
+                .expect("Table should be joined");
+        }
+
+        // when
+        let seatings = shared_lobby.leave_all_tables(client_id).await;
+
+        // then
+        assert_eq!(seatings.len(), 2, "Both tables should be left");
+        assert!(shared_lobby.leave_all_tables(client_id).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn take_only_lobbies_of_client() {
+        let shared_lobbies = SharedLobbies::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let name = test_data::lobby_name_poker();
+        shared_lobbies
+            .create(name.clone())
+            .await
+            .expect("Lobby should be created");
+        let (_, table) = shared_lobbies
+            .get(&name)
+            .await
+            .expect("Lobby should exist")
+            .add_table(None, Placement::Last, test_data::table_to_add_foo_fighters())
+            .await
+            .expect("Table should be added");
+        let client_id = ClientId::new();
+        shared_lobbies
+            .join_table(&name, table.id, client_id)
+            .await
+            .expect("Table should be joined");
+
+        // when
+        let client_lobbies = shared_lobbies.take_client_lobbies(client_id).await;
+
+        // then
+        let names: Vec<LobbyName> = client_lobbies.into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, vec![name]);
+        assert!(shared_lobbies.take_client_lobbies(client_id).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn unseat_clients_of_deleted_lobby() {
+        let shared_lobbies = SharedLobbies::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let name = test_data::lobby_name_poker();
+        shared_lobbies
+            .create(name.clone())
+            .await
+            .expect("Lobby should be created");
+        let (_, table) = shared_lobbies
+            .get(&name)
+            .await
+            .expect("Lobby should exist")
+            .add_table(None, Placement::Last, test_data::table_to_add_foo_fighters())
+            .await
+            .expect("Table should be added");
+        let client_id = ClientId::new();
+        shared_lobbies
+            .join_table(&name, table.id, client_id)
+            .await
+            .expect("Table should be joined");
+
+        // when
+        let result = shared_lobbies.delete(name).await;
+
+        // then
+        let (_, seatings) = result.expect("Lobby should be deleted");
+        assert_eq!(seatings.len(), 1);
+        assert_eq!(seatings[0].unseated_client_ids, vec![client_id]);
+    }
+
+    #[tokio::test]
+    async fn update_statuses_of_scheduled_tables() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let prepopulated_table = shared_lobby.read_table(0).await;
+        let changes = TableChanges {
+            opens_at: Some(Some(test_data::timestamp(100))),
+            closes_at: Some(Some(test_data::timestamp(200))),
+            ..TableChanges::default()
+        };
+        shared_lobby
+            .patch_table(None, prepopulated_table.id, changes)
+            .await
+            .expect("Table should be patched");
+
+        // when
+        let scheduled_tables = shared_lobby.update_statuses(test_data::timestamp(50)).await;
+        let unchanged_tables = shared_lobby.update_statuses(test_data::timestamp(60)).await;
+        let open_tables = shared_lobby.update_statuses(test_data::timestamp(100)).await;
+        let closed_tables = shared_lobby.update_statuses(test_data::timestamp(200)).await;
+
+        // then
+        assert_eq!(scheduled_tables.len(), 1);
+        assert_eq!(scheduled_tables[0].table.as_ref().map(|table| table.status), Some(TableStatus::Scheduled));
+        assert!(unchanged_tables.is_empty(), "Status should remain the same");
+        assert_eq!(open_tables.len(), 1);
+        assert_eq!(open_tables[0].table.as_ref().map(|table| table.status), Some(TableStatus::Open));
+        assert_eq!(closed_tables.len(), 1);
+        assert_eq!(closed_tables[0].table.as_ref().map(|table| table.status), Some(TableStatus::Closed));
+
+        let result = shared_lobby.join_table(prepopulated_table.id, ClientId::new()).await;
+        assert!(result.is_err(), "Closed table should not be joined");
+    }
+
+    #[tokio::test]
+    async fn free_seats_when_scheduled_table_closes() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let prepopulated_table = shared_lobby.read_table(0).await;
+        let client_id = ClientId::new();
+        let changes = TableChanges {
+            closes_at: Some(Some(Timestamp::now() + Duration::from_secs(60))),
+            ..TableChanges::default()
+        };
+        shared_lobby
+            .patch_table(None, prepopulated_table.id, changes)
+            .await
+            .expect("Table should be patched");
+        shared_lobby
+            .join_table(prepopulated_table.id, client_id)
+            .await
+            .expect("Table should be joined");
+
+        // when
+        let seatings = shared_lobby
+            .update_statuses(Timestamp::now() + Duration::from_secs(60))
+            .await;
+
+        // then
+        assert_eq!(seatings.len(), 1);
+        assert_eq!(seatings[0].unseated_client_ids, vec![client_id]);
+        let table = seatings[0].table.as_ref().expect("Table should be updated");
+        assert_eq!(table.status, TableStatus::Closed);
+        assert_eq!(table.participants, 0);
+    }
+
+    #[tokio::test]
+    async fn create_lobby() {
+        let shared_lobbies = SharedLobbies::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let name = test_data::lobby_name_poker();
+
+        // when
+        let result = shared_lobbies.create(name.clone()).await;
+
+        // then
+        let created_name = result.expect("Lobby should be created");
+        assert_eq!(created_name, name);
+
+        let shared_lobby = shared_lobbies.get(&name).await.expect("Lobby should exist");
+        assert_eq!(shared_lobby.len().await, 0, "Lobby should be empty");
+    }
+
+    #[tokio::test]
+    async fn lock_changes_of_each_lobby_separately() {
+        let shared_lobbies = SharedLobbies::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let name = test_data::lobby_name_poker();
+        shared_lobbies
+            .create(name.clone())
+            .await
+            .expect("Lobby should be created");
+        let _change_guard = shared_lobbies.lock_changes(&LobbyName::default()).await;
+
+        // when
+        let other_guard = tokio::time::timeout(Duration::from_millis(100), shared_lobbies.lock_changes(&name)).await;
+        let same_guard =
+            tokio::time::timeout(Duration::from_millis(100), shared_lobbies.lock_changes(&LobbyName::default())).await;
+
+        // then
+        assert!(other_guard.is_ok_and(|guard| guard.is_some()), "Other lobby should be locked");
+        assert!(same_guard.is_err(), "Same lobby should not be locked twice");
+    }
+
+    #[tokio::test]
+    async fn not_create_lobby_when_lobby_exists() {
+        let shared_lobbies = SharedLobbies::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+
+        // when
+        let result = shared_lobbies.create(LobbyName::default()).await;
+
+        // then
+        assert!(result.is_err(), "Lobby should not be created");
+    }
+
+    #[tokio::test]
+    async fn delete_lobby() {
+        let shared_lobbies = SharedLobbies::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let name = test_data::lobby_name_poker();
+        shared_lobbies
+            .create(name.clone())
+            .await
+            .expect("Lobby should be created");
+
+        // when
+        let result = shared_lobbies.delete(name.clone()).await;
+
+        // then
+        let (deleted_name, seatings) = result.expect("Lobby should be deleted");
+        assert_eq!(deleted_name, name);
+        assert!(seatings.is_empty(), "Nobody should be unseated");
+        assert!(shared_lobbies.get(&name).await.is_err(), "Lobby should not exist");
+    }
+
+    #[tokio::test]
+    async fn not_delete_default_lobby() {
+        let shared_lobbies = SharedLobbies::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+
+        // when
+        let result = shared_lobbies.delete(LobbyName::default()).await;
+
+        // then
+        assert!(result.is_err(), "Lobby should not be deleted");
+        assert!(shared_lobbies.get(&LobbyName::default()).await.is_ok(), "Lobby should exist");
+    }
+
+    #[tokio::test]
+    async fn record_table_history_along_with_users() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let admin = Some(test_data::username_admin());
+        let (_, table) = shared_lobby
+            .add_table(admin.clone(), Placement::Last, test_data::table_to_add_foo_fighters())
+            .await
+            .expect("Table should be added");
+        let changes = TableChanges {
+            name: Some(TableName::new(String::from("Pink Floyd"))),
+            ..TableChanges::default()
+        };
+        shared_lobby
+            .patch_table(admin.clone(), table.id, changes)
+            .await
+            .expect("Table should be patched");
+        shared_lobby
+            .join_table(table.id, ClientId::new())
+            .await
+            .expect("Table should be joined");
+
+        // when
+        let result = shared_lobby.read_table_history(table.id).await;
+
+        // then
+        let revisions = result.expect("Table history should be found");
+        let usernames: Vec<_> = revisions.iter().map(|revision| revision.username.clone()).collect();
+        assert_eq!(usernames, vec![admin.clone(), admin], "Joining table should not be recorded");
+        let names: Vec<_> = revisions
+            .iter()
+            .filter_map(|revision| revision.table.as_ref())
+            .map(|table| table.name.clone())
+            .collect();
+        assert_eq!(names[0], TableName::new(String::from("Foo Fighters")));
+        assert_eq!(names[1], TableName::new(String::from("Pink Floyd")));
+    }
+
+    #[tokio::test]
+    async fn not_find_history_of_unknown_table() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+
+        // when
+        let result = shared_lobby.read_table_history(test_data::TABLE_ID_INVALID).await;
+
+        // then
+        assert!(result.is_err(), "Table history should not be found");
+    }
+
+    #[tokio::test]
+    async fn reconstruct_tables_as_of_now() {
+        let shared_lobby = SharedLobby::prepopulated(TableRules::default(), table_ids(), TRASH_RETENTION);
+        let first_table = shared_lobby.read_table(0).await;
+        let operations = vec![
+            TableOperation::AddTable {
+                placement: Placement::Last,
+                table: test_data::table_to_add_foo_fighters(),
+            },
+            TableOperation::MoveTable {
+                id: first_table.id,
+                placement: Placement::Last,
+            },
+        ];
+        shared_lobby
+            .apply_batch(None, operations)
+            .await
+            .expect("Batch should be applied");
+        shared_lobby
+            .remove_table(None, first_table.id)
+            .await
+            .expect("Table should be removed");
+
+        // when
+        let result = shared_lobby.read_tables_at(Timestamp::now()).await;
+
+        // then
+        let tables: Vec<Table> = shared_lobby
+            .read_tables()
+            .await
+            .iter()
+            .map(|table| Table::clone(table))
+            .collect();
+        assert_eq!(result.expect("Tables should be reconstructed"), tables);
+        assert!(
+            shared_lobby.read_tables_at(test_data::timestamp(0)).await.is_err(),
+            "Tables should not be reconstructed before the lobby has been created"
+        );
+    }
+
     impl SharedLobby {
         async fn len(&self) -> usize {
-            self.lobby.read().await.tables.len()
+            self.read_tables().await.len()
         }
 
         async fn read_table(&self, index: usize) -> Table {
-            self.lobby
-                .read()
+            self.read_tables()
                 .await
-                .tables
                 .get(index)
+                .map(|table| Table::clone(table))
                 .unwrap_or_else(|| panic!("Table at index {} should exist", index))
-                .clone()
         }
     }
 }
@@ -1,9 +1,61 @@
+use std::env;
+use std::fs;
+use std::time::Duration;
+
+use lobby_api_rust::idempotency::IDEMPOTENCY_WINDOW;
+use lobby_api_rust::lobby::TRASH_RETENTION;
+use lobby_api_rust::permission::Permissions;
+use lobby_api_rust::table_id::TableIdConfig;
+use lobby_api_rust::validation::TableRules;
 use lobby_api_rust::web_socket;
 
 #[tokio::main]
 async fn main() {
     pretty_env_logger::init();
 
+    // Generate table ids as configured, e.g. `LOBBY_TABLE_IDS=snowflake:1` for each instance in the cluster
+    let table_ids = match env::var("LOBBY_TABLE_IDS") {
+        Ok(value) => value
+            .parse()
+            .unwrap_or_else(|e| panic!("Failed to parse LOBBY_TABLE_IDS: {}", e)),
+        Err(_) => TableIdConfig::default(),
+    };
+
+    // Validate tables against the configured rules, e.g. `LOBBY_TABLE_RULES='{"name_max_length": 32}'`
+    let rules = match env::var("LOBBY_TABLE_RULES") {
+        Ok(value) => {
+            serde_json::from_str(&value).unwrap_or_else(|e| panic!("Failed to parse LOBBY_TABLE_RULES: {}", e))
+        }
+        Err(_) => TableRules::default(),
+    };
+
+    // Keep removed tables for the configured number of seconds, e.g. `LOBBY_TRASH_RETENTION=3600`
+    let trash_retention = match env::var("LOBBY_TRASH_RETENTION") {
+        Ok(value) => value
+            .parse()
+            .map(Duration::from_secs)
+            .unwrap_or_else(|e| panic!("Failed to parse LOBBY_TRASH_RETENTION: {}", e)),
+        Err(_) => TRASH_RETENTION,
+    };
+
+    // Allow user types to send input messages as configured in the file, e.g. `LOBBY_PERMISSIONS=permissions.json`
+    let permissions = match env::var("LOBBY_PERMISSIONS") {
+        Ok(path) => fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|content| serde_json::from_str(&content).map_err(|e| e.to_string()))
+            .unwrap_or_else(|e| panic!("Failed to read permissions from {:?}: {}", path, e)),
+        Err(_) => Permissions::default(),
+    };
+
+    // Remember idempotency keys for the configured number of seconds, e.g. `LOBBY_IDEMPOTENCY_WINDOW=3600`
+    let idempotency_window = match env::var("LOBBY_IDEMPOTENCY_WINDOW") {
+        Ok(value) => value
+            .parse()
+            .map(Duration::from_secs)
+            .unwrap_or_else(|e| panic!("Failed to parse LOBBY_IDEMPOTENCY_WINDOW: {}", e)),
+        Err(_) => IDEMPOTENCY_WINDOW,
+    };
+
     // TODO Extract host and port into configuration parameters
-    web_socket::run(([127, 0, 0, 1], 9000)).await;
+    web_socket::run(([127, 0, 0, 1], 9000), table_ids, rules, trash_retention, permissions, idempotency_window).await;
 }
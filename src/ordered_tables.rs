@@ -0,0 +1,565 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound::{Excluded, Unbounded};
+use std::sync::{Arc, OnceLock};
+
+use crate::protocol::{Placement, Table, TableId, TableName};
+
+/// Represents the immutable snapshot of ordered tables, which can be shared without copying tables.
+pub type TableSnapshot = Arc<[Arc<Table>]>;
+
+/// Represents the position of the table, tables are ordered by their positions.
+type Position = i64;
+
+/// The gap between positions of adjacent tables, once positions are spread evenly.
+const POSITION_GAP: Position = 1 << 32;
+
+/// The factor, by which the density allowed within the window of positions shrinks each time the window doubles,
+/// so that larger windows are spread only once they are sparse enough to leave room for many insertions.
+const DENSITY_THRESHOLD: f64 = 1.4;
+
+/// Represents the table along with its position. The table is shared with snapshots taken before it changes.
+#[derive(Clone)]
+struct Entry {
+    position: Position,
+    table: Arc<Table>,
+}
+
+/// Represents the ordered collection of tables, which finds tables by id in constant time and inserts, moves
+/// and removes them in logarithmic time.
+///
+/// Tables are ordered by sparse positions, so that the table inserted between two others takes the position
+/// halfway between theirs. Once there is no room left between them, positions are spread evenly within
+/// the smallest aligned window around them, which is sparse enough, while the allowed density shrinks as windows
+/// grow. Like in order-maintenance, repeated insertions at the same place spread ever larger windows ever more
+/// rarely, which keeps insertion logarithmic on average. Positions of all tables are spread only once the whole
+/// range of positions is exhausted.
+///
+/// Tables are copied on write, so that the snapshot remains unchanged, while it is cheap to take. The snapshot
+/// is built once after each change and then shared by all the readers until the next change.
+#[derive(Clone, Default)]
+pub struct OrderedTables {
+    entries: HashMap<TableId, Entry>,
+    order: BTreeMap<Position, TableId>,
+    /// Ids of tables with each name, which are usually unique.
+    names: HashMap<TableName, Vec<TableId>>,
+    snapshot: OnceLock<TableSnapshot>,
+}
+impl OrderedTables {
+    pub fn contains(&self, id: TableId) -> bool {
+        self.entries.contains_key(&id)
+    }
+
+    pub fn get(&self, id: TableId) -> Option<&Table> {
+        self.entries.get(&id).map(|entry| entry.table.as_ref())
+    }
+
+    /// Returns the table for changing anything but its name, which should be changed with `replace` instead.
+    /// The table is copied first, if it is still shared with the snapshot.
+    pub fn get_mut(&mut self, id: TableId) -> Option<&mut Table> {
+        let entry = self.entries.get_mut(&id)?;
+        self.snapshot.take();
+        Some(Arc::make_mut(&mut entry.table))
+    }
+
+    /// Returns whether any table other than the given one has the same name.
+    pub fn is_name_taken(&self, table: &Table) -> bool {
+        self.names
+            .get(&table.name)
+            .is_some_and(|ids| ids.iter().any(|id| *id != table.id))
+    }
+
+    /// Returns the snapshot of tables in their order, which is built only once after each change.
+    pub fn snapshot(&self) -> TableSnapshot {
+        self.snapshot
+            .get_or_init(|| self.order.values().map(|id| self.entries[id].table.clone()).collect())
+            .clone()
+    }
+
+    /// Returns tables in their order without taking the snapshot.
+    pub fn iter(&self) -> impl Iterator<Item = &Table> {
+        self.order.values().map(|id| self.entries[id].table.as_ref())
+    }
+
+    /// Returns ids of tables in their order.
+    pub fn ids(&self) -> Vec<TableId> {
+        self.order.values().copied().collect()
+    }
+
+    /// Returns ids of tables right before and right after the table with the given id, which are absent at
+    /// either end.
+    pub fn neighbours(&self, id: TableId) -> Option<(TableId, TableId)> {
+        let position = self.entries.get(&id)?.position;
+        let before_id = self.order.range(..position).next_back().map(|(_, id)| *id);
+        let after_id = self
+            .order
+            .range((Excluded(position), Unbounded))
+            .next()
+            .map(|(_, id)| *id);
+        Some((before_id.unwrap_or(TableId::ABSENT), after_id.unwrap_or(TableId::ABSENT)))
+    }
+
+    /// Returns the id of the table, after which the table should be put according to the given placement,
+    /// or the absent id to put it in front. The table being moved is skipped, as it leaves its place once
+    /// moved, while the absent id can be given to skip none. Returns none once the place cannot be found.
+    ///
+    /// Tables are found by id or at either end in logarithmic time, but at the given index in linear time.
+    pub fn resolve(&self, placement: Placement, moved_id: TableId) -> Option<TableId> {
+        let after_id = match placement {
+            Placement::First => TableId::ABSENT,
+            Placement::Last => self
+                .order
+                .values()
+                .rev()
+                .find(|id| **id != moved_id)
+                .copied()
+                .unwrap_or(TableId::ABSENT),
+            Placement::After(id) if id != moved_id && self.contains(id) => id,
+            Placement::After(_) => return None,
+            Placement::Before(id) if id != moved_id => {
+                let position = self.entries.get(&id)?.position;
+                self.order
+                    .range(..position)
+                    .rev()
+                    .map(|(_, id)| *id)
+                    .find(|id| *id != moved_id)
+                    .unwrap_or(TableId::ABSENT)
+            }
+            Placement::Before(_) => return None,
+            Placement::Index(0) => TableId::ABSENT,
+            Placement::Index(index) => *self.order.values().filter(|id| **id != moved_id).nth(index - 1)?,
+        };
+        Some(after_id)
+    }
+
+    /// Inserts the table after the table with the given id or in front, if the id is absent. The table,
+    /// after which the given table is inserted, should exist, otherwise the table is inserted in front.
+    pub fn insert_after(&mut self, after_id: TableId, table: Table) {
+        let position = self.position_after(after_id);
+        self.order.insert(position, table.id);
+        self.names.entry(table.name.clone()).or_default().push(table.id);
+        self.entries.insert(
+            table.id,
+            Entry {
+                position,
+                table: Arc::new(table),
+            },
+        );
+        self.snapshot.take();
+    }
+
+    /// Replaces the existing table with the same id, keeping its position, and returns the replaced table.
+    pub fn replace(&mut self, table: Table) -> Option<Table> {
+        let entry = self.entries.get_mut(&table.id)?;
+        if entry.table.name != table.name {
+            Self::remove_name(&mut self.names, &entry.table);
+            self.names.entry(table.name.clone()).or_default().push(table.id);
+        }
+        self.snapshot.take();
+        let replaced_table = std::mem::replace(&mut entry.table, Arc::new(table));
+        Some(Arc::unwrap_or_clone(replaced_table))
+    }
+
+    /// Moves the existing table after the table with the given id or in front, if the id is absent,
+    /// returning whether the table has been moved.
+    pub fn move_after(&mut self, id: TableId, after_id: TableId) -> bool {
+        let old_position = match self.entries.get(&id) {
+            Some(entry) => entry.position,
+            None => return false,
+        };
+        self.order.remove(&old_position);
+        let position = self.position_after(after_id);
+        self.order.insert(position, id);
+        if let Some(entry) = self.entries.get_mut(&id) {
+            entry.position = position;
+        }
+        self.snapshot.take();
+        true
+    }
+
+    pub fn remove(&mut self, id: TableId) -> Option<Table> {
+        let entry = self.entries.remove(&id)?;
+        self.order.remove(&entry.position);
+        Self::remove_name(&mut self.names, &entry.table);
+        self.snapshot.take();
+        Some(Arc::unwrap_or_clone(entry.table))
+    }
+
+    fn remove_name(names: &mut HashMap<TableName, Vec<TableId>>, table: &Table) {
+        if let Some(ids) = names.get_mut(&table.name) {
+            ids.retain(|id| *id != table.id);
+            if ids.is_empty() {
+                names.remove(&table.name);
+            }
+        }
+    }
+
+    /// Returns the free position right after the table with the given id, spreading positions if needed.
+    fn position_after(&mut self, after_id: TableId) -> Position {
+        self.try_position_after(after_id).unwrap_or_else(|| {
+            let spread = match self.entries.get(&after_id) {
+                Some(entry) => self.spread_window(entry.position),
+                None => false,
+            };
+            if !spread {
+                self.spread_positions();
+            }
+            self.try_position_after(after_id)
+                .expect("Evenly spread positions should leave room between tables")
+        })
+    }
+
+    fn try_position_after(&self, after_id: TableId) -> Option<Position> {
+        let previous = self.entries.get(&after_id).map(|entry| entry.position);
+        let next = match previous {
+            Some(previous) => self.order.range(previous + 1..).next(),
+            None => self.order.iter().next(),
+        }
+        .map(|(position, _)| *position);
+        match (previous, next) {
+            (None, None) => Some(0),
+            (None, Some(next)) => next.checked_sub(POSITION_GAP),
+            (Some(previous), None) => previous.checked_add(POSITION_GAP),
+            (Some(previous), Some(next)) if next - previous > 1 => Some(previous + (next - previous) / 2),
+            (Some(_), Some(_)) => None,
+        }
+    }
+
+    /// Spreads positions evenly within the smallest aligned window around the given position, which has room
+    /// for one more table within the allowed density, returning whether any window has been spread.
+    fn spread_window(&mut self, position: Position) -> bool {
+        for level in 1..Position::BITS - 2 {
+            let size: Position = 1 << level;
+            let start = position & !(size - 1);
+            let end = match start.checked_add(size) {
+                Some(end) => end,
+                None => return false,
+            };
+            let ids: Vec<TableId> = self.order.range(start..end).map(|(_, id)| *id).collect();
+            let count = ids.len() as Position + 1;
+            let gap = size / count;
+            if count as f64 > (2.0 / DENSITY_THRESHOLD).powi(level as i32) || gap < 2 {
+                continue;
+            }
+            // Clear the window first, so that spread positions never collide with positions not spread yet
+            for id in ids.iter() {
+                self.order.remove(&self.entries[id].position);
+            }
+            for (index, id) in ids.into_iter().enumerate() {
+                let position = start + gap / 2 + index as Position * gap;
+                self.order.insert(position, id);
+                if let Some(entry) = self.entries.get_mut(&id) {
+                    entry.position = position;
+                }
+            }
+            return true;
+        }
+        false
+    }
+
+    fn spread_positions(&mut self) {
+        debug!("Spreading positions of {} tables", self.order.len());
+        let ids = self.ids();
+        self.order.clear();
+        for (index, id) in ids.into_iter().enumerate() {
+            let position = index as Position * POSITION_GAP;
+            self.order.insert(position, id);
+            if let Some(entry) = self.entries.get_mut(&id) {
+                entry.position = position;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    use crate::protocol::{test_data, Placement, Table, TableId, TableName};
+    use crate::table_id::{TableIdGenerator, UlidIds};
+
+    use super::OrderedTables;
+
+    fn table(name: &str) -> Table {
+        Table {
+            id: UlidIds.generate().expect("Table id should be generated"),
+            name: TableName::new(String::from(name)),
+            ..test_data::table_foo_fighters()
+        }
+    }
+
+    fn tables() -> OrderedTables {
+        let mut tables = OrderedTables::default();
+        tables.insert_after(TableId::ABSENT, test_data::table_mission_impossible());
+        tables.insert_after(TableId::ABSENT, test_data::table_james_bond());
+        tables
+    }
+
+    #[test]
+    fn insert_tables_in_order() {
+        let mut tables = tables();
+
+        // when
+        tables.insert_after(test_data::table_james_bond().id, test_data::table_foo_fighters());
+
+        // then
+        let inserted_tables: Vec<Table> = tables.snapshot().iter().map(|table| Table::clone(table)).collect();
+        assert_eq!(
+            inserted_tables,
+            vec![
+                test_data::table_james_bond(),
+                test_data::table_foo_fighters(),
+                test_data::table_mission_impossible()
+            ]
+        );
+        assert_eq!(tables.get(test_data::table_foo_fighters().id), Some(&test_data::table_foo_fighters()));
+    }
+
+    #[test]
+    fn insert_tables_after_same_table_once_positions_are_exhausted() {
+        let mut tables = tables();
+        let first_id = test_data::table_james_bond().id;
+        let inserted_tables: Vec<Table> = (0..100).map(|index| table(&format!("Table {}", index))).collect();
+
+        // when
+        for table in inserted_tables.iter() {
+            tables.insert_after(first_id, table.clone());
+        }
+
+        // then
+        let expected_ids: Vec<TableId> = [first_id]
+            .into_iter()
+            .chain(inserted_tables.iter().rev().map(|table| table.id))
+            .chain([test_data::table_mission_impossible().id])
+            .collect();
+        assert_eq!(tables.ids(), expected_ids);
+    }
+
+    #[test]
+    fn spread_positions_only_within_window_around_exhausted_place() {
+        let mut tables = tables();
+        let first_id = test_data::table_james_bond().id;
+        let last_id = test_data::table_mission_impossible().id;
+        let last_position = tables.entries[&last_id].position;
+
+        // when
+        for index in 0..1000 {
+            tables.insert_after(first_id, table(&format!("Table {}", index)));
+        }
+
+        // then
+        assert_eq!(tables.ids().len(), 1002);
+        assert_eq!(tables.ids().last(), Some(&last_id));
+        assert_eq!(
+            tables.entries[&last_id].position, last_position,
+            "Position of the table far from the exhausted place should remain the same"
+        );
+    }
+
+    #[test]
+    fn resolve_placements() {
+        let tables = tables();
+        let james_bond_id = test_data::table_james_bond().id;
+        let mission_impossible_id = test_data::table_mission_impossible().id;
+
+        // when
+        let resolve = |placement| tables.resolve(placement, TableId::ABSENT);
+
+        // then
+        assert_eq!(resolve(Placement::First), Some(TableId::ABSENT));
+        assert_eq!(resolve(Placement::Last), Some(mission_impossible_id));
+        assert_eq!(resolve(Placement::After(james_bond_id)), Some(james_bond_id));
+        assert_eq!(resolve(Placement::Before(james_bond_id)), Some(TableId::ABSENT));
+        assert_eq!(resolve(Placement::Before(mission_impossible_id)), Some(james_bond_id));
+        assert_eq!(resolve(Placement::Index(0)), Some(TableId::ABSENT));
+        assert_eq!(resolve(Placement::Index(2)), Some(mission_impossible_id));
+        assert_eq!(resolve(Placement::Index(3)), None);
+        assert_eq!(resolve(Placement::After(test_data::TABLE_ID_INVALID)), None);
+    }
+
+    #[test]
+    fn resolve_placements_skipping_moved_table() {
+        let tables = tables();
+        let james_bond_id = test_data::table_james_bond().id;
+        let mission_impossible_id = test_data::table_mission_impossible().id;
+
+        // when
+        let resolve = |placement| tables.resolve(placement, mission_impossible_id);
+
+        // then
+        assert_eq!(resolve(Placement::Last), Some(james_bond_id));
+        assert_eq!(resolve(Placement::Index(1)), Some(james_bond_id));
+        assert_eq!(resolve(Placement::Index(2)), None);
+    }
+
+    #[test]
+    fn find_neighbours_of_table() {
+        let mut tables = tables();
+        tables.insert_after(test_data::table_james_bond().id, test_data::table_foo_fighters());
+
+        // when
+        let neighbours = [
+            test_data::table_james_bond(),
+            test_data::table_foo_fighters(),
+            test_data::table_mission_impossible(),
+        ]
+        .map(|table| tables.neighbours(table.id));
+
+        // then
+        let james_bond_id = test_data::table_james_bond().id;
+        let foo_fighters_id = test_data::table_foo_fighters().id;
+        let mission_impossible_id = test_data::table_mission_impossible().id;
+        assert_eq!(neighbours[0], Some((TableId::ABSENT, foo_fighters_id)));
+        assert_eq!(neighbours[1], Some((james_bond_id, mission_impossible_id)));
+        assert_eq!(neighbours[2], Some((foo_fighters_id, TableId::ABSENT)));
+        assert_eq!(tables.neighbours(test_data::TABLE_ID_INVALID), None);
+    }
+
+    #[test]
+    fn move_table_in_front() {
+        let mut tables = tables();
+
+        // when
+        let moved = tables.move_after(test_data::table_mission_impossible().id, TableId::ABSENT);
+
+        // then
+        assert!(moved, "Table should be moved");
+        assert_eq!(
+            tables.ids(),
+            vec![
+                test_data::table_mission_impossible().id,
+                test_data::table_james_bond().id
+            ]
+        );
+    }
+
+    #[test]
+    fn remove_table_along_with_its_name() {
+        let mut tables = tables();
+
+        // when
+        let removed_table = tables.remove(test_data::table_james_bond().id);
+
+        // then
+        assert_eq!(removed_table, Some(test_data::table_james_bond()));
+        assert_eq!(tables.ids(), vec![test_data::table_mission_impossible().id]);
+        assert!(!tables.is_name_taken(&test_data::table_foo_fighters()), "Name should not be taken");
+    }
+
+    #[test]
+    fn track_names_of_replaced_tables() {
+        let mut tables = tables();
+        let renamed_table = Table {
+            name: test_data::table_foo_fighters().name,
+            ..test_data::table_james_bond()
+        };
+
+        // when
+        tables.replace(renamed_table.clone());
+
+        // then
+        assert!(tables.is_name_taken(&test_data::table_foo_fighters()), "New name should be taken");
+        assert!(!tables.is_name_taken(&renamed_table), "Own name should not be taken");
+        assert!(
+            !tables.is_name_taken(&Table {
+                id: UlidIds.generate().expect("Table id should be generated"),
+                ..test_data::table_james_bond()
+            }),
+            "Old name should not be taken"
+        );
+    }
+
+    #[test]
+    fn share_snapshot_until_tables_change() {
+        let mut tables = tables();
+        let snapshot = tables.snapshot();
+
+        // when
+        let unchanged_snapshot = tables.snapshot();
+        if let Some(table) = tables.get_mut(test_data::table_james_bond().id) {
+            table.participants += 1;
+        }
+        let changed_snapshot = tables.snapshot();
+
+        // then
+        assert!(Arc::ptr_eq(&snapshot, &unchanged_snapshot), "Snapshot should be shared");
+        assert_eq!(snapshot[0].participants, test_data::table_james_bond().participants);
+        assert_eq!(changed_snapshot[0].participants, test_data::table_james_bond().participants + 1);
+        assert!(Arc::ptr_eq(&snapshot[1], &changed_snapshot[1]), "Unchanged table should be shared");
+    }
+
+    /// Compares ordered tables against tables kept in the vector, run it in release mode with:
+    ///
+    ///     cargo test --release benchmark_ordered_tables -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn benchmark_ordered_tables() {
+        const TABLES: usize = 20000;
+
+        let added_tables: Vec<Table> = (0..TABLES).map(|index| table(&format!("Table {}", index))).collect();
+        // Add each table after the one added halfway before it, so that tables are added all over the lobby
+        let spread_after_ids: Vec<TableId> = (0..TABLES)
+            .map(|index| match index {
+                0 => TableId::ABSENT,
+                _ => added_tables[index / 2].id,
+            })
+            .collect();
+        // Add each table right after the first one, so that the gap after it is exhausted over and over again
+        let same_after_ids: Vec<TableId> = (0..TABLES)
+            .map(|index| match index {
+                0 => TableId::ABSENT,
+                _ => added_tables[0].id,
+            })
+            .collect();
+
+        for (pattern, after_ids) in [
+            ("all over the lobby", spread_after_ids),
+            ("after the same table", same_after_ids),
+        ] {
+            // when
+            let started = Instant::now();
+            let mut vector: Vec<Table> = vec![];
+            for (after_id, table) in after_ids.iter().zip(added_tables.iter()) {
+                let index = match vector.iter().position(|other| other.id == *after_id) {
+                    Some(index) => index + 1,
+                    None => 0,
+                };
+                vector.insert(index, table.clone());
+            }
+            for table in added_tables.iter() {
+                if let Some(other) = vector.iter_mut().find(|other| other.id == table.id) {
+                    other.participants += 1;
+                }
+            }
+            for table in added_tables.iter() {
+                if let Some(index) = vector.iter().position(|other| other.id == table.id) {
+                    vector.remove(index);
+                }
+            }
+            let vector_elapsed = started.elapsed();
+
+            let started = Instant::now();
+            let mut tables = OrderedTables::default();
+            for (after_id, table) in after_ids.iter().zip(added_tables.iter()) {
+                tables.insert_after(*after_id, table.clone());
+            }
+            for table in added_tables.iter() {
+                if let Some(other) = tables.get_mut(table.id) {
+                    other.participants += 1;
+                }
+            }
+            for table in added_tables.iter() {
+                tables.remove(table.id);
+            }
+            let tables_elapsed = started.elapsed();
+
+            // then
+            println!(
+                "Adding {} tables {}, updating and removing them takes {:?} with vector and {:?} with ordered tables",
+                TABLES, pattern, vector_elapsed, tables_elapsed
+            );
+            assert!(vector.is_empty() && tables.ids().is_empty(), "All tables should be removed");
+        }
+    }
+}
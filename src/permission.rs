@@ -0,0 +1,140 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::Deserialize;
+
+use crate::protocol::InputDiscriminants::{self, *};
+use crate::protocol::UserType;
+
+/// Represents the configurable permissions, which define input messages each user type is allowed to send.
+///
+/// Permissions can be deserialized from JSON, which maps user types to input message types, e.g.
+/// `{"viewer": ["ping", "login"], "admin": ["ping", "login", "add_table"]}`. User types missing from it are
+/// not allowed to send anything.
+#[derive(Clone, Deserialize)]
+#[serde(transparent)]
+pub struct Permissions {
+    pub roles: HashMap<UserType, HashSet<InputDiscriminants>>,
+}
+impl Default for Permissions {
+    fn default() -> Self {
+        let viewer = HashSet::from([Ping, Login, SubscribeTables, UnsubscribeTables]);
+        let user: HashSet<InputDiscriminants> = viewer
+            .iter()
+            .copied()
+            .chain([JoinTable, LeaveTable, JoinWaitlist, LeaveWaitlist])
+            .collect();
+        let operator: HashSet<InputDiscriminants> = user
+            .iter()
+            .copied()
+            .chain([ListSessions, KickSession, Announce])
+            .collect();
+        let table_manager: HashSet<InputDiscriminants> = user
+            .iter()
+            .copied()
+            .chain([AddTable, UpdateTable, PatchTable, MoveTable, RemoveTable, Batch])
+            .collect();
+        let admin: HashSet<InputDiscriminants> = operator
+            .union(&table_manager)
+            .copied()
+            .chain([
+                CreateLobby,
+                DeleteLobby,
+                QueryAuditLog,
+                QueryTableHistory,
+                QueryLobbySnapshot,
+                RestoreTable,
+            ])
+            .collect();
+        Permissions {
+            roles: HashMap::from([
+                (UserType::Viewer, viewer),
+                (UserType::User, user),
+                (UserType::Operator, operator),
+                (UserType::TableManager, table_manager),
+                (UserType::Admin, admin),
+            ]),
+        }
+    }
+}
+impl Permissions {
+    /// Checks whether the given user type is allowed to send the given input message.
+    pub fn allows(&self, user_type: &UserType, input: InputDiscriminants) -> bool {
+        self.roles.get(user_type).is_some_and(|inputs| inputs.contains(&input))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::collections::{HashMap, HashSet};
+
+    use strum::IntoEnumIterator;
+
+    use crate::protocol::{InputDiscriminants, UserType};
+
+    use super::Permissions;
+
+    #[test]
+    fn allow_admin_everything() {
+        let permissions = Permissions::default();
+
+        // when
+        let denied_inputs: Vec<InputDiscriminants> = InputDiscriminants::iter()
+            .filter(|&input| !permissions.allows(&UserType::Admin, input))
+            .collect();
+
+        // then
+        assert!(denied_inputs.is_empty(), "Admin should be allowed everything");
+    }
+
+    #[test]
+    fn allow_operator_only_session_management() {
+        let permissions = Permissions::default();
+
+        // when
+        let kick_allowed = permissions.allows(&UserType::Operator, InputDiscriminants::KickSession);
+        let add_allowed = permissions.allows(&UserType::Operator, InputDiscriminants::AddTable);
+
+        // then
+        assert!(kick_allowed, "Operator should be allowed to kick sessions");
+        assert!(!add_allowed, "Operator should not be allowed to add tables");
+    }
+
+    #[test]
+    fn deny_viewer_joining_tables() {
+        let permissions = Permissions::default();
+
+        // when
+        let allowed = permissions.allows(&UserType::Viewer, InputDiscriminants::JoinTable);
+
+        // then
+        assert!(!allowed, "Viewer should not be allowed to join tables");
+    }
+
+    #[test]
+    fn deserialize_roles() {
+        // when
+        let permissions: Permissions =
+            serde_json::from_str(r#"{"viewer": ["ping", "login", "subscribe_tables"], "operator": ["kick_session"]}"#)
+                .expect("Permissions should be parsed");
+
+        // then
+        assert!(permissions.allows(&UserType::Viewer, InputDiscriminants::SubscribeTables));
+        assert!(permissions.allows(&UserType::Operator, InputDiscriminants::KickSession));
+        assert!(!permissions.allows(&UserType::Operator, InputDiscriminants::Ping));
+        assert!(!permissions.allows(&UserType::Admin, InputDiscriminants::Ping));
+    }
+
+    #[test]
+    fn deny_user_type_without_role() {
+        let permissions = Permissions {
+            roles: HashMap::from([(UserType::User, HashSet::from([InputDiscriminants::Ping]))]),
+        };
+
+        // when
+        let allowed = permissions.allows(&UserType::Admin, InputDiscriminants::Ping);
+
+        // then
+        assert!(!allowed, "User type without role should not be allowed anything");
+    }
+}
@@ -1,44 +1,148 @@
 use serde::{Deserialize, Serialize};
-use std::sync::atomic::{AtomicIsize, Ordering};
+use std::net::SocketAddr;
+use std::ops::Add;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use strum_macros::{EnumDiscriminants, EnumIter};
 
 #[derive(Clone, Copy, Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
 #[serde(transparent)]
 pub struct Seq(u64);
 
-#[derive(Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
 #[serde(transparent)]
 pub struct Username(String);
+impl Username {
+    pub fn new(value: String) -> Self {
+        Self(value)
+    }
+}
 impl AsRef<str> for Username {
     fn as_ref(&self) -> &str {
         &self.0
     }
 }
 
-#[derive(Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
 #[serde(transparent)]
 pub struct Password(String);
+impl Password {
+    pub fn new(value: String) -> Self {
+        Self(value)
+    }
+}
 impl AsRef<str> for Password {
     fn as_ref(&self) -> &str {
         &self.0
     }
 }
 
-/// The global unique table id generator.
-static NEXT_TABLE_ID: AtomicIsize = AtomicIsize::new(1);
+/// Represents the key chosen by the client for the change, so that the change is applied only once, even if
+/// the client retries it.
+#[derive(Clone, Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct IdempotencyKey(String);
+impl IdempotencyKey {
+    pub fn new(value: String) -> Self {
+        Self(value)
+    }
+}
+
+/// The global unique client id generator.
+static NEXT_CLIENT_ID: AtomicUsize = AtomicUsize::new(1);
 
-#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
+/// Represents the client id.
+#[derive(Clone, Copy, Debug, Hash, Eq, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
 #[serde(transparent)]
-pub struct TableId(isize);
+pub struct ClientId(usize);
+impl ClientId {
+    pub(crate) fn new() -> Self {
+        ClientId(NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// Represents the table id, which is either numeric or the ULID (Universally Unique Lexicographically Sortable
+/// Identifier). Numeric ids are sent as numbers, while ULIDs are sent as strings of 26 Crockford's Base32 digits.
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "TableIdValue", into = "TableIdValue")]
+pub struct TableId(i128);
 impl TableId {
-    pub fn new() -> Self {
-        TableId(NEXT_TABLE_ID.fetch_add(1, Ordering::Relaxed))
+    pub(crate) fn new(value: i128) -> Self {
+        TableId(value)
     }
 
     /// Table id to use as an absent (special, nonexistent) value.
     pub const ABSENT: TableId = TableId(-1);
 }
 
+/// The digits of Crockford's Base32 used by ULIDs.
+const ULID_DIGITS: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// The number of digits in ULIDs.
+const ULID_LENGTH: usize = 26;
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum TableIdValue {
+    Number(i64),
+    Ulid(String),
+}
+impl From<TableId> for TableIdValue {
+    fn from(id: TableId) -> Self {
+        match i64::try_from(id.0) {
+            Ok(number) => TableIdValue::Number(number),
+            Err(_) => TableIdValue::Ulid(
+                (0..ULID_LENGTH)
+                    .map(|index| ULID_DIGITS[(id.0 as u128 >> (5 * (ULID_LENGTH - 1 - index)) & 31) as usize] as char)
+                    .collect(),
+            ),
+        }
+    }
+}
+impl TryFrom<TableIdValue> for TableId {
+    type Error = String;
+
+    fn try_from(value: TableIdValue) -> Result<Self, Self::Error> {
+        let ulid = match value {
+            TableIdValue::Number(number) => return Ok(TableId(number as i128)),
+            TableIdValue::Ulid(ulid) => ulid,
+        };
+        if ulid.len() != ULID_LENGTH || !ulid.starts_with(|c: char| ('0'..='7').contains(&c)) {
+            return Err(format!("Table id {:?} should be the number or the ULID", ulid));
+        }
+        let mut id: u128 = 0;
+        for c in ulid.chars() {
+            match ULID_DIGITS
+                .iter()
+                .position(|digit| *digit as char == c.to_ascii_uppercase())
+            {
+                Some(digit) => id = id << 5 | digit as u128,
+                None => return Err(format!("Table id {:?} should not contain character {:?}", ulid, c)),
+            }
+        }
+        match i64::try_from(id) {
+            Ok(_) => Err(format!("Table id {:?} should be sent as the number", ulid)),
+            Err(_) => Ok(TableId(id as i128)),
+        }
+    }
+}
+
+/// Represents the lobby name, the default lobby is used whenever the lobby name is omitted.
+#[derive(Clone, Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct LobbyName(String);
+impl LobbyName {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+impl Default for LobbyName {
+    fn default() -> Self {
+        Self(String::from("default"))
+    }
+}
+
 #[derive(Clone, Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
 #[serde(transparent)]
 pub struct TableName(String);
@@ -47,18 +151,66 @@ impl TableName {
         Self(value)
     }
 }
+impl AsRef<str> for TableName {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Represents the point in time as the number of seconds since Unix epoch.
+#[derive(Clone, Copy, Debug, Hash, Eq, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Timestamp(u64);
+impl Timestamp {
+    pub fn now() -> Self {
+        let duration = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        Timestamp(duration.as_secs())
+    }
+}
+impl Add<Duration> for Timestamp {
+    type Output = Timestamp;
+
+    fn add(self, duration: Duration) -> Self::Output {
+        Timestamp(self.0.saturating_add(duration.as_secs()))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Hash, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TableStatus {
+    /// The table is waiting for its open time.
+    Scheduled,
+    #[default]
+    Open,
+    /// The table has passed its close time.
+    Closed,
+}
+impl TableStatus {
+    pub fn is_open(&self) -> bool {
+        *self == TableStatus::Open
+    }
+}
 
-#[derive(Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
 pub struct TableToAdd {
     pub name: TableName,
-    pub participants: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capacity: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub opens_at: Option<Timestamp>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub closes_at: Option<Timestamp>,
 }
 impl TableToAdd {
     pub fn into_table(self, id: TableId) -> Table {
         Table {
             id,
             name: self.name,
-            participants: self.participants,
+            participants: 0,
+            capacity: self.capacity,
+            opens_at: self.opens_at,
+            closes_at: self.closes_at,
+            status: TableStatus::Open,
         }
     }
 }
@@ -67,54 +219,692 @@ impl TableToAdd {
 pub struct Table {
     pub id: TableId,
     pub name: TableName,
+    /// The number of clients seated at this table maintained by the server, cannot be updated by clients.
+    #[serde(default)]
     pub participants: u64,
+    /// The maximum number of participants, which can join this table, unlimited if absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capacity: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub opens_at: Option<Timestamp>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub closes_at: Option<Timestamp>,
+    /// The status maintained by the server according to open and close times, cannot be updated by clients.
+    #[serde(default, skip_serializing_if = "TableStatus::is_open")]
+    pub status: TableStatus,
 }
 impl Table {
-    /// Updates all fields of this table with fields of the other table, excluding id.
+    /// Updates all fields of this table with fields of the other table, excluding id, participants and status.
     pub fn update_with(&mut self, other: Table) {
         self.name = other.name;
-        self.participants = other.participants;
+        self.capacity = other.capacity;
+        self.opens_at = other.opens_at;
+        self.closes_at = other.closes_at;
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.capacity.is_some_and(|capacity| self.participants >= capacity)
+    }
+
+    /// Returns the status this table should have at the given time according to its open and close times.
+    pub fn status_at(&self, now: Timestamp) -> TableStatus {
+        if self.closes_at.is_some_and(|closes_at| closes_at <= now) {
+            TableStatus::Closed
+        } else if self.opens_at.is_some_and(|opens_at| opens_at > now) {
+            TableStatus::Scheduled
+        } else {
+            TableStatus::Open
+        }
+    }
+
+    /// Updates only those fields of this table that are present in the given changes.
+    pub fn patch_with(&mut self, changes: TableChanges) {
+        if let Some(name) = changes.name {
+            self.name = name;
+        }
+        if let Some(capacity) = changes.capacity {
+            self.capacity = capacity;
+        }
+        if let Some(opens_at) = changes.opens_at {
+            self.opens_at = opens_at;
+        }
+        if let Some(closes_at) = changes.closes_at {
+            self.closes_at = closes_at;
+        }
+        if let Some(participants) = changes.participants {
+            self.participants = participants;
+        }
+        if let Some(status) = changes.status {
+            self.status = status;
+        }
+    }
+
+    /// Returns only those fields of this table that differ from the given former version of this table.
+    pub fn changes_from(&self, before: &Table) -> TableChanges {
+        fn changed<T: Clone + PartialEq>(after: &T, before: &T) -> Option<T> {
+            (after != before).then(|| after.clone())
+        }
+        TableChanges {
+            name: changed(&self.name, &before.name),
+            capacity: changed(&self.capacity, &before.capacity),
+            opens_at: changed(&self.opens_at, &before.opens_at),
+            closes_at: changed(&self.closes_at, &before.closes_at),
+            participants: changed(&self.participants, &before.participants),
+            status: changed(&self.status, &before.status),
+        }
+    }
+}
+
+/// Represents the subset of table fields to change, absent fields are left intact, while optional fields set
+/// to `null` are cleared.
+#[derive(Clone, Debug, Default, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TableChanges {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<TableName>,
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "double_option")]
+    pub capacity: Option<Option<u64>>,
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "double_option")]
+    pub opens_at: Option<Option<Timestamp>>,
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "double_option")]
+    pub closes_at: Option<Option<Timestamp>>,
+    /// The number of seated clients changed by the server along with the table, ignored once sent by clients.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub participants: Option<u64>,
+    /// The status recomputed by the server along with the table, ignored once sent by clients.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<TableStatus>,
+}
+impl TableChanges {
+    pub fn is_empty(&self) -> bool {
+        *self == TableChanges::default()
+    }
+}
+
+/// Serializes the optional field, which can be changed, so that the absent field is left intact, while `null`
+/// clears the field.
+mod double_option {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<T, S>(value: &Option<Option<T>>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: Serialize,
+        S: Serializer,
+    {
+        match value {
+            Some(value) => value.serialize(serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+    where
+        T: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        Option::<T>::deserialize(deserializer).map(Some)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TableField {
+    Name,
+    Capacity,
+    ClosesAt,
+}
+
+/// Represents the validation error of the particular table field.
+#[derive(Clone, Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct FieldError {
+    pub field: TableField,
+    pub message: String,
+}
+impl FieldError {
+    pub fn new(field: TableField, message: String) -> Self {
+        FieldError { field, message }
+    }
+}
+
+/// Represents the criteria, which tables should match to be visible to the subscribed client.
+#[derive(Clone, Debug, Default, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TableFilter {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name_prefix: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub participants_min: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub participants_max: Option<u64>,
+}
+impl TableFilter {
+    pub fn matches(&self, table: &Table) -> bool {
+        self.name_prefix
+            .as_ref()
+            .is_none_or(|name_prefix| table.name.as_ref().starts_with(name_prefix.as_str()))
+            && self.participants_min.is_none_or(|min| table.participants >= min)
+            && self.participants_max.is_none_or(|max| table.participants <= max)
+    }
+}
+
+/// Represents the window of filtered tables visible to the subscribed client.
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TablePage {
+    pub offset: usize,
+    pub limit: usize,
+}
+
+/// Represents the place among other tables, where the table should be added or moved to.
+///
+/// For compatibility, the place can also be given as `after_id`, the id of the table the table should be put
+/// after or `-1` to put it in front.
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Placement {
+    /// In front of all the tables.
+    First,
+    /// Behind all the tables.
+    Last,
+    After(TableId),
+    Before(TableId),
+    /// At the given index starting from 0, which is at most the number of other tables.
+    Index(usize),
+}
+
+/// Deserializes the placement from either the explicit placement or the legacy `after_id`.
+fn deserialize_placement<'de, D>(deserializer: D) -> Result<Placement, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum PlacementValue {
+        AfterId(TableId),
+        Placement(Placement),
     }
+
+    Ok(match PlacementValue::deserialize(deserializer)? {
+        PlacementValue::AfterId(TableId::ABSENT) => Placement::First,
+        PlacementValue::AfterId(after_id) => Placement::After(after_id),
+        PlacementValue::Placement(placement) => placement,
+    })
+}
+
+/// Represents the single table operation, which can be applied as part of the batch.
+/// Variant names mirror the corresponding input messages, so that the JSON format stays the same.
+#[allow(clippy::enum_variant_names)]
+#[derive(Clone, Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "$type", rename_all = "snake_case")]
+pub enum TableOperation {
+    AddTable {
+        #[serde(alias = "after_id", deserialize_with = "deserialize_placement")]
+        placement: Placement,
+        table: TableToAdd,
+    },
+    UpdateTable {
+        table: Table,
+    },
+    PatchTable {
+        id: TableId,
+        changes: TableChanges,
+    },
+    MoveTable {
+        id: TableId,
+        #[serde(alias = "after_id", deserialize_with = "deserialize_placement")]
+        placement: Placement,
+    },
+    RemoveTable {
+        id: TableId,
+    },
 }
 
 #[derive(Clone, Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum UserType {
+    /// Can only view tables.
+    Viewer,
+    /// Can view and join tables.
     User,
+    /// Can also manage connected sessions and send announcements.
+    Operator,
+    /// Can also add, update and remove tables.
+    TableManager,
+    /// Can do everything, including creating and deleting lobbies.
     Admin,
 }
 
-#[derive(Debug, Hash, Eq, EnumDiscriminants, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, Default, Hash, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnnouncementSeverity {
+    #[default]
+    Info,
+    Warning,
+    Critical,
+}
+
+/// Represents the clients, which should receive the announcement.
+#[derive(Clone, Copy, Debug, Default, Hash, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnnouncementAudience {
+    /// All connected clients, including unauthenticated ones.
+    #[default]
+    All,
+    /// Only clients authenticated as users or admins.
+    Authenticated,
+}
+
+/// Represents the connected client session as seen by admins.
+#[derive(Clone, Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SessionInfo {
+    pub client_id: ClientId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<Username>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_type: Option<UserType>,
+    pub subscribed: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_address: Option<SocketAddr>,
+    pub connected_at: Timestamp,
+    pub messages_sent: u64,
+}
+
+/// Represents the version of the table resulting from the change to the lobby.
+#[derive(Clone, Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TableRevision {
+    /// The number of the change among all the changes to the lobby, `0` for the oldest retained version.
+    pub revision: u64,
+    pub timestamp: Timestamp,
+    /// The admin, who has made the change, absent for the oldest retained version, as its author is unknown.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<Username>,
+    /// The table after the change, absent once the table has been removed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub table: Option<Table>,
+}
+
+/// Represents the record of the administrative action, including the request and its outcome.
+#[derive(Clone, Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub timestamp: Timestamp,
+    /// The client, which has sent the request over WebSocket, absent for requests sent over HTTP.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<ClientId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<Username>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_address: Option<SocketAddr>,
+    pub request: Input,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outcome: Option<Output>,
+}
+
+#[derive(Clone, Debug, Hash, Eq, EnumDiscriminants, PartialEq, Serialize, Deserialize)]
 #[serde(tag = "$type", rename_all = "snake_case")]
-#[strum_discriminants(derive(EnumIter))]
+#[strum_discriminants(derive(EnumIter, Hash, Deserialize), serde(rename_all = "snake_case"))]
 pub enum Input {
-    Ping { seq: Seq },
-    Login { username: Username, password: Password },
-    SubscribeTables,
-    UnsubscribeTables,
-    AddTable { after_id: TableId, table: TableToAdd },
-    UpdateTable { table: Table },
-    RemoveTable { id: TableId },
+    Ping {
+        seq: Seq,
+    },
+    Login {
+        username: Username,
+        password: Password,
+    },
+    SubscribeTables {
+        #[serde(default, skip_serializing_if = "LobbyName::is_default")]
+        lobby: LobbyName,
+        #[serde(default)]
+        filter: TableFilter,
+        #[serde(default)]
+        page: Option<TablePage>,
+    },
+    UnsubscribeTables {
+        #[serde(default, skip_serializing_if = "LobbyName::is_default")]
+        lobby: LobbyName,
+    },
+    AddTable {
+        #[serde(default, skip_serializing_if = "LobbyName::is_default")]
+        lobby: LobbyName,
+        #[serde(alias = "after_id", deserialize_with = "deserialize_placement")]
+        placement: Placement,
+        table: TableToAdd,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        idempotency_key: Option<IdempotencyKey>,
+    },
+    UpdateTable {
+        #[serde(default, skip_serializing_if = "LobbyName::is_default")]
+        lobby: LobbyName,
+        table: Table,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        idempotency_key: Option<IdempotencyKey>,
+    },
+    PatchTable {
+        #[serde(default, skip_serializing_if = "LobbyName::is_default")]
+        lobby: LobbyName,
+        id: TableId,
+        changes: TableChanges,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        idempotency_key: Option<IdempotencyKey>,
+    },
+    MoveTable {
+        #[serde(default, skip_serializing_if = "LobbyName::is_default")]
+        lobby: LobbyName,
+        id: TableId,
+        #[serde(alias = "after_id", deserialize_with = "deserialize_placement")]
+        placement: Placement,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        idempotency_key: Option<IdempotencyKey>,
+    },
+    RemoveTable {
+        #[serde(default, skip_serializing_if = "LobbyName::is_default")]
+        lobby: LobbyName,
+        id: TableId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        idempotency_key: Option<IdempotencyKey>,
+    },
+    RestoreTable {
+        #[serde(default, skip_serializing_if = "LobbyName::is_default")]
+        lobby: LobbyName,
+        id: TableId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        idempotency_key: Option<IdempotencyKey>,
+    },
+    Batch {
+        #[serde(default, skip_serializing_if = "LobbyName::is_default")]
+        lobby: LobbyName,
+        operations: Vec<TableOperation>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        idempotency_key: Option<IdempotencyKey>,
+    },
+    CreateLobby {
+        name: LobbyName,
+    },
+    DeleteLobby {
+        name: LobbyName,
+    },
+    JoinTable {
+        #[serde(default, skip_serializing_if = "LobbyName::is_default")]
+        lobby: LobbyName,
+        id: TableId,
+    },
+    LeaveTable {
+        #[serde(default, skip_serializing_if = "LobbyName::is_default")]
+        lobby: LobbyName,
+        id: TableId,
+    },
+    JoinWaitlist {
+        #[serde(default, skip_serializing_if = "LobbyName::is_default")]
+        lobby: LobbyName,
+        id: TableId,
+    },
+    LeaveWaitlist {
+        #[serde(default, skip_serializing_if = "LobbyName::is_default")]
+        lobby: LobbyName,
+        id: TableId,
+    },
+    ListSessions,
+    KickSession {
+        client_id: ClientId,
+        reason: String,
+    },
+    Announce {
+        text: String,
+        #[serde(default)]
+        severity: AnnouncementSeverity,
+        #[serde(default)]
+        audience: AnnouncementAudience,
+    },
+    QueryAuditLog {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        limit: Option<usize>,
+    },
+    QueryTableHistory {
+        #[serde(default, skip_serializing_if = "LobbyName::is_default")]
+        lobby: LobbyName,
+        id: TableId,
+    },
+    QueryLobbySnapshot {
+        #[serde(default, skip_serializing_if = "LobbyName::is_default")]
+        lobby: LobbyName,
+        timestamp: Timestamp,
+    },
+}
+impl Input {
+    /// Returns the idempotency key of the change, if the client has given one.
+    pub fn idempotency_key(&self) -> Option<&IdempotencyKey> {
+        match self {
+            Input::AddTable { idempotency_key, .. }
+            | Input::UpdateTable { idempotency_key, .. }
+            | Input::PatchTable { idempotency_key, .. }
+            | Input::MoveTable { idempotency_key, .. }
+            | Input::RemoveTable { idempotency_key, .. }
+            | Input::RestoreTable { idempotency_key, .. }
+            | Input::Batch { idempotency_key, .. } => idempotency_key.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Returns the lobby, whose tables are changed by the input or whose snapshot is taken for the subscription,
+    /// so that the lobby should be locked while the input is processed.
+    pub fn locked_lobby(&self) -> Option<&LobbyName> {
+        match self {
+            Input::SubscribeTables { lobby, .. }
+            | Input::AddTable { lobby, .. }
+            | Input::UpdateTable { lobby, .. }
+            | Input::PatchTable { lobby, .. }
+            | Input::MoveTable { lobby, .. }
+            | Input::RemoveTable { lobby, .. }
+            | Input::RestoreTable { lobby, .. }
+            | Input::Batch { lobby, .. }
+            | Input::JoinTable { lobby, .. }
+            | Input::LeaveTable { lobby, .. }
+            | Input::JoinWaitlist { lobby, .. }
+            | Input::LeaveWaitlist { lobby, .. }
+            | Input::DeleteLobby { name: lobby } => Some(lobby),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Hash, Eq, EnumDiscriminants, PartialEq, Serialize, Deserialize)]
 #[serde(tag = "$type", rename_all = "snake_case")]
 #[strum_discriminants(derive(EnumIter))]
 pub enum Output {
-    LoginSuccessful { user_type: UserType },
+    LoginSuccessful {
+        user_type: UserType,
+    },
     LoginFailed,
-    Pong { seq: Seq },
-    TableList { tables: Vec<Table> },
-    TableAdded { after_id: TableId, table: Table },
-    TableUpdated { table: Table },
-    TableRemoved { id: TableId },
-    TableAddFailed,
-    TableUpdateFailed { id: TableId },
-    TableRemoveFailed { id: TableId },
+    Pong {
+        seq: Seq,
+    },
+    TableList {
+        #[serde(default, skip_serializing_if = "LobbyName::is_default")]
+        lobby: LobbyName,
+        /// Tables shared with the snapshot of the lobby, so that they are serialized without being copied.
+        tables: Arc<[Arc<Table>]>,
+    },
+    TableAdded {
+        #[serde(default, skip_serializing_if = "LobbyName::is_default")]
+        lobby: LobbyName,
+        after_id: TableId,
+        table: Table,
+    },
+    TableUpdated {
+        #[serde(default, skip_serializing_if = "LobbyName::is_default")]
+        lobby: LobbyName,
+        table: Table,
+    },
+    TablePatched {
+        #[serde(default, skip_serializing_if = "LobbyName::is_default")]
+        lobby: LobbyName,
+        id: TableId,
+        changes: TableChanges,
+    },
+    TableMoved {
+        #[serde(default, skip_serializing_if = "LobbyName::is_default")]
+        lobby: LobbyName,
+        id: TableId,
+        after_id: TableId,
+    },
+    TableRemoved {
+        #[serde(default, skip_serializing_if = "LobbyName::is_default")]
+        lobby: LobbyName,
+        id: TableId,
+    },
+    BatchApplied {
+        #[serde(default, skip_serializing_if = "LobbyName::is_default")]
+        lobby: LobbyName,
+        outputs: Vec<Output>,
+    },
+    LobbyCreated {
+        name: LobbyName,
+    },
+    LobbyDeleted {
+        name: LobbyName,
+    },
+    TableJoined {
+        #[serde(default, skip_serializing_if = "LobbyName::is_default")]
+        lobby: LobbyName,
+        id: TableId,
+    },
+    TableLeft {
+        #[serde(default, skip_serializing_if = "LobbyName::is_default")]
+        lobby: LobbyName,
+        id: TableId,
+    },
+    WaitlistPosition {
+        #[serde(default, skip_serializing_if = "LobbyName::is_default")]
+        lobby: LobbyName,
+        id: TableId,
+        position: usize,
+    },
+    WaitlistLeft {
+        #[serde(default, skip_serializing_if = "LobbyName::is_default")]
+        lobby: LobbyName,
+        id: TableId,
+    },
+    SessionList {
+        sessions: Vec<SessionInfo>,
+    },
+    SessionKicked {
+        client_id: ClientId,
+    },
+    Kicked {
+        reason: String,
+    },
+    Announcement {
+        text: String,
+        severity: AnnouncementSeverity,
+    },
+    Announced {
+        recipients: usize,
+    },
+    AuditLog {
+        entries: Vec<AuditEntry>,
+    },
+    TableHistory {
+        #[serde(default, skip_serializing_if = "LobbyName::is_default")]
+        lobby: LobbyName,
+        id: TableId,
+        revisions: Vec<TableRevision>,
+    },
+    LobbySnapshot {
+        #[serde(default, skip_serializing_if = "LobbyName::is_default")]
+        lobby: LobbyName,
+        timestamp: Timestamp,
+        tables: Vec<Table>,
+    },
+    SubscribeTablesFailed {
+        #[serde(default, skip_serializing_if = "LobbyName::is_default")]
+        lobby: LobbyName,
+    },
+    TableAddFailed {
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        errors: Vec<FieldError>,
+    },
+    TableUpdateFailed {
+        id: TableId,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        errors: Vec<FieldError>,
+    },
+    TablePatchFailed {
+        id: TableId,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        errors: Vec<FieldError>,
+    },
+    TableMoveFailed {
+        id: TableId,
+    },
+    TableRemoveFailed {
+        id: TableId,
+    },
+    TableRestoreFailed {
+        id: TableId,
+    },
+    BatchFailed {
+        index: usize,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        errors: Vec<FieldError>,
+    },
+    LobbyCreateFailed {
+        name: LobbyName,
+    },
+    LobbyDeleteFailed {
+        name: LobbyName,
+    },
+    TableJoinFailed {
+        id: TableId,
+    },
+    TableLeaveFailed {
+        id: TableId,
+    },
+    WaitlistJoinFailed {
+        id: TableId,
+    },
+    WaitlistLeaveFailed {
+        id: TableId,
+    },
+    SessionKickFailed {
+        client_id: ClientId,
+    },
+    TableHistoryFailed {
+        id: TableId,
+    },
+    LobbySnapshotFailed {
+        timestamp: Timestamp,
+    },
+    IdempotencyKeyMismatch {
+        idempotency_key: IdempotencyKey,
+    },
     NotAuthorized,
     NotAuthenticated,
     InvalidMessage,
 }
+impl Output {
+    /// Returns ids of tables affected by this output message.
+    pub fn table_ids(&self) -> Vec<TableId> {
+        match self {
+            Output::TableAdded { table, .. } | Output::TableUpdated { table, .. } => vec![table.id],
+            Output::TablePatched { id, .. } | Output::TableMoved { id, .. } | Output::TableRemoved { id, .. } => {
+                vec![*id]
+            }
+            Output::BatchApplied { outputs, .. } => outputs.iter().flat_map(Output::table_ids).collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Returns the name of the lobby, to which subscribers this output message should be broadcasted.
+    pub fn lobby(&self) -> Option<&LobbyName> {
+        match self {
+            Output::TableList { lobby, .. }
+            | Output::TableAdded { lobby, .. }
+            | Output::TableUpdated { lobby, .. }
+            | Output::TablePatched { lobby, .. }
+            | Output::TableMoved { lobby, .. }
+            | Output::TableRemoved { lobby, .. }
+            | Output::BatchApplied { lobby, .. }
+            | Output::LobbyDeleted { name: lobby } => Some(lobby),
+            _ => None,
+        }
+    }
+}
 
 #[cfg(test)]
 mod tests {
@@ -122,7 +912,9 @@ mod tests {
     use serde_json::{json, Value};
     use strum::IntoEnumIterator;
 
-    use super::{test_data, Input, InputDiscriminants, Output, OutputDiscriminants};
+    use super::{
+        test_data, Input, InputDiscriminants, Output, OutputDiscriminants, Placement, TableChanges, TableId, Timestamp,
+    };
 
     #[test]
     fn provide_correct_input_decoders() {
@@ -149,29 +941,67 @@ mod tests {
                     }"#,
                     test_data::login(),
                 ),
-                InputDiscriminants::SubscribeTables => verify(
-                    r#"{
-                        "$type": "subscribe_tables"
-                    }"#,
-                    test_data::subscribe_tables(),
-                ),
+                InputDiscriminants::SubscribeTables => {
+                    verify(
+                        r#"{
+                            "$type": "subscribe_tables"
+                        }"#,
+                        test_data::subscribe_tables(),
+                    );
+                    verify(
+                        r#"{
+                            "$type": "subscribe_tables",
+                            "filter": {
+                              "name_prefix": "Foo",
+                              "participants_max": 5
+                            },
+                            "page": {
+                              "offset": 0,
+                              "limit": 10
+                            }
+                        }"#,
+                        test_data::subscribe_tables_filtered(),
+                    );
+                }
                 InputDiscriminants::UnsubscribeTables => verify(
                     r#"{
                         "$type": "unsubscribe_tables"
                     }"#,
                     test_data::unsubscribe_tables(),
                 ),
-                InputDiscriminants::AddTable => verify(
-                    r#"{
-                        "$type": "add_table",
-                        "after_id": -1,
-                        "table": {
-                          "name": "Foo Fighters",
-                          "participants": 4
-                        }
-                    }"#,
-                    test_data::add_table(),
-                ),
+                InputDiscriminants::AddTable => {
+                    verify(
+                        r#"{
+                            "$type": "add_table",
+                            "placement": "first",
+                            "table": {
+                              "name": "Foo Fighters"
+                            }
+                        }"#,
+                        test_data::add_table(),
+                    );
+                    verify(
+                        r#"{
+                            "$type": "add_table",
+                            "after_id": -1,
+                            "table": {
+                              "name": "Foo Fighters"
+                            }
+                        }"#,
+                        test_data::add_table(),
+                    );
+                    verify(
+                        r#"{
+                            "$type": "add_table",
+                            "placement": "first",
+                            "idempotency_key": "add-foo-fighters",
+                            "table": {
+                              "name": "Foo Fighters"
+                            }
+                        }"#,
+                        test_data::add_table_idempotent(),
+                    );
+                }
                 InputDiscriminants::UpdateTable => verify(
                     r#"{
                         "$type": "update_table",
@@ -183,6 +1013,36 @@ mod tests {
                     }"#,
                     test_data::update_table(),
                 ),
+                InputDiscriminants::PatchTable => verify(
+                    r#"{
+                        "$type": "patch_table",
+                        "id": 3,
+                        "changes": {
+                          "capacity": 5
+                        }
+                    }"#,
+                    test_data::patch_table(),
+                ),
+                InputDiscriminants::MoveTable => {
+                    verify(
+                        r#"{
+                            "$type": "move_table",
+                            "id": 3,
+                            "placement": {
+                              "after": 1
+                            }
+                        }"#,
+                        test_data::move_table(),
+                    );
+                    verify(
+                        r#"{
+                            "$type": "move_table",
+                            "id": 3,
+                            "after_id": 1
+                        }"#,
+                        test_data::move_table(),
+                    );
+                }
                 InputDiscriminants::RemoveTable => verify(
                     r#"{
                         "$type": "remove_table",
@@ -190,6 +1050,126 @@ mod tests {
                     }"#,
                     test_data::remove_table(),
                 ),
+                InputDiscriminants::RestoreTable => verify(
+                    r#"{
+                        "$type": "restore_table",
+                        "id": 3
+                    }"#,
+                    test_data::restore_table(),
+                ),
+                InputDiscriminants::Batch => verify(
+                    r#"{
+                        "$type": "batch",
+                        "operations": [
+                          {
+                            "$type": "add_table",
+                            "after_id": -1,
+                            "table": {
+                              "name": "Foo Fighters"
+                            }
+                          }, {
+                            "$type": "remove_table",
+                            "id": 3
+                          }
+                        ]
+                    }"#,
+                    test_data::batch(),
+                ),
+                InputDiscriminants::CreateLobby => verify(
+                    r#"{
+                        "$type": "create_lobby",
+                        "name": "poker"
+                    }"#,
+                    test_data::create_lobby(),
+                ),
+                InputDiscriminants::DeleteLobby => verify(
+                    r#"{
+                        "$type": "delete_lobby",
+                        "name": "poker"
+                    }"#,
+                    test_data::delete_lobby(),
+                ),
+                InputDiscriminants::JoinTable => verify(
+                    r#"{
+                        "$type": "join_table",
+                        "id": 3
+                    }"#,
+                    test_data::join_table(),
+                ),
+                InputDiscriminants::LeaveTable => verify(
+                    r#"{
+                        "$type": "leave_table",
+                        "id": 3
+                    }"#,
+                    test_data::leave_table(),
+                ),
+                InputDiscriminants::JoinWaitlist => verify(
+                    r#"{
+                        "$type": "join_waitlist",
+                        "id": 3
+                    }"#,
+                    test_data::join_waitlist(),
+                ),
+                InputDiscriminants::LeaveWaitlist => verify(
+                    r#"{
+                        "$type": "leave_waitlist",
+                        "id": 3
+                    }"#,
+                    test_data::leave_waitlist(),
+                ),
+                InputDiscriminants::ListSessions => verify(
+                    r#"{
+                        "$type": "list_sessions"
+                    }"#,
+                    test_data::list_sessions(),
+                ),
+                InputDiscriminants::KickSession => verify(
+                    r#"{
+                        "$type": "kick_session",
+                        "client_id": 7,
+                        "reason": "Spamming"
+                    }"#,
+                    test_data::kick_session(),
+                ),
+                InputDiscriminants::Announce => {
+                    verify(
+                        r#"{
+                            "$type": "announce",
+                            "text": "Maintenance starts in 10 minutes"
+                        }"#,
+                        test_data::announce(),
+                    );
+                    verify(
+                        r#"{
+                            "$type": "announce",
+                            "text": "Maintenance starts in 10 minutes",
+                            "severity": "warning",
+                            "audience": "authenticated"
+                        }"#,
+                        test_data::announce_authenticated(),
+                    );
+                }
+                InputDiscriminants::QueryAuditLog => verify(
+                    r#"{
+                        "$type": "query_audit_log",
+                        "limit": 50
+                    }"#,
+                    test_data::query_audit_log(),
+                ),
+                InputDiscriminants::QueryTableHistory => verify(
+                    r#"{
+                        "$type": "query_table_history",
+                        "id": 3
+                    }"#,
+                    test_data::query_table_history(),
+                ),
+                InputDiscriminants::QueryLobbySnapshot => verify(
+                    r#"{
+                        "$type": "query_lobby_snapshot",
+                        "timestamp": 1700000000
+                    }"#,
+                    test_data::query_lobby_snapshot(),
+                ),
             }
         }
     }
@@ -263,17 +1243,51 @@ mod tests {
                         }
                     }),
                 ),
-                OutputDiscriminants::TableUpdated => verify(
-                    test_data::table_updated(),
+                OutputDiscriminants::TableUpdated => {
+                    verify(
+                        test_data::table_updated(),
+                        json!({
+                            "$type": "table_updated",
+                            "table": {
+                                "id": 3,
+                                "name": "Foo Fighters",
+                                "participants": 4
+                            }
+                        }),
+                    );
+                    verify(
+                        test_data::table_updated_scheduled(),
+                        json!({
+                            "$type": "table_updated",
+                            "table": {
+                                "id": 3,
+                                "name": "Foo Fighters",
+                                "participants": 4,
+                                "opens_at": 1700000000,
+                                "closes_at": 1700003600,
+                                "status": "scheduled"
+                            }
+                        }),
+                    );
+                }
+                OutputDiscriminants::TablePatched => verify(
+                    test_data::table_patched(),
                     json!({
-                        "$type": "table_updated",
-                        "table": {
-                            "id": 3,
-                            "name": "Foo Fighters",
-                            "participants": 4
+                        "$type": "table_patched",
+                        "id": 3,
+                        "changes": {
+                            "capacity": 5
                         }
                     }),
                 ),
+                OutputDiscriminants::TableMoved => verify(
+                    test_data::table_moved(),
+                    json!({
+                        "$type": "table_moved",
+                        "id": 3,
+                        "after_id": 1
+                    }),
+                ),
                 OutputDiscriminants::TableRemoved => verify(
                     test_data::table_removed(),
                     json!({
@@ -281,27 +1295,324 @@ mod tests {
                         "id": 3
                     }),
                 ),
-                OutputDiscriminants::TableAddFailed => verify(
-                    test_data::table_add_failed(),
+                OutputDiscriminants::BatchApplied => verify(
+                    test_data::batch_applied(),
                     json!({
-                        "$type": "table_add_failed"
+                        "$type": "batch_applied",
+                        "outputs": [
+                          {
+                            "$type": "table_added",
+                            "after_id": -1,
+                            "table": {
+                                "id": 3,
+                                "name": "Foo Fighters",
+                                "participants": 4
+                            }
+                          }, {
+                            "$type": "table_removed",
+                            "id": 3
+                          }
+                        ]
                     }),
                 ),
-                OutputDiscriminants::TableUpdateFailed => verify(
-                    test_data::table_update_failed(),
+                OutputDiscriminants::LobbyCreated => verify(
+                    test_data::lobby_created(),
                     json!({
-                        "$type": "table_update_failed",
-                        "id": 99999
+                        "$type": "lobby_created",
+                        "name": "poker"
                     }),
                 ),
-                OutputDiscriminants::TableRemoveFailed => verify(
-                    test_data::table_remove_failed(),
+                OutputDiscriminants::LobbyDeleted => verify(
+                    test_data::lobby_deleted(),
                     json!({
-                        "$type": "table_remove_failed",
-                        "id": 99999
+                        "$type": "lobby_deleted",
+                        "name": "poker"
                     }),
                 ),
-                OutputDiscriminants::NotAuthorized => verify(
+                OutputDiscriminants::TableJoined => verify(
+                    test_data::table_joined(),
+                    json!({
+                        "$type": "table_joined",
+                        "id": 3
+                    }),
+                ),
+                OutputDiscriminants::TableLeft => verify(
+                    test_data::table_left(),
+                    json!({
+                        "$type": "table_left",
+                        "id": 3
+                    }),
+                ),
+                OutputDiscriminants::WaitlistPosition => verify(
+                    test_data::waitlist_position(),
+                    json!({
+                        "$type": "waitlist_position",
+                        "id": 3,
+                        "position": 2
+                    }),
+                ),
+                OutputDiscriminants::WaitlistLeft => verify(
+                    test_data::waitlist_left(),
+                    json!({
+                        "$type": "waitlist_left",
+                        "id": 3
+                    }),
+                ),
+                OutputDiscriminants::SessionList => verify(
+                    test_data::session_list(),
+                    json!({
+                        "$type": "session_list",
+                        "sessions": [
+                          {
+                            "client_id": 7,
+                            "username": "user",
+                            "user_type": "user",
+                            "subscribed": true,
+                            "remote_address": "127.0.0.1:54321",
+                            "connected_at": 1700000000,
+                            "messages_sent": 12
+                          },
+                          {
+                            "client_id": 8,
+                            "subscribed": false,
+                            "connected_at": 1700000060,
+                            "messages_sent": 0
+                          }
+                        ]
+                    }),
+                ),
+                OutputDiscriminants::SessionKicked => verify(
+                    test_data::session_kicked(),
+                    json!({
+                        "$type": "session_kicked",
+                        "client_id": 7
+                    }),
+                ),
+                OutputDiscriminants::Kicked => verify(
+                    test_data::kicked(),
+                    json!({
+                        "$type": "kicked",
+                        "reason": "Spamming"
+                    }),
+                ),
+                OutputDiscriminants::Announcement => verify(
+                    test_data::announcement(),
+                    json!({
+                        "$type": "announcement",
+                        "text": "Maintenance starts in 10 minutes",
+                        "severity": "warning"
+                    }),
+                ),
+                OutputDiscriminants::Announced => verify(
+                    test_data::announced(),
+                    json!({
+                        "$type": "announced",
+                        "recipients": 2
+                    }),
+                ),
+                OutputDiscriminants::AuditLog => verify(
+                    test_data::audit_log(),
+                    json!({
+                        "$type": "audit_log",
+                        "entries": [
+                          {
+                            "timestamp": 1700000000,
+                            "client_id": 7,
+                            "username": "admin",
+                            "remote_address": "127.0.0.1:54321",
+                            "request": {
+                              "$type": "remove_table",
+                              "id": 3
+                            },
+                            "outcome": {
+                              "$type": "table_removed",
+                              "id": 3
+                            }
+                          }
+                        ]
+                    }),
+                ),
+                OutputDiscriminants::TableHistory => verify(
+                    test_data::table_history(),
+                    json!({
+                        "$type": "table_history",
+                        "id": 3,
+                        "revisions": [
+                          {
+                            "revision": 1,
+                            "timestamp": 1700000000,
+                            "username": "admin",
+                            "table": {
+                              "id": 3,
+                              "name": "Foo Fighters",
+                              "participants": 4
+                            }
+                          }, {
+                            "revision": 2,
+                            "timestamp": 1700000060,
+                            "username": "admin"
+                          }
+                        ]
+                    }),
+                ),
+                OutputDiscriminants::LobbySnapshot => verify(
+                    test_data::lobby_snapshot(),
+                    json!({
+                        "$type": "lobby_snapshot",
+                        "timestamp": 1700000000,
+                        "tables": [
+                          {
+                            "id": 1,
+                            "name": "James Bond",
+                            "participants": 7
+                          }, {
+                            "id": 3,
+                            "name": "Foo Fighters",
+                            "participants": 4
+                          }
+                        ]
+                    }),
+                ),
+                OutputDiscriminants::SubscribeTablesFailed => verify(
+                    test_data::subscribe_tables_failed(),
+                    json!({
+                        "$type": "subscribe_tables_failed",
+                        "lobby": "poker"
+                    }),
+                ),
+                OutputDiscriminants::TableAddFailed => {
+                    verify(
+                        test_data::table_add_failed(),
+                        json!({
+                            "$type": "table_add_failed"
+                        }),
+                    );
+                    verify(
+                        test_data::table_add_failed_invalid(),
+                        json!({
+                            "$type": "table_add_failed",
+                            "errors": [
+                              {
+                                "field": "name",
+                                "message": "Name should be unique"
+                              }
+                            ]
+                        }),
+                    );
+                }
+                OutputDiscriminants::TableUpdateFailed => verify(
+                    test_data::table_update_failed(),
+                    json!({
+                        "$type": "table_update_failed",
+                        "id": 99999
+                    }),
+                ),
+                OutputDiscriminants::TablePatchFailed => verify(
+                    test_data::table_patch_failed(),
+                    json!({
+                        "$type": "table_patch_failed",
+                        "id": 99999
+                    }),
+                ),
+                OutputDiscriminants::TableMoveFailed => verify(
+                    test_data::table_move_failed(),
+                    json!({
+                        "$type": "table_move_failed",
+                        "id": 99999
+                    }),
+                ),
+                OutputDiscriminants::TableRemoveFailed => verify(
+                    test_data::table_remove_failed(),
+                    json!({
+                        "$type": "table_remove_failed",
+                        "id": 99999
+                    }),
+                ),
+                OutputDiscriminants::TableRestoreFailed => verify(
+                    test_data::table_restore_failed(),
+                    json!({
+                        "$type": "table_restore_failed",
+                        "id": 99999
+                    }),
+                ),
+                OutputDiscriminants::BatchFailed => verify(
+                    test_data::batch_failed(),
+                    json!({
+                        "$type": "batch_failed",
+                        "index": 1
+                    }),
+                ),
+                OutputDiscriminants::LobbyCreateFailed => verify(
+                    test_data::lobby_create_failed(),
+                    json!({
+                        "$type": "lobby_create_failed",
+                        "name": "poker"
+                    }),
+                ),
+                OutputDiscriminants::LobbyDeleteFailed => verify(
+                    test_data::lobby_delete_failed(),
+                    json!({
+                        "$type": "lobby_delete_failed",
+                        "name": "poker"
+                    }),
+                ),
+                OutputDiscriminants::TableJoinFailed => verify(
+                    test_data::table_join_failed(),
+                    json!({
+                        "$type": "table_join_failed",
+                        "id": 99999
+                    }),
+                ),
+                OutputDiscriminants::TableLeaveFailed => verify(
+                    test_data::table_leave_failed(),
+                    json!({
+                        "$type": "table_leave_failed",
+                        "id": 99999
+                    }),
+                ),
+                OutputDiscriminants::WaitlistJoinFailed => verify(
+                    test_data::waitlist_join_failed(),
+                    json!({
+                        "$type": "waitlist_join_failed",
+                        "id": 99999
+                    }),
+                ),
+                OutputDiscriminants::WaitlistLeaveFailed => verify(
+                    test_data::waitlist_leave_failed(),
+                    json!({
+                        "$type": "waitlist_leave_failed",
+                        "id": 99999
+                    }),
+                ),
+                OutputDiscriminants::SessionKickFailed => verify(
+                    test_data::session_kick_failed(),
+                    json!({
+                        "$type": "session_kick_failed",
+                        "client_id": 99999
+                    }),
+                ),
+                OutputDiscriminants::TableHistoryFailed => verify(
+                    test_data::table_history_failed(),
+                    json!({
+                        "$type": "table_history_failed",
+                        "id": 99999
+                    }),
+                ),
+                OutputDiscriminants::LobbySnapshotFailed => verify(
+                    test_data::lobby_snapshot_failed(),
+                    json!({
+                        "$type": "lobby_snapshot_failed",
+                        "timestamp": 1600000000
+                    }),
+                ),
+                OutputDiscriminants::IdempotencyKeyMismatch => verify(
+                    test_data::idempotency_key_mismatch(),
+                    json!({
+                        "$type": "idempotency_key_mismatch",
+                        "idempotency_key": "add-foo-fighters"
+                    }),
+                ),
+                OutputDiscriminants::NotAuthorized => verify(
                     test_data::not_authorized(),
                     json!({
                         "$type": "not_authorized"
@@ -322,6 +1633,84 @@ mod tests {
             }
         }
     }
+    #[test]
+    fn provide_placement_decoder() {
+        let placements: Vec<Placement> = [
+            r#""first""#,
+            r#""last""#,
+            r#"{"after": 1}"#,
+            r#"{"before": 2}"#,
+            r#"{"index": 3}"#,
+        ]
+        .into_iter()
+        .map(|str| serde_json::from_str(str).expect("Failed to deserialize placement"))
+        .collect();
+
+        // then
+        let expected_placements = vec![
+            Placement::First,
+            Placement::Last,
+            Placement::After(TableId(1)),
+            Placement::Before(TableId(2)),
+            Placement::Index(3),
+        ];
+        assert_eq!(placements, expected_placements);
+    }
+
+    #[test]
+    fn provide_table_id_codec_for_numbers_and_ulids() {
+        let numeric_id = TableId::new(12345);
+        let ulid_id = TableId::new(0x0188_E5F5_3A00_7E1C_9B3C_2F1D_0A6B_44F1);
+
+        // when
+        let numeric_value = serde_json::to_value(numeric_id).expect("Numeric id should be serialized");
+        let ulid_value = serde_json::to_value(ulid_id).expect("ULID should be serialized");
+
+        // then
+        assert_eq!(numeric_value, json!(12345));
+        assert_eq!(ulid_value, json!("01H3JZAEG0FRE9PF1F3M56PH7H"));
+        assert_eq!(serde_json::from_value::<TableId>(numeric_value).ok(), Some(numeric_id));
+        assert_eq!(serde_json::from_value::<TableId>(ulid_value).ok(), Some(ulid_id));
+        assert_eq!(
+            serde_json::from_value::<TableId>(json!("01h3jzaeg0fre9pf1f3m56ph7h")).ok(),
+            Some(ulid_id),
+            "ULID should be case insensitive"
+        );
+        for invalid_value in [
+            json!("01H3JZAEG0"),
+            json!("81H3JZAEG0FRE9PF1F3M56PH7H"),
+            json!("0000000000000000000000000U"),
+        ] {
+            assert!(
+                serde_json::from_value::<TableId>(invalid_value.clone()).is_err(),
+                "Table id {} should be invalid",
+                invalid_value
+            );
+        }
+    }
+
+    #[test]
+    fn provide_table_changes_codec_telling_cleared_fields_from_absent_ones() {
+        let value = json!({
+            "capacity": null,
+            "opens_at": null,
+            "closes_at": 1600000000
+        });
+
+        // when
+        let changes: TableChanges = serde_json::from_value(value.clone()).expect("Changes should be deserialized");
+
+        // then
+        let expected_changes = TableChanges {
+            capacity: Some(None),
+            opens_at: Some(None),
+            closes_at: Some(Some(Timestamp(1600000000))),
+            ..TableChanges::default()
+        };
+        assert_eq!(changes, expected_changes);
+        assert_eq!(serde_json::to_value(&changes).ok(), Some(value));
+        assert_eq!(serde_json::from_value::<TableChanges>(json!({})).ok(), Some(TableChanges::default()));
+    }
 }
 
 #[cfg(test)]
@@ -334,12 +1723,33 @@ pub mod test_data {
     // Common
 
     pub const TABLE_ID_INVALID: TableId = TableId(99999);
+    pub const CLIENT_ID_INVALID: ClientId = ClientId(99999);
+
+    pub fn username_user() -> Username {
+        Username(String::from("user"))
+    }
+
+    pub fn username_admin() -> Username {
+        Username(String::from("admin"))
+    }
+
+    pub fn timestamp(seconds: u64) -> Timestamp {
+        Timestamp(seconds)
+    }
+
+    pub fn lobby_name_poker() -> LobbyName {
+        LobbyName(String::from("poker"))
+    }
 
     pub fn table_james_bond() -> Table {
         Table {
             id: TableId(1),
             name: TableName(String::from("James Bond")),
             participants: 7,
+            capacity: None,
+            opens_at: None,
+            closes_at: None,
+            status: TableStatus::Open,
         }
     }
 
@@ -348,6 +1758,10 @@ pub mod test_data {
             id: TableId(2),
             name: TableName(String::from("Mission Impossible")),
             participants: 9,
+            capacity: None,
+            opens_at: None,
+            closes_at: None,
+            status: TableStatus::Open,
         }
     }
 
@@ -356,13 +1770,41 @@ pub mod test_data {
             id: TableId(3),
             name: TableName(String::from("Foo Fighters")),
             participants: 4,
+            capacity: None,
+            opens_at: None,
+            closes_at: None,
+            status: TableStatus::Open,
         }
     }
 
     pub fn table_to_add_foo_fighters() -> TableToAdd {
         TableToAdd {
             name: TableName(String::from("Foo Fighters")),
-            participants: 4,
+            capacity: None,
+            opens_at: None,
+            closes_at: None,
+        }
+    }
+
+    pub fn table_changes_capacity() -> TableChanges {
+        TableChanges {
+            name: None,
+            capacity: Some(Some(5)),
+            opens_at: None,
+            closes_at: None,
+            participants: None,
+            status: None,
+        }
+    }
+
+    pub fn audit_entry() -> AuditEntry {
+        AuditEntry {
+            timestamp: Timestamp(1700000000),
+            client_id: Some(ClientId(7)),
+            username: Some(username_admin()),
+            remote_address: Some(SocketAddr::from(([127, 0, 0, 1], 54321))),
+            request: remove_table(),
+            outcome: Some(table_removed()),
         }
     }
 
@@ -370,7 +1812,7 @@ pub mod test_data {
 
     pub fn login() -> Input {
         Login {
-            username: Username(String::from("user")),
+            username: username_user(),
             password: Password(String::from("pass")),
         }
     }
@@ -380,28 +1822,188 @@ pub mod test_data {
     }
 
     pub fn subscribe_tables() -> Input {
-        SubscribeTables
+        SubscribeTables {
+            lobby: LobbyName::default(),
+            filter: TableFilter::default(),
+            page: None,
+        }
+    }
+
+    pub fn subscribe_tables_filtered() -> Input {
+        SubscribeTables {
+            lobby: LobbyName::default(),
+            filter: TableFilter {
+                name_prefix: Some(String::from("Foo")),
+                participants_min: None,
+                participants_max: Some(5),
+            },
+            page: Some(TablePage { offset: 0, limit: 10 }),
+        }
     }
 
     pub fn unsubscribe_tables() -> Input {
-        UnsubscribeTables
+        UnsubscribeTables {
+            lobby: LobbyName::default(),
+        }
     }
 
     pub fn add_table() -> Input {
         AddTable {
-            after_id: TableId::ABSENT,
+            lobby: LobbyName::default(),
+            placement: Placement::First,
+            table: table_to_add_foo_fighters(),
+            idempotency_key: None,
+        }
+    }
+
+    pub fn add_table_idempotent() -> Input {
+        AddTable {
+            lobby: LobbyName::default(),
+            placement: Placement::First,
             table: table_to_add_foo_fighters(),
+            idempotency_key: Some(IdempotencyKey(String::from("add-foo-fighters"))),
         }
     }
 
     pub fn update_table() -> Input {
         UpdateTable {
+            lobby: LobbyName::default(),
             table: table_foo_fighters(),
+            idempotency_key: None,
+        }
+    }
+
+    pub fn patch_table() -> Input {
+        PatchTable {
+            lobby: LobbyName::default(),
+            id: TableId(3),
+            changes: table_changes_capacity(),
+            idempotency_key: None,
+        }
+    }
+
+    pub fn move_table() -> Input {
+        MoveTable {
+            lobby: LobbyName::default(),
+            id: TableId(3),
+            placement: Placement::After(TableId(1)),
+            idempotency_key: None,
         }
     }
 
     pub fn remove_table() -> Input {
-        RemoveTable { id: TableId(3) }
+        RemoveTable {
+            lobby: LobbyName::default(),
+            id: TableId(3),
+            idempotency_key: None,
+        }
+    }
+
+    pub fn restore_table() -> Input {
+        RestoreTable {
+            lobby: LobbyName::default(),
+            id: TableId(3),
+            idempotency_key: None,
+        }
+    }
+
+    pub fn batch() -> Input {
+        Batch {
+            lobby: LobbyName::default(),
+            operations: vec![
+                TableOperation::AddTable {
+                    placement: Placement::First,
+                    table: table_to_add_foo_fighters(),
+                },
+                TableOperation::RemoveTable { id: TableId(3) },
+            ],
+            idempotency_key: None,
+        }
+    }
+
+    pub fn create_lobby() -> Input {
+        CreateLobby {
+            name: lobby_name_poker(),
+        }
+    }
+
+    pub fn delete_lobby() -> Input {
+        DeleteLobby {
+            name: lobby_name_poker(),
+        }
+    }
+
+    pub fn join_table() -> Input {
+        JoinTable {
+            lobby: LobbyName::default(),
+            id: TableId(3),
+        }
+    }
+
+    pub fn leave_table() -> Input {
+        LeaveTable {
+            lobby: LobbyName::default(),
+            id: TableId(3),
+        }
+    }
+
+    pub fn join_waitlist() -> Input {
+        JoinWaitlist {
+            lobby: LobbyName::default(),
+            id: TableId(3),
+        }
+    }
+
+    pub fn leave_waitlist() -> Input {
+        LeaveWaitlist {
+            lobby: LobbyName::default(),
+            id: TableId(3),
+        }
+    }
+
+    pub fn list_sessions() -> Input {
+        ListSessions
+    }
+
+    pub fn kick_session() -> Input {
+        KickSession {
+            client_id: ClientId(7),
+            reason: String::from("Spamming"),
+        }
+    }
+
+    pub fn announce() -> Input {
+        Announce {
+            text: String::from("Maintenance starts in 10 minutes"),
+            severity: AnnouncementSeverity::Info,
+            audience: AnnouncementAudience::All,
+        }
+    }
+
+    pub fn announce_authenticated() -> Input {
+        Announce {
+            text: String::from("Maintenance starts in 10 minutes"),
+            severity: AnnouncementSeverity::Warning,
+            audience: AnnouncementAudience::Authenticated,
+        }
+    }
+
+    pub fn query_audit_log() -> Input {
+        QueryAuditLog { limit: Some(50) }
+    }
+
+    pub fn query_table_history() -> Input {
+        QueryTableHistory {
+            lobby: LobbyName::default(),
+            id: TableId(3),
+        }
+    }
+
+    pub fn query_lobby_snapshot() -> Input {
+        QueryLobbySnapshot {
+            lobby: LobbyName::default(),
+            timestamp: Timestamp(1700000000),
+        }
     }
 
     // Output
@@ -428,12 +2030,14 @@ pub mod test_data {
 
     pub fn table_list() -> Output {
         TableList {
-            tables: vec![table_james_bond(), table_mission_impossible()],
+            lobby: LobbyName::default(),
+            tables: Arc::from([Arc::new(table_james_bond()), Arc::new(table_mission_impossible())]),
         }
     }
 
     pub fn table_added() -> Output {
         TableAdded {
+            lobby: LobbyName::default(),
             after_id: TableId::ABSENT,
             table: table_foo_fighters(),
         }
@@ -441,26 +2045,274 @@ pub mod test_data {
 
     pub fn table_updated() -> Output {
         TableUpdated {
+            lobby: LobbyName::default(),
             table: table_foo_fighters(),
         }
     }
 
+    pub fn table_updated_scheduled() -> Output {
+        TableUpdated {
+            lobby: LobbyName::default(),
+            table: Table {
+                opens_at: Some(Timestamp(1700000000)),
+                closes_at: Some(Timestamp(1700003600)),
+                status: TableStatus::Scheduled,
+                ..table_foo_fighters()
+            },
+        }
+    }
+
+    pub fn table_patched() -> Output {
+        TablePatched {
+            lobby: LobbyName::default(),
+            id: TableId(3),
+            changes: table_changes_capacity(),
+        }
+    }
+
+    pub fn table_moved() -> Output {
+        TableMoved {
+            lobby: LobbyName::default(),
+            id: TableId(3),
+            after_id: TableId(1),
+        }
+    }
+
     pub fn table_removed() -> Output {
-        TableRemoved { id: TableId(3) }
+        TableRemoved {
+            lobby: LobbyName::default(),
+            id: TableId(3),
+        }
+    }
+
+    pub fn batch_applied() -> Output {
+        BatchApplied {
+            lobby: LobbyName::default(),
+            outputs: vec![table_added(), table_removed()],
+        }
+    }
+
+    pub fn lobby_created() -> Output {
+        LobbyCreated {
+            name: lobby_name_poker(),
+        }
+    }
+
+    pub fn lobby_deleted() -> Output {
+        LobbyDeleted {
+            name: lobby_name_poker(),
+        }
+    }
+
+    pub fn table_joined() -> Output {
+        TableJoined {
+            lobby: LobbyName::default(),
+            id: TableId(3),
+        }
+    }
+
+    pub fn table_left() -> Output {
+        TableLeft {
+            lobby: LobbyName::default(),
+            id: TableId(3),
+        }
+    }
+
+    pub fn waitlist_position() -> Output {
+        WaitlistPosition {
+            lobby: LobbyName::default(),
+            id: TableId(3),
+            position: 2,
+        }
+    }
+
+    pub fn waitlist_left() -> Output {
+        WaitlistLeft {
+            lobby: LobbyName::default(),
+            id: TableId(3),
+        }
+    }
+
+    pub fn session_list() -> Output {
+        SessionList {
+            sessions: vec![
+                SessionInfo {
+                    client_id: ClientId(7),
+                    username: Some(username_user()),
+                    user_type: Some(UserType::User),
+                    subscribed: true,
+                    remote_address: Some(SocketAddr::from(([127, 0, 0, 1], 54321))),
+                    connected_at: Timestamp(1700000000),
+                    messages_sent: 12,
+                },
+                SessionInfo {
+                    client_id: ClientId(8),
+                    username: None,
+                    user_type: None,
+                    subscribed: false,
+                    remote_address: None,
+                    connected_at: Timestamp(1700000060),
+                    messages_sent: 0,
+                },
+            ],
+        }
+    }
+
+    pub fn session_kicked() -> Output {
+        SessionKicked { client_id: ClientId(7) }
+    }
+
+    pub fn kicked() -> Output {
+        Kicked {
+            reason: String::from("Spamming"),
+        }
+    }
+
+    pub fn announcement() -> Output {
+        Announcement {
+            text: String::from("Maintenance starts in 10 minutes"),
+            severity: AnnouncementSeverity::Warning,
+        }
+    }
+
+    pub fn announced() -> Output {
+        Announced { recipients: 2 }
+    }
+
+    pub fn audit_log() -> Output {
+        AuditLog {
+            entries: vec![audit_entry()],
+        }
+    }
+
+    pub fn table_history() -> Output {
+        TableHistory {
+            lobby: LobbyName::default(),
+            id: TableId(3),
+            revisions: vec![
+                TableRevision {
+                    revision: 1,
+                    timestamp: Timestamp(1700000000),
+                    username: Some(username_admin()),
+                    table: Some(table_foo_fighters()),
+                },
+                TableRevision {
+                    revision: 2,
+                    timestamp: Timestamp(1700000060),
+                    username: Some(username_admin()),
+                    table: None,
+                },
+            ],
+        }
+    }
+
+    pub fn lobby_snapshot() -> Output {
+        LobbySnapshot {
+            lobby: LobbyName::default(),
+            timestamp: Timestamp(1700000000),
+            tables: vec![table_james_bond(), table_foo_fighters()],
+        }
+    }
+
+    pub fn subscribe_tables_failed() -> Output {
+        SubscribeTablesFailed {
+            lobby: lobby_name_poker(),
+        }
     }
 
     pub fn table_add_failed() -> Output {
-        TableAddFailed
+        TableAddFailed { errors: vec![] }
+    }
+
+    pub fn table_add_failed_invalid() -> Output {
+        TableAddFailed {
+            errors: vec![FieldError::new(TableField::Name, String::from("Name should be unique"))],
+        }
     }
 
     pub fn table_update_failed() -> Output {
-        TableUpdateFailed { id: TABLE_ID_INVALID }
+        TableUpdateFailed {
+            id: TABLE_ID_INVALID,
+            errors: vec![],
+        }
+    }
+
+    pub fn table_patch_failed() -> Output {
+        TablePatchFailed {
+            id: TABLE_ID_INVALID,
+            errors: vec![],
+        }
+    }
+
+    pub fn table_move_failed() -> Output {
+        TableMoveFailed { id: TABLE_ID_INVALID }
     }
 
     pub fn table_remove_failed() -> Output {
         TableRemoveFailed { id: TABLE_ID_INVALID }
     }
 
+    pub fn table_restore_failed() -> Output {
+        TableRestoreFailed { id: TABLE_ID_INVALID }
+    }
+
+    pub fn batch_failed() -> Output {
+        BatchFailed {
+            index: 1,
+            errors: vec![],
+        }
+    }
+
+    pub fn lobby_create_failed() -> Output {
+        LobbyCreateFailed {
+            name: lobby_name_poker(),
+        }
+    }
+
+    pub fn lobby_delete_failed() -> Output {
+        LobbyDeleteFailed {
+            name: lobby_name_poker(),
+        }
+    }
+
+    pub fn table_join_failed() -> Output {
+        TableJoinFailed { id: TABLE_ID_INVALID }
+    }
+
+    pub fn table_leave_failed() -> Output {
+        TableLeaveFailed { id: TABLE_ID_INVALID }
+    }
+
+    pub fn waitlist_join_failed() -> Output {
+        WaitlistJoinFailed { id: TABLE_ID_INVALID }
+    }
+
+    pub fn waitlist_leave_failed() -> Output {
+        WaitlistLeaveFailed { id: TABLE_ID_INVALID }
+    }
+
+    pub fn session_kick_failed() -> Output {
+        SessionKickFailed {
+            client_id: CLIENT_ID_INVALID,
+        }
+    }
+
+    pub fn table_history_failed() -> Output {
+        TableHistoryFailed { id: TABLE_ID_INVALID }
+    }
+
+    pub fn lobby_snapshot_failed() -> Output {
+        LobbySnapshotFailed {
+            timestamp: Timestamp(1600000000),
+        }
+    }
+
+    pub fn idempotency_key_mismatch() -> Output {
+        IdempotencyKeyMismatch {
+            idempotency_key: IdempotencyKey(String::from("add-foo-fighters")),
+        }
+    }
+
     pub fn not_authorized() -> Output {
         NotAuthorized
     }
@@ -1,79 +1,209 @@
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
-use crate::lobby::SharedLobby;
+use crate::audit::SharedAuditLog;
+use crate::idempotency::{Reservation, SharedIdempotencyKeys};
+use crate::lobby::{Seating, SharedLobbies};
+use crate::permission::Permissions;
 use crate::protocol::Input::*;
 use crate::protocol::Output::*;
-use crate::protocol::{Input, Output, Password, Seq, Table, TableId, TableToAdd, UserType, Username};
+use crate::protocol::{
+    AnnouncementAudience, AnnouncementSeverity, AuditEntry, ClientId, Input, InputDiscriminants, LobbyName, Output,
+    Password, Placement, Seq, Table, TableChanges, TableFilter, TableId, TableOperation, TablePage, TableToAdd,
+    Timestamp, UserType, Username,
+};
 use crate::service::ClientSessionAction::*;
+use crate::session::SharedSessions;
+use crate::subscription::Subscription;
 
 /// The action to perform to the client session upon processing the input message.
 pub enum ClientSessionAction {
     DoNothing,
-    UpdateUserType { user_type: Option<UserType> },
-    UpdateSubscribed { subscribed: bool },
+    UpdateUser {
+        username: Option<Username>,
+        user_type: Option<UserType>,
+    },
+    UpdateSubscription {
+        lobby: LobbyName,
+        subscription: Option<Subscription>,
+    },
+}
+
+/// The number of audit log entries returned, unless the limit is given.
+const AUDIT_LOG_LIMIT: usize = 100;
+
+/// Represents the state shared among all the clients, which input messages are processed against.
+#[derive(Clone)]
+pub struct ServiceContext {
+    pub permissions: Arc<Permissions>,
+    pub sessions: SharedSessions,
+    pub lobbies: SharedLobbies,
+    pub audit_log: SharedAuditLog,
+    pub idempotency_keys: SharedIdempotencyKeys,
 }
 
 /// Represents the result of processing the input message.
 pub struct ProcessResult {
     pub output: Option<Output>,
     pub subscription_output: Option<Output>,
+    pub client_outputs: Vec<(ClientId, Output)>,
     pub action: ClientSessionAction,
 }
 
-/// The global unique client id generator.
-static NEXT_CLIENT_ID: AtomicUsize = AtomicUsize::new(1);
-
-/// Represents the client id.
-#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
-pub struct ClientId(pub usize);
-impl ClientId {
-    pub fn new() -> Self {
-        ClientId(NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed))
-    }
-}
-
-pub async fn process(input: Input, user_type: &Option<UserType>, lobby: &SharedLobby) -> ProcessResult {
-    match user_type {
-        None => process_unathenticated(input),
-        Some(UserType::User) => process_user(input, lobby).await,
-        Some(UserType::Admin) => process_admin(input, lobby).await,
-    }
-}
+/// Processes the input message, once the client is authenticated and its user type is allowed to send it.
+/// Administrative actions are recorded in the audit log along with their outcome, even if they fail.
+///
+/// Once the change is retried with the same idempotency key, the client receives the original output
+/// instead of applying the change twice, while subscribers are not notified again. The retry sent while the change
+/// is still being applied waits for its outcome, while the key reused with another change is rejected.
+pub async fn process(
+    client_id: ClientId,
+    input: Input,
+    user_type: &Option<UserType>,
+    context: &ServiceContext,
+) -> ProcessResult {
+    let ServiceContext {
+        permissions,
+        sessions,
+        lobbies,
+        audit_log,
+        idempotency_keys,
+    } = context;
+    // Read the session before processing, as the action may remove it (e.g. when admin kicks themselves)
+    let audited = if is_administrative(&input) {
+        Some((input.clone(), sessions.read_info(client_id).await.ok()))
+    } else {
+        None
+    };
+    let username = audited
+        .as_ref()
+        .and_then(|(_, session)| session.as_ref())
+        .and_then(|session| session.username.clone());
 
-fn process_unathenticated(input: Input) -> ProcessResult {
-    match input {
-        Login { username, password } => login(username, password),
-        _ => ProcessResult {
-            output: Some(NotAuthenticated),
+    let output = match user_type {
+        None if !matches!(input, Login { .. }) => Some(NotAuthenticated),
+        Some(user_type) if !permissions.allows(user_type, InputDiscriminants::from(&input)) => Some(NotAuthorized),
+        _ => None,
+    };
+    let process_result = match output {
+        Some(output) => ProcessResult {
+            output: Some(output),
             subscription_output: None,
+            client_outputs: vec![],
             action: DoNothing,
         },
+        None => match input.idempotency_key().cloned() {
+            Some(key) => match idempotency_keys.reserve(&username, &key, &input).await {
+                Reservation::Replayed(output) => ProcessResult {
+                    output: Some(output),
+                    subscription_output: None,
+                    client_outputs: vec![],
+                    action: DoNothing,
+                },
+                Reservation::Mismatched => ProcessResult {
+                    output: Some(IdempotencyKeyMismatch { idempotency_key: key }),
+                    subscription_output: None,
+                    client_outputs: vec![],
+                    action: DoNothing,
+                },
+                Reservation::Reserved(reservation) => {
+                    let process_result =
+                        process_authorized(client_id, username, input, sessions, lobbies, audit_log).await;
+                    reservation.complete(process_result.subscription_output.clone());
+                    process_result
+                }
+            },
+            None => process_authorized(client_id, username, input, sessions, lobbies, audit_log).await,
+        },
+    };
+
+    if let Some((request, session)) = audited {
+        let (username, remote_address) = match session {
+            Some(session) => (session.username, session.remote_address),
+            None => (None, None),
+        };
+        let entry = AuditEntry {
+            timestamp: Timestamp::now(),
+            client_id: Some(client_id),
+            username,
+            remote_address,
+            request,
+            outcome: process_result
+                .output
+                .clone()
+                .or_else(|| process_result.subscription_output.clone()),
+        };
+        audit_log.append(entry).await;
     }
+    process_result
+}
+
+/// Checks whether the input message is the administrative action, which changes the state of the server.
+/// Read-only queries, such as listing sessions or querying the audit log itself, are not administrative.
+fn is_administrative(input: &Input) -> bool {
+    matches!(
+        input,
+        AddTable { .. }
+            | UpdateTable { .. }
+            | PatchTable { .. }
+            | MoveTable { .. }
+            | RemoveTable { .. }
+            | RestoreTable { .. }
+            | Batch { .. }
+            | CreateLobby { .. }
+            | DeleteLobby { .. }
+            | KickSession { .. }
+            | Announce { .. }
+    )
 }
 
-async fn process_user(input: Input, lobby: &SharedLobby) -> ProcessResult {
+async fn process_authorized(
+    client_id: ClientId,
+    username: Option<Username>,
+    input: Input,
+    sessions: &SharedSessions,
+    lobbies: &SharedLobbies,
+    audit_log: &SharedAuditLog,
+) -> ProcessResult {
     match input {
         Ping { seq } => ping(seq),
         Login { username, password } => login(username, password),
-        SubscribeTables => subscribe(lobby).await,
-        UnsubscribeTables => unsubscribe(),
-        AddTable { .. } | UpdateTable { .. } | RemoveTable { .. } => ProcessResult {
-            output: Some(NotAuthorized),
+        SubscribeTables { lobby, filter, page } => subscribe(lobby, filter, page, lobbies).await,
+        UnsubscribeTables { lobby } => unsubscribe(lobby),
+        AddTable {
+            lobby,
+            placement,
+            table,
+            ..
+        } => add_table(username, lobby, placement, table, lobbies).await,
+        UpdateTable { lobby, table, .. } => update_table(username, lobby, table, lobbies).await,
+        PatchTable { lobby, id, changes, .. } => patch_table(username, lobby, id, changes, lobbies).await,
+        MoveTable {
+            lobby, id, placement, ..
+        } => move_table(username, lobby, id, placement, lobbies).await,
+        RemoveTable { lobby, id, .. } => remove_table(username, lobby, id, lobbies).await,
+        RestoreTable { lobby, id, .. } => restore_table(username, lobby, id, lobbies).await,
+        Batch { lobby, operations, .. } => batch(username, lobby, operations, lobbies).await,
+        CreateLobby { name } => create_lobby(name, lobbies).await,
+        DeleteLobby { name } => delete_lobby(name, lobbies).await,
+        JoinTable { lobby, id } => join_table(client_id, lobby, id, lobbies).await,
+        LeaveTable { lobby, id } => leave_table(client_id, lobby, id, lobbies).await,
+        JoinWaitlist { lobby, id } => join_waitlist(client_id, lobby, id, lobbies).await,
+        LeaveWaitlist { lobby, id } => leave_waitlist(client_id, lobby, id, lobbies).await,
+        ListSessions => list_sessions(sessions).await,
+        KickSession { client_id, reason } => kick_session(client_id, reason, sessions).await,
+        Announce {
+            text,
+            severity,
+            audience,
+        } => ProcessResult {
+            output: Some(announce(text, severity, audience, sessions).await),
             subscription_output: None,
+            client_outputs: vec![],
             action: DoNothing,
         },
-    }
-}
-
-async fn process_admin(input: Input, lobby: &SharedLobby) -> ProcessResult {
-    match input {
-        Ping { seq } => ping(seq),
-        Login { username, password } => login(username, password),
-        SubscribeTables => subscribe(lobby).await,
-        UnsubscribeTables => unsubscribe(),
-        AddTable { after_id, table } => add_table(after_id, table, lobby).await,
-        UpdateTable { table } => update_table(table, lobby).await,
-        RemoveTable { id } => remove_table(id, lobby).await,
+        QueryAuditLog { limit } => query_audit_log(limit, audit_log).await,
+        QueryTableHistory { lobby, id } => query_table_history(lobby, id, lobbies).await,
+        QueryLobbySnapshot { lobby, timestamp } => query_lobby_snapshot(lobby, timestamp, lobbies).await,
     }
 }
 
@@ -81,16 +211,25 @@ fn ping(seq: Seq) -> ProcessResult {
     ProcessResult {
         output: Some(Pong { seq }),
         subscription_output: None,
+        client_outputs: vec![],
         action: DoNothing,
     }
 }
 
-fn login(username: Username, password: Password) -> ProcessResult {
-    let user_type = match (username.as_ref(), password.as_ref()) {
+/// Returns the user type of the user with the given credentials, if they are valid.
+pub fn authenticate(username: &Username, password: &Password) -> Option<UserType> {
+    match (username.as_ref(), password.as_ref()) {
         ("admin", "admin") => Some(UserType::Admin),
+        ("manager", "manager") => Some(UserType::TableManager),
+        ("operator", "operator") => Some(UserType::Operator),
         ("user", "user") => Some(UserType::User),
+        ("viewer", "viewer") => Some(UserType::Viewer),
         _ => None,
-    };
+    }
+}
+
+fn login(username: Username, password: Password) -> ProcessResult {
+    let user_type = authenticate(&username, &password);
     let output = match user_type.clone() {
         None => LoginFailed,
         Some(user_type) => LoginSuccessful { user_type },
@@ -98,69 +237,211 @@ fn login(username: Username, password: Password) -> ProcessResult {
     ProcessResult {
         output: Some(output),
         subscription_output: None,
-        action: UpdateUserType { user_type },
+        client_outputs: vec![],
+        action: UpdateUser {
+            username: user_type.as_ref().map(|_| username),
+            user_type,
+        },
     }
 }
 
-async fn subscribe(lobby: &SharedLobby) -> ProcessResult {
-    let tables = lobby.read_tables().await;
-    ProcessResult {
-        output: Some(TableList { tables }),
-        subscription_output: None,
-        action: UpdateSubscribed { subscribed: true },
+async fn subscribe(
+    lobby: LobbyName,
+    filter: TableFilter,
+    page: Option<TablePage>,
+    lobbies: &SharedLobbies,
+) -> ProcessResult {
+    match lobbies.get(&lobby).await {
+        Ok(shared_lobby) => {
+            let mut subscription = Subscription::new(filter, page);
+            let tables = subscription.refresh(&shared_lobby.read_tables().await);
+            ProcessResult {
+                output: Some(TableList {
+                    lobby: lobby.clone(),
+                    tables,
+                }),
+                subscription_output: None,
+                client_outputs: vec![],
+                action: UpdateSubscription {
+                    lobby,
+                    subscription: Some(subscription),
+                },
+            }
+        }
+        Err(e) => {
+            debug!("Failed to subscribe to tables: {}", e);
+            ProcessResult {
+                output: Some(SubscribeTablesFailed { lobby }),
+                subscription_output: None,
+                client_outputs: vec![],
+                action: DoNothing,
+            }
+        }
     }
 }
 
-fn unsubscribe() -> ProcessResult {
+fn unsubscribe(lobby: LobbyName) -> ProcessResult {
     ProcessResult {
         output: None,
         subscription_output: None,
-        action: UpdateSubscribed { subscribed: false },
+        client_outputs: vec![],
+        action: UpdateSubscription {
+            lobby,
+            subscription: None,
+        },
     }
 }
 
-async fn add_table(after_id: TableId, table_to_add: TableToAdd, lobby: &SharedLobby) -> ProcessResult {
-    match lobby.add_table(after_id, table_to_add).await {
-        Ok(table) => ProcessResult {
+async fn add_table(
+    username: Option<Username>,
+    lobby: LobbyName,
+    placement: Placement,
+    table_to_add: TableToAdd,
+    lobbies: &SharedLobbies,
+) -> ProcessResult {
+    let result = match lobbies.get(&lobby).await {
+        Ok(shared_lobby) => shared_lobby.add_table(username, placement, table_to_add).await,
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok((after_id, table)) => ProcessResult {
             output: None,
-            subscription_output: Some(TableAdded { after_id, table }),
+            subscription_output: Some(TableAdded { lobby, after_id, table }),
+            client_outputs: vec![],
             action: DoNothing,
         },
         Err(e) => {
             debug!("Failed to add table: {}", e);
             ProcessResult {
-                output: Some(TableAddFailed),
+                output: Some(TableAddFailed {
+                    errors: e.into_field_errors(),
+                }),
                 subscription_output: None,
+                client_outputs: vec![],
                 action: DoNothing,
             }
         }
     }
 }
 
-async fn update_table(table_to_update: Table, lobby: &SharedLobby) -> ProcessResult {
+async fn update_table(
+    username: Option<Username>,
+    lobby: LobbyName,
+    table_to_update: Table,
+    lobbies: &SharedLobbies,
+) -> ProcessResult {
     let id = table_to_update.id;
-    match lobby.update_table(table_to_update).await {
-        Ok(table) => ProcessResult {
+    let result = match lobbies.get(&lobby).await {
+        Ok(shared_lobby) => shared_lobby.update_table(username, table_to_update).await,
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok((table, seating)) => ProcessResult {
             output: None,
-            subscription_output: Some(TableUpdated { table }),
+            client_outputs: seated_client_outputs(&lobby, seating),
+            subscription_output: Some(TableUpdated { lobby, table }),
             action: DoNothing,
         },
         Err(e) => {
             debug!("Failed to update table: {}", e);
             ProcessResult {
-                output: Some(TableUpdateFailed { id }),
+                output: Some(TableUpdateFailed {
+                    id,
+                    errors: e.into_field_errors(),
+                }),
+                subscription_output: None,
+                client_outputs: vec![],
+                action: DoNothing,
+            }
+        }
+    }
+}
+
+async fn patch_table(
+    username: Option<Username>,
+    lobby: LobbyName,
+    id: TableId,
+    changes: TableChanges,
+    lobbies: &SharedLobbies,
+) -> ProcessResult {
+    let result = match lobbies.get(&lobby).await {
+        Ok(shared_lobby) => shared_lobby.patch_table(username, id, changes).await,
+        Err(e) => Err(e),
+    };
+    match result {
+        // Let only the admin know, once the table has remained the same
+        Ok((changes, _)) if changes.is_empty() => ProcessResult {
+            output: Some(TablePatched { lobby, id, changes }),
+            subscription_output: None,
+            client_outputs: vec![],
+            action: DoNothing,
+        },
+        Ok((changes, seating)) => ProcessResult {
+            output: None,
+            client_outputs: seated_client_outputs(&lobby, seating),
+            subscription_output: Some(TablePatched { lobby, id, changes }),
+            action: DoNothing,
+        },
+        Err(e) => {
+            debug!("Failed to patch table: {}", e);
+            ProcessResult {
+                output: Some(TablePatchFailed {
+                    id,
+                    errors: e.into_field_errors(),
+                }),
                 subscription_output: None,
+                client_outputs: vec![],
                 action: DoNothing,
             }
         }
     }
 }
 
-async fn remove_table(id: TableId, lobby: &SharedLobby) -> ProcessResult {
-    match lobby.remove_table(id).await {
-        Ok(id) => ProcessResult {
+async fn move_table(
+    username: Option<Username>,
+    lobby: LobbyName,
+    id: TableId,
+    placement: Placement,
+    lobbies: &SharedLobbies,
+) -> ProcessResult {
+    let result = match lobbies.get(&lobby).await {
+        Ok(shared_lobby) => shared_lobby.move_table(username, id, placement).await,
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(after_id) => ProcessResult {
             output: None,
-            subscription_output: Some(TableRemoved { id }),
+            subscription_output: Some(TableMoved { lobby, id, after_id }),
+            client_outputs: vec![],
+            action: DoNothing,
+        },
+        Err(e) => {
+            debug!("Failed to move table: {}", e);
+            ProcessResult {
+                output: Some(TableMoveFailed { id }),
+                subscription_output: None,
+                client_outputs: vec![],
+                action: DoNothing,
+            }
+        }
+    }
+}
+
+async fn remove_table(
+    username: Option<Username>,
+    lobby: LobbyName,
+    id: TableId,
+    lobbies: &SharedLobbies,
+) -> ProcessResult {
+    let result = match lobbies.get(&lobby).await {
+        Ok(shared_lobby) => shared_lobby.remove_table(username, id).await,
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok((id, seating)) => ProcessResult {
+            output: None,
+            client_outputs: seated_client_outputs(&lobby, seating),
+            subscription_output: Some(TableRemoved { lobby, id }),
             action: DoNothing,
         },
         Err(e) => {
@@ -168,8 +449,368 @@ async fn remove_table(id: TableId, lobby: &SharedLobby) -> ProcessResult {
             ProcessResult {
                 output: Some(TableRemoveFailed { id }),
                 subscription_output: None,
+                client_outputs: vec![],
+                action: DoNothing,
+            }
+        }
+    }
+}
+
+async fn restore_table(
+    username: Option<Username>,
+    lobby: LobbyName,
+    id: TableId,
+    lobbies: &SharedLobbies,
+) -> ProcessResult {
+    let result = match lobbies.get(&lobby).await {
+        Ok(shared_lobby) => shared_lobby.restore_table(username, id).await,
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok((after_id, table)) => ProcessResult {
+            output: None,
+            subscription_output: Some(TableAdded { lobby, after_id, table }),
+            client_outputs: vec![],
+            action: DoNothing,
+        },
+        Err(e) => {
+            debug!("Failed to restore table: {}", e);
+            ProcessResult {
+                output: Some(TableRestoreFailed { id }),
+                subscription_output: None,
+                client_outputs: vec![],
+                action: DoNothing,
+            }
+        }
+    }
+}
+
+async fn batch(
+    username: Option<Username>,
+    lobby: LobbyName,
+    operations: Vec<TableOperation>,
+    lobbies: &SharedLobbies,
+) -> ProcessResult {
+    let result = match lobbies.get(&lobby).await {
+        Ok(shared_lobby) => shared_lobby.apply_batch(username, operations).await,
+        Err(e) => Err((0, e)),
+    };
+    match result {
+        Ok((outputs, seatings)) => ProcessResult {
+            output: None,
+            client_outputs: seated_client_outputs(&lobby, seatings),
+            subscription_output: Some(BatchApplied { lobby, outputs }),
+            action: DoNothing,
+        },
+        Err((index, e)) => {
+            debug!("Failed to apply batch: {}", e);
+            ProcessResult {
+                output: Some(BatchFailed {
+                    index,
+                    errors: e.into_field_errors(),
+                }),
+                subscription_output: None,
+                client_outputs: vec![],
+                action: DoNothing,
+            }
+        }
+    }
+}
+
+async fn create_lobby(name: LobbyName, lobbies: &SharedLobbies) -> ProcessResult {
+    match lobbies.create(name.clone()).await {
+        Ok(name) => ProcessResult {
+            output: Some(LobbyCreated { name }),
+            subscription_output: None,
+            client_outputs: vec![],
+            action: DoNothing,
+        },
+        Err(e) => {
+            debug!("Failed to create lobby: {}", e);
+            ProcessResult {
+                output: Some(LobbyCreateFailed { name }),
+                subscription_output: None,
+                client_outputs: vec![],
                 action: DoNothing,
             }
         }
     }
 }
+
+async fn delete_lobby(name: LobbyName, lobbies: &SharedLobbies) -> ProcessResult {
+    match lobbies.delete(name.clone()).await {
+        Ok((name, seatings)) => ProcessResult {
+            output: Some(LobbyDeleted { name: name.clone() }),
+            client_outputs: seated_client_outputs(&name, seatings),
+            subscription_output: Some(LobbyDeleted { name }),
+            action: DoNothing,
+        },
+        Err(e) => {
+            debug!("Failed to delete lobby: {}", e);
+            ProcessResult {
+                output: Some(LobbyDeleteFailed { name }),
+                subscription_output: None,
+                client_outputs: vec![],
+                action: DoNothing,
+            }
+        }
+    }
+}
+
+async fn join_table(client_id: ClientId, lobby: LobbyName, id: TableId, lobbies: &SharedLobbies) -> ProcessResult {
+    let result = lobbies.join_table(&lobby, id, client_id).await;
+    match result {
+        Ok(table) => ProcessResult {
+            output: Some(TableJoined {
+                lobby: lobby.clone(),
+                id,
+            }),
+            subscription_output: Some(TableUpdated { lobby, table }),
+            client_outputs: vec![],
+            action: DoNothing,
+        },
+        Err(e) => {
+            debug!("Failed to join table: {}", e);
+            ProcessResult {
+                output: Some(TableJoinFailed { id }),
+                subscription_output: None,
+                client_outputs: vec![],
+                action: DoNothing,
+            }
+        }
+    }
+}
+
+async fn leave_table(client_id: ClientId, lobby: LobbyName, id: TableId, lobbies: &SharedLobbies) -> ProcessResult {
+    let result = match lobbies.get(&lobby).await {
+        Ok(shared_lobby) => shared_lobby.leave_table(id, client_id).await,
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(seating) => {
+            let (subscription_output, client_outputs) = seating_outputs(&lobby, seating);
+            ProcessResult {
+                output: Some(TableLeft { lobby, id }),
+                subscription_output,
+                client_outputs,
+                action: DoNothing,
+            }
+        }
+        Err(e) => {
+            debug!("Failed to leave table: {}", e);
+            ProcessResult {
+                output: Some(TableLeaveFailed { id }),
+                subscription_output: None,
+                client_outputs: vec![],
+                action: DoNothing,
+            }
+        }
+    }
+}
+
+async fn join_waitlist(client_id: ClientId, lobby: LobbyName, id: TableId, lobbies: &SharedLobbies) -> ProcessResult {
+    let result = lobbies.join_waitlist(&lobby, id, client_id).await;
+    match result {
+        Ok(position) => ProcessResult {
+            output: Some(WaitlistPosition { lobby, id, position }),
+            subscription_output: None,
+            client_outputs: vec![],
+            action: DoNothing,
+        },
+        Err(e) => {
+            debug!("Failed to join waitlist: {}", e);
+            ProcessResult {
+                output: Some(WaitlistJoinFailed { id }),
+                subscription_output: None,
+                client_outputs: vec![],
+                action: DoNothing,
+            }
+        }
+    }
+}
+
+async fn leave_waitlist(client_id: ClientId, lobby: LobbyName, id: TableId, lobbies: &SharedLobbies) -> ProcessResult {
+    let result = match lobbies.get(&lobby).await {
+        Ok(shared_lobby) => shared_lobby.leave_waitlist(id, client_id).await,
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(seating) => {
+            let (subscription_output, client_outputs) = seating_outputs(&lobby, seating);
+            ProcessResult {
+                output: Some(WaitlistLeft { lobby, id }),
+                subscription_output,
+                client_outputs,
+                action: DoNothing,
+            }
+        }
+        Err(e) => {
+            debug!("Failed to leave waitlist: {}", e);
+            ProcessResult {
+                output: Some(WaitlistLeaveFailed { id }),
+                subscription_output: None,
+                client_outputs: vec![],
+                action: DoNothing,
+            }
+        }
+    }
+}
+
+async fn list_sessions(sessions: &SharedSessions) -> ProcessResult {
+    ProcessResult {
+        output: Some(SessionList {
+            sessions: sessions.list().await,
+        }),
+        subscription_output: None,
+        client_outputs: vec![],
+        action: DoNothing,
+    }
+}
+
+async fn kick_session(client_id: ClientId, reason: String, sessions: &SharedSessions) -> ProcessResult {
+    let output = match sessions.kick(client_id, reason).await {
+        Ok(()) => SessionKicked { client_id },
+        Err(e) => {
+            debug!("Failed to kick session: {}", e);
+            SessionKickFailed { client_id }
+        }
+    };
+    ProcessResult {
+        output: Some(output),
+        subscription_output: None,
+        client_outputs: vec![],
+        action: DoNothing,
+    }
+}
+
+async fn query_audit_log(limit: Option<usize>, audit_log: &SharedAuditLog) -> ProcessResult {
+    ProcessResult {
+        output: Some(AuditLog {
+            entries: audit_log.read_recent(limit.unwrap_or(AUDIT_LOG_LIMIT)).await,
+        }),
+        subscription_output: None,
+        client_outputs: vec![],
+        action: DoNothing,
+    }
+}
+
+async fn query_table_history(lobby: LobbyName, id: TableId, lobbies: &SharedLobbies) -> ProcessResult {
+    let result = match lobbies.get(&lobby).await {
+        Ok(shared_lobby) => shared_lobby.read_table_history(id).await,
+        Err(e) => Err(e),
+    };
+    let output = match result {
+        Ok(revisions) => TableHistory { lobby, id, revisions },
+        Err(e) => {
+            debug!("Failed to query table history: {}", e);
+            TableHistoryFailed { id }
+        }
+    };
+    ProcessResult {
+        output: Some(output),
+        subscription_output: None,
+        client_outputs: vec![],
+        action: DoNothing,
+    }
+}
+
+async fn query_lobby_snapshot(lobby: LobbyName, timestamp: Timestamp, lobbies: &SharedLobbies) -> ProcessResult {
+    let result = match lobbies.get(&lobby).await {
+        Ok(shared_lobby) => shared_lobby.read_tables_at(timestamp).await,
+        Err(e) => Err(e),
+    };
+    let output = match result {
+        Ok(tables) => LobbySnapshot {
+            lobby,
+            timestamp,
+            tables,
+        },
+        Err(e) => {
+            debug!("Failed to query lobby snapshot: {}", e);
+            LobbySnapshotFailed { timestamp }
+        }
+    };
+    ProcessResult {
+        output: Some(output),
+        subscription_output: None,
+        client_outputs: vec![],
+        action: DoNothing,
+    }
+}
+
+/// Sends the announcement to all clients of the given audience and returns the output message for the announcer.
+pub async fn announce(
+    text: String,
+    severity: AnnouncementSeverity,
+    audience: AnnouncementAudience,
+    sessions: &SharedSessions,
+) -> Output {
+    let broadcast_result = sessions.announce(Announcement { text, severity }, audience).await;
+    debug!("Announced message: {:?}", broadcast_result);
+    Announced {
+        recipients: broadcast_result.success_client_ids.len(),
+    }
+}
+
+/// Returns the output message about the change to seats, which should be broadcasted, along with output messages,
+/// which should be sent to the promoted clients and to the clients whose position in the waitlist has changed.
+pub fn seating_outputs(lobby: &LobbyName, seating: Seating) -> (Option<Output>, Vec<(ClientId, Output)>) {
+    let id = seating.id;
+    let subscription_output = seating.table.map(|table| TableUpdated {
+        lobby: lobby.clone(),
+        table,
+    });
+    let promoted_outputs = seating.promoted_client_ids.into_iter().map(|client_id| {
+        (
+            client_id,
+            TableJoined {
+                lobby: lobby.clone(),
+                id,
+            },
+        )
+    });
+    let unseated_outputs = seating.unseated_client_ids.into_iter().map(|client_id| {
+        (
+            client_id,
+            TableLeft {
+                lobby: lobby.clone(),
+                id,
+            },
+        )
+    });
+    let unlisted_outputs = seating.unlisted_client_ids.into_iter().map(|client_id| {
+        (
+            client_id,
+            WaitlistLeft {
+                lobby: lobby.clone(),
+                id,
+            },
+        )
+    });
+    let position_outputs = seating.waitlist.into_iter().enumerate().map(|(index, client_id)| {
+        let position = index + 1;
+        (
+            client_id,
+            WaitlistPosition {
+                lobby: lobby.clone(),
+                id,
+                position,
+            },
+        )
+    });
+    let client_outputs = promoted_outputs
+        .chain(unseated_outputs)
+        .chain(unlisted_outputs)
+        .chain(position_outputs)
+        .collect();
+    (subscription_output, client_outputs)
+}
+
+/// Returns output messages, which should be sent to clients affected by changes to seats made along with
+/// the change to tables, which is broadcasted on its own.
+fn seated_client_outputs(lobby: &LobbyName, seatings: impl IntoIterator<Item = Seating>) -> Vec<(ClientId, Output)> {
+    seatings
+        .into_iter()
+        .flat_map(|seating| seating_outputs(lobby, seating).1)
+        .collect()
+}
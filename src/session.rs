@@ -1,130 +1,357 @@
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
 use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::mpsc::{self, error::TrySendError};
 use tokio::sync::RwLock;
 
 use crate::{
-    protocol::{Output, UserType},
-    service::ClientId,
+    protocol::{AnnouncementAudience, ClientId, LobbyName, Output, SessionInfo, Table, Timestamp, UserType, Username},
+    subscription::Subscription,
 };
 
-/// Represents the sender, which can be used to output messages to the client.
-type ClientSender = UnboundedSender<Output>;
+/// The number of shards the sessions are split into, so that clients connecting, disconnecting and changing
+/// their subscriptions contend only with the shard currently broadcast to instead of all the sessions.
+const SHARDS: usize = 16;
+
+/// The maximum number of output messages queued for the client, once it is exceeded the client is lagging.
+const CLIENT_QUEUE_CAPACITY: usize = 1024;
+
+/// Represents the output message encoded once, which can be shared among all the clients it is sent to.
+///
+/// The frame is shared only up to the connection of each client, as WebSocket messages of warp own their text.
+/// Writing the frame therefore still copies it once per client, which `benchmark_broadcast` measures as well.
+#[derive(Clone, Debug)]
+pub struct Frame(Arc<str>);
+impl Frame {
+    pub fn encode(output: &Output) -> Result<Self, String> {
+        serde_json::to_string(output)
+            .map(|json| Frame(Arc::from(json)))
+            .map_err(|e| e.to_string())
+    }
+}
+impl AsRef<str> for Frame {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Represents the sender, which can be used to output encoded messages to the client.
+pub type ClientSender = mpsc::Sender<Frame>;
+
+/// Represents the receiver of encoded messages, which should be written to the client connection.
+pub type ClientReceiver = mpsc::Receiver<Frame>;
+
+/// Creates the bounded channel for output messages of the client, so that the client, which does not keep up
+/// with them, is detected as lagging instead of queueing messages indefinitely.
+pub fn client_channel() -> (ClientSender, ClientReceiver) {
+    mpsc::channel(CLIENT_QUEUE_CAPACITY)
+}
 
 /// Represents the client session.
 struct Session {
     pub client_id: ClientId,
     pub client_sender: ClientSender,
+    pub username: Option<Username>,
     pub user_type: Option<UserType>,
-    pub subscribed: bool,
+    pub subscriptions: HashMap<LobbyName, Subscription>,
+    pub remote_address: Option<SocketAddr>,
+    pub connected_at: Timestamp,
+    pub messages_sent: AtomicU64,
+    pub lagging: AtomicBool,
+}
+impl Session {
+    /// Sends the encoded output message to the client and counts it as sent. Once the queue of the client
+    /// is full, the message is dropped and the client is marked as lagging.
+    fn send(&self, frame: Frame) -> Result<(), String> {
+        match self.client_sender.try_send(frame) {
+            Ok(()) => {
+                self.messages_sent.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
+            Err(TrySendError::Full(_)) => {
+                self.lagging.store(true, Ordering::Relaxed);
+                Err(format!("Client {:?} is lagging behind by {} messages", self.client_id, CLIENT_QUEUE_CAPACITY))
+            }
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    /// Returns whether the client has missed messages, so its session should not be used anymore.
+    fn is_lagging(&self) -> bool {
+        self.lagging.load(Ordering::Relaxed)
+    }
+
+    fn info(&self) -> SessionInfo {
+        SessionInfo {
+            client_id: self.client_id,
+            username: self.username.clone(),
+            user_type: self.user_type.clone(),
+            subscribed: !self.subscriptions.is_empty(),
+            remote_address: self.remote_address,
+            connected_at: self.connected_at,
+            messages_sent: self.messages_sent.load(Ordering::Relaxed),
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct BroadcastResult {
     pub success_client_ids: HashSet<ClientId>,
     pub failure_client_ids: HashSet<ClientId>,
+    /// The failed clients, which have been disconnected for lagging.
+    pub lagging_client_ids: HashSet<ClientId>,
 }
 impl BroadcastResult {
     pub fn new() -> Self {
         BroadcastResult {
             success_client_ids: HashSet::new(),
             failure_client_ids: HashSet::new(),
+            lagging_client_ids: HashSet::new(),
         }
     }
 }
 
+type Shard = RwLock<HashMap<ClientId, Session>>;
+
 /// Represents the currently connected client sessions.
+///
+/// Sessions are split into shards by client id, each guarded by its own lock. Clients, which lag behind
+/// broadcasted messages, are disconnected, so that they can reconnect and receive the fresh table list
+/// instead of missing changes.
 #[derive(Clone)]
 pub struct SharedSessions {
-    sessions: Arc<RwLock<HashMap<ClientId, Session>>>,
+    shards: Arc<[Shard]>,
+    /// Notified of clients dropped by the server, so that their seats are freed without waiting for their
+    /// connections to close.
+    dropped_sender: mpsc::UnboundedSender<ClientId>,
 }
 impl SharedSessions {
-    pub fn new() -> Self {
+    /// Creates sessions, which notify the given sender of clients dropped for lagging or kicked.
+    pub fn notifying(dropped_sender: mpsc::UnboundedSender<ClientId>) -> Self {
         SharedSessions {
-            sessions: Arc::default(),
+            shards: (0..SHARDS).map(|_| Shard::default()).collect(),
+            dropped_sender,
         }
     }
 
-    pub async fn add(&self, client_id: ClientId, client_sender: ClientSender) {
+    fn notify_dropped(&self, client_id: ClientId) {
+        if self.dropped_sender.send(client_id).is_err() {
+            debug!("Nobody is notified of dropped client {:?}", client_id);
+        }
+    }
+
+    fn shard(&self, client_id: ClientId) -> &Shard {
+        let mut hasher = DefaultHasher::new();
+        client_id.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % self.shards.len()]
+    }
+
+    pub async fn add(&self, client_id: ClientId, client_sender: ClientSender, remote_address: Option<SocketAddr>) {
         let session = Session {
             client_id,
             client_sender,
+            username: None,
             user_type: None,
-            subscribed: false,
+            subscriptions: HashMap::new(),
+            remote_address,
+            connected_at: Timestamp::now(),
+            messages_sent: AtomicU64::new(0),
+            lagging: AtomicBool::new(false),
         };
-        self.sessions.write().await.insert(client_id, session);
+        self.shard(client_id).write().await.insert(client_id, session);
     }
 
     pub async fn remove(&self, client_id: ClientId) {
-        self.sessions.write().await.remove(&client_id);
+        self.shard(client_id).write().await.remove(&client_id);
     }
 
-    /// Sends the output message to the given client.
+    /// Returns all connected client sessions ordered by client id.
+    pub async fn list(&self) -> Vec<SessionInfo> {
+        let mut sessions: Vec<SessionInfo> = vec![];
+        for shard in self.shards.iter() {
+            sessions.extend(shard.read().await.values().map(Session::info));
+        }
+        sessions.sort_by_key(|session| session.client_id);
+        sessions
+    }
+
+    /// Sends the kicked message with the given reason to the client and removes its session. Once the session
+    /// is removed, the connection of the client is closed as soon as all pending messages are sent, while the client
+    /// is reported as dropped right away. The client is kicked even if the kicked message cannot be queued for it.
+    pub async fn kick(&self, client_id: ClientId, reason: String) -> Result<(), String> {
+        let frame = Frame::encode(&Output::Kicked { reason })?;
+        let session = match self.shard(client_id).write().await.remove(&client_id) {
+            Some(session) => session,
+            None => return Self::no_session(client_id),
+        };
+        self.notify_dropped(client_id);
+        if let Err(e) = session.send(frame) {
+            debug!("Failed to send kicked message to client {:?}: {}", client_id, e);
+        }
+        Ok(())
+    }
+
+    /// Sends the output message to the given client, disconnecting it once it is lagging.
     pub async fn send(&self, client_id: ClientId, output: Output) -> Result<(), String> {
-        match self.sessions.read().await.get(&client_id) {
-            Some(session) => session.client_sender.send(output).map_err(|e| e.to_string()),
-            None => Self::no_session(client_id),
+        let frame = Frame::encode(&output)?;
+        let shard = self.shard(client_id);
+        let result = match shard.read().await.get(&client_id) {
+            Some(session) => session.send(frame),
+            None => return Self::no_session(client_id),
+        };
+        if result.is_err() {
+            let mut sessions = shard.write().await;
+            if sessions.get(&client_id).is_some_and(Session::is_lagging) {
+                warn!("Disconnecting lagging client {:?}", client_id);
+                sessions.remove(&client_id);
+                self.notify_dropped(client_id);
+            }
         }
+        result
     }
 
-    /// Broadcasts the output message to all subscribed clients.
-    pub async fn broadcast(&self, output: Output) -> BroadcastResult {
-        self.sessions
-            .read()
-            .await
-            .values()
-            .filter_map(|session| {
-                if session.subscribed {
-                    Some(
-                        session
-                            .client_sender
-                            .send(output.clone())
-                            .map(|_| session.client_id)
-                            .map_err(|_| session.client_id),
-                    )
-                } else {
-                    None
-                }
-            })
-            .fold(BroadcastResult::new(), |mut acc, result| match result {
-                Ok(client_id) => {
-                    acc.success_client_ids.insert(client_id);
-                    acc
+    /// Broadcasts the output message about the change to the lobby to all clients subscribed to that lobby,
+    /// which can see the changed tables. Once the lobby is deleted, its subscriptions are removed as well.
+    pub async fn broadcast(&self, output: Output, tables: &[Arc<Table>]) -> BroadcastResult {
+        let lobby = match output.lobby() {
+            Some(lobby) => lobby,
+            None => return BroadcastResult::new(),
+        };
+        // Encode the change once, as most subscribers receive it as is
+        let frame = Frame::encode(&output);
+        self.fan_out(|session| {
+            if let Output::LobbyDeleted { .. } = output {
+                session.subscriptions.remove(lobby)?;
+                Some(frame.clone())
+            } else {
+                match session.subscriptions.get_mut(lobby)?.track(&output, tables)? {
+                    Cow::Borrowed(_) => Some(frame.clone()),
+                    Cow::Owned(output) => Some(Frame::encode(&output)),
                 }
-                Err(client_id) => {
-                    acc.failure_client_ids.insert(client_id);
-                    acc
+            }
+        })
+        .await
+    }
+
+    /// Returns whether any client subscribed to the lobby can see only some of its tables, so that broadcasts
+    /// to the lobby need the snapshot of its tables to select them.
+    pub async fn needs_tables(&self, lobby: &LobbyName) -> bool {
+        for shard in self.shards.iter() {
+            let sessions = shard.read().await;
+            let restricted = sessions.values().any(|session| {
+                session
+                    .subscriptions
+                    .get(lobby)
+                    .is_some_and(Subscription::is_restricted)
+            });
+            if restricted {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Broadcasts the announcement to all clients of the given audience regardless of their subscriptions.
+    pub async fn announce(&self, output: Output, audience: AnnouncementAudience) -> BroadcastResult {
+        let frame = Frame::encode(&output);
+        self.fan_out(|session| match audience {
+            AnnouncementAudience::All => Some(frame.clone()),
+            AnnouncementAudience::Authenticated => session.user_type.as_ref().map(|_| frame.clone()),
+        })
+        .await
+    }
+
+    /// Sends the encoded output message returned by the given function to each client, for which it is returned.
+    /// Clients, for which the output message cannot be encoded or sent, are reported as failed, while lagging
+    /// clients are disconnected and reported as dropped as well.
+    ///
+    /// Shards are locked one by one, so that the other shards remain available in the meantime.
+    async fn fan_out<F>(&self, mut f: F) -> BroadcastResult
+    where
+        F: FnMut(&mut Session) -> Option<Result<Frame, String>>,
+    {
+        let mut broadcast_result = BroadcastResult::new();
+        for shard in self.shards.iter() {
+            let mut sessions = shard.write().await;
+            let mut lagging_client_ids = vec![];
+            for session in sessions.values_mut() {
+                match f(session).map(|result| result.and_then(|frame| session.send(frame))) {
+                    Some(Ok(())) => {
+                        broadcast_result.success_client_ids.insert(session.client_id);
+                    }
+                    Some(Err(_)) => {
+                        broadcast_result.failure_client_ids.insert(session.client_id);
+                        if session.is_lagging() {
+                            lagging_client_ids.push(session.client_id);
+                        }
+                    }
+                    None => {}
                 }
-            })
+            }
+            for client_id in lagging_client_ids {
+                warn!("Disconnecting lagging client {:?}", client_id);
+                sessions.remove(&client_id);
+                self.notify_dropped(client_id);
+                broadcast_result.lagging_client_ids.insert(client_id);
+            }
+        }
+        broadcast_result
+    }
+
+    pub async fn read_info(&self, client_id: ClientId) -> Result<SessionInfo, String> {
+        match self.shard(client_id).read().await.get(&client_id) {
+            Some(session) => Ok(session.info()),
+            None => Self::no_session(client_id),
+        }
     }
 
     pub async fn read_user_type(&self, client_id: ClientId) -> Result<Option<UserType>, String> {
-        if let Some(session) = self.sessions.read().await.get(&client_id) {
+        if let Some(session) = self.shard(client_id).read().await.get(&client_id) {
             Ok(session.user_type.clone())
         } else {
             Self::no_session(client_id)
         }
     }
 
-    pub async fn write_user_type(&self, client_id: ClientId, user_type: Option<UserType>) -> Result<(), String> {
+    pub async fn write_user(
+        &self,
+        client_id: ClientId,
+        username: Option<Username>,
+        user_type: Option<UserType>,
+    ) -> Result<(), String> {
         self.write(client_id, |session| {
+            session.username = username;
             session.user_type = user_type;
         })
         .await
     }
 
-    pub async fn write_subscribed(&self, client_id: ClientId, subscribed: bool) -> Result<(), String> {
-        self.write(client_id, |session| {
-            session.subscribed = subscribed;
+    pub async fn write_subscription(
+        &self,
+        client_id: ClientId,
+        lobby: LobbyName,
+        subscription: Option<Subscription>,
+    ) -> Result<(), String> {
+        self.write(client_id, |session| match subscription {
+            Some(subscription) => {
+                session.subscriptions.insert(lobby, subscription);
+            }
+            None => {
+                session.subscriptions.remove(&lobby);
+            }
         })
         .await
     }
 
     async fn write<F>(&self, client_id: ClientId, f: F) -> Result<(), String>
     where
-        F: FnOnce(&mut Session) -> (),
+        F: FnOnce(&mut Session),
     {
-        if let Some(session) = self.sessions.write().await.get_mut(&client_id) {
+        if let Some(session) = self.shard(client_id).write().await.get_mut(&client_id) {
             f(session);
             Ok(())
         } else {
@@ -141,22 +368,34 @@ impl SharedSessions {
 mod tests {
 
     use std::collections::HashSet;
-
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
     use tokio::sync::mpsc;
+    use warp::ws::Message;
 
     use crate::{
-        protocol::{test_data, Output, UserType},
-        service::ClientId,
-        session::SharedSessions,
+        protocol::{test_data, AnnouncementAudience, ClientId, LobbyName, Output, TablePage, UserType},
+        session::{client_channel, Frame, SharedSessions, CLIENT_QUEUE_CAPACITY},
+        subscription::Subscription,
     };
 
+    impl SharedSessions {
+        fn new() -> Self {
+            Self::notifying(mpsc::unbounded_channel().0)
+        }
+    }
+
+    fn decode(frame: Frame) -> Output {
+        serde_json::from_str(frame.as_ref()).expect("Frame should be decoded")
+    }
+
     #[tokio::test]
     async fn send_output_to_existing_client_id() {
         let shared_sessions = SharedSessions::new();
         let client_id = ClientId::new();
-        let (client_sender, mut client_receiver) = mpsc::unbounded_channel::<Output>();
+        let (client_sender, mut client_receiver) = client_channel();
         let sent_output = test_data::pong();
-        shared_sessions.add(client_id, client_sender).await;
+        shared_sessions.add(client_id, client_sender, None).await;
 
         // when
         let result = shared_sessions.send(client_id, sent_output.clone()).await;
@@ -164,7 +403,7 @@ mod tests {
         // then
         result.expect("Output should be sent");
         let received_output = client_receiver.recv().await.expect("Output should be received");
-        assert_eq!(received_output, sent_output);
+        assert_eq!(decode(received_output), sent_output);
     }
 
     #[tokio::test]
@@ -188,27 +427,27 @@ mod tests {
         let client_id_2 = ClientId::new();
         let client_id_3 = ClientId::new();
 
-        let (client_sender_1, mut client_receiver_1) = mpsc::unbounded_channel::<Output>();
-        let (client_sender_2, mut client_receiver_2) = mpsc::unbounded_channel::<Output>();
-        let (client_sender_3, mut client_receiver_3) = mpsc::unbounded_channel::<Output>();
+        let (client_sender_1, mut client_receiver_1) = client_channel();
+        let (client_sender_2, mut client_receiver_2) = client_channel();
+        let (client_sender_3, mut client_receiver_3) = client_channel();
 
-        let broadcasted_output = test_data::pong();
+        let broadcasted_output = test_data::table_removed();
 
-        shared_sessions.add(client_id_1, client_sender_1).await;
-        shared_sessions.add(client_id_2, client_sender_2).await;
-        shared_sessions.add(client_id_3, client_sender_3).await;
+        shared_sessions.add(client_id_1, client_sender_1, None).await;
+        shared_sessions.add(client_id_2, client_sender_2, None).await;
+        shared_sessions.add(client_id_3, client_sender_3, None).await;
 
         shared_sessions
-            .write_subscribed(client_id_2, true)
+            .write_subscription(client_id_2, LobbyName::default(), Some(Subscription::default()))
             .await
             .expect("Client #2 should be subscribed");
         shared_sessions
-            .write_subscribed(client_id_3, true)
+            .write_subscription(client_id_3, LobbyName::default(), Some(Subscription::default()))
             .await
             .expect("Client #3 should be subscribed");
 
         // when
-        let broadcast_result = shared_sessions.broadcast(broadcasted_output.clone()).await;
+        let broadcast_result = shared_sessions.broadcast(broadcasted_output.clone(), &[]).await;
 
         // then
         assert_eq!(broadcast_result.success_client_ids, HashSet::from([client_id_2, client_id_3]));
@@ -225,16 +464,106 @@ mod tests {
             .expect("Output for client #3 should be received");
 
         assert!(received_output_1.is_err(), "Output for client #1 should not be received");
-        assert_eq!(received_output_2, broadcasted_output);
-        assert_eq!(received_output_3, broadcasted_output);
+        assert_eq!(decode(received_output_2), broadcasted_output);
+        assert_eq!(decode(received_output_3), broadcasted_output);
+    }
+
+    #[tokio::test]
+    async fn broadcast_output_encoded_once() {
+        let shared_sessions = SharedSessions::new();
+        let mut client_receivers = vec![];
+        for _ in 0..3 {
+            let client_id = ClientId::new();
+            let (client_sender, client_receiver) = client_channel();
+            shared_sessions.add(client_id, client_sender, None).await;
+            shared_sessions
+                .write_subscription(client_id, LobbyName::default(), Some(Subscription::default()))
+                .await
+                .expect("Client should be subscribed");
+            client_receivers.push(client_receiver);
+        }
+
+        // when
+        shared_sessions.broadcast(test_data::table_removed(), &[]).await;
+
+        // then
+        let frames: Vec<Frame> = client_receivers
+            .iter_mut()
+            .map(|client_receiver| client_receiver.try_recv().expect("Frame should be received"))
+            .collect();
+        assert!(frames.windows(2).all(|pair| Arc::ptr_eq(&pair[0].0, &pair[1].0)), "Frame should be shared");
+    }
+
+    #[tokio::test]
+    async fn need_tables_only_for_restricted_subscriptions() {
+        let shared_sessions = SharedSessions::new();
+        let client_id = ClientId::new();
+        let (client_sender, _client_receiver) = client_channel();
+        shared_sessions.add(client_id, client_sender, None).await;
+        shared_sessions
+            .write_subscription(client_id, LobbyName::default(), Some(Subscription::default()))
+            .await
+            .expect("Client should be subscribed");
+        let needs_tables_unrestricted = shared_sessions.needs_tables(&LobbyName::default()).await;
+
+        // when
+        let page = Some(TablePage { offset: 0, limit: 1 });
+        shared_sessions
+            .write_subscription(client_id, LobbyName::default(), Some(Subscription::new(Default::default(), page)))
+            .await
+            .expect("Client should be subscribed");
+        let needs_tables_restricted = shared_sessions.needs_tables(&LobbyName::default()).await;
+
+        // then
+        assert!(!needs_tables_unrestricted, "Tables should not be needed for unrestricted subscription");
+        assert!(needs_tables_restricted, "Tables should be needed for restricted subscription");
+    }
+
+    #[tokio::test]
+    async fn announce_output_to_authenticated_client_ids() {
+        let shared_sessions = SharedSessions::new();
+
+        let client_id_1 = ClientId::new();
+        let client_id_2 = ClientId::new();
+
+        let (client_sender_1, mut client_receiver_1) = client_channel();
+        let (client_sender_2, mut client_receiver_2) = client_channel();
+
+        let announced_output = test_data::announcement();
+
+        shared_sessions.add(client_id_1, client_sender_1, None).await;
+        shared_sessions.add(client_id_2, client_sender_2, None).await;
+
+        shared_sessions
+            .write_user(client_id_2, Some(test_data::username_user()), Some(UserType::User))
+            .await
+            .expect("Client #2 should be authenticated");
+
+        // when
+        let broadcast_result = shared_sessions
+            .announce(announced_output.clone(), AnnouncementAudience::Authenticated)
+            .await;
+
+        // then
+        assert_eq!(broadcast_result.success_client_ids, HashSet::from([client_id_2]));
+        assert!(broadcast_result.failure_client_ids.is_empty());
+
+        let received_output_1 = client_receiver_1.try_recv();
+        let received_output_2 = client_receiver_2
+            .recv()
+            .await
+            .expect("Output for client #2 should be received");
+
+        assert!(received_output_1.is_err(), "Output for client #1 should not be received");
+        assert_eq!(decode(received_output_2), announced_output);
     }
 
     #[tokio::test]
     async fn read_user_type_of_existing_client_id() {
         let shared_sessions = SharedSessions::new();
         let client_id = ClientId::new();
-        let (client_sender, _) = mpsc::unbounded_channel::<Output>();
-        shared_sessions.add(client_id, client_sender).await;
+        let (client_sender, _) = client_channel();
+        shared_sessions.add(client_id, client_sender, None).await;
 
         // when
         let result = shared_sessions.read_user_type(client_id).await;
@@ -257,16 +586,16 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn write_user_type_of_existing_client_id() {
+    async fn write_user_of_existing_client_id() {
         let shared_sessions = SharedSessions::new();
         let client_id = ClientId::new();
-        let (client_sender, _) = mpsc::unbounded_channel::<Output>();
+        let (client_sender, _) = client_channel();
         let written_user_type = UserType::Admin;
-        shared_sessions.add(client_id, client_sender).await;
+        shared_sessions.add(client_id, client_sender, None).await;
 
         // when
         let write_result = shared_sessions
-            .write_user_type(client_id, Some(written_user_type.clone()))
+            .write_user(client_id, Some(test_data::username_user()), Some(written_user_type.clone()))
             .await;
 
         // then
@@ -281,14 +610,239 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn not_write_user_type_of_existing_client_id() {
+    async fn not_write_user_of_missing_client_id() {
         let shared_sessions = SharedSessions::new();
         let client_id = ClientId::new();
 
         // when
-        let result = shared_sessions.write_user_type(client_id, Some(UserType::Admin)).await;
+        let result = shared_sessions
+            .write_user(client_id, Some(test_data::username_user()), Some(UserType::Admin))
+            .await;
 
         // then
         assert!(result.is_err(), "User type should not be written");
     }
+
+    #[tokio::test]
+    async fn list_sessions_with_sent_messages() {
+        let shared_sessions = SharedSessions::new();
+        let client_id_1 = ClientId::new();
+        let client_id_2 = ClientId::new();
+        let (client_sender_1, _client_receiver_1) = client_channel();
+        let (client_sender_2, _client_receiver_2) = client_channel();
+        shared_sessions.add(client_id_2, client_sender_2, None).await;
+        shared_sessions.add(client_id_1, client_sender_1, None).await;
+        shared_sessions
+            .send(client_id_2, test_data::pong())
+            .await
+            .expect("Output should be sent");
+
+        // when
+        let sessions = shared_sessions.list().await;
+
+        // then
+        let messages_sent: Vec<(ClientId, u64)> = sessions
+            .iter()
+            .map(|session| (session.client_id, session.messages_sent))
+            .collect();
+        assert_eq!(messages_sent, vec![(client_id_1, 0), (client_id_2, 1)]);
+    }
+
+    #[tokio::test]
+    async fn kick_existing_client_id() {
+        let shared_sessions = SharedSessions::new();
+        let client_id = ClientId::new();
+        let (client_sender, mut client_receiver) = client_channel();
+        shared_sessions.add(client_id, client_sender, None).await;
+
+        // when
+        let result = shared_sessions.kick(client_id, String::from("Spamming")).await;
+
+        // then
+        result.expect("Client should be kicked");
+        let received_output = client_receiver.recv().await.expect("Output should be received");
+        assert_eq!(decode(received_output), test_data::kicked());
+        assert!(client_receiver.recv().await.is_none(), "Client sender should be dropped");
+        assert!(shared_sessions.list().await.is_empty(), "Session should be removed");
+    }
+
+    #[tokio::test]
+    async fn notify_of_kicked_client_id() {
+        let (dropped_sender, mut dropped_receiver) = mpsc::unbounded_channel();
+        let shared_sessions = SharedSessions::notifying(dropped_sender);
+        let client_id = ClientId::new();
+        let (client_sender, _client_receiver) = client_channel();
+        shared_sessions.add(client_id, client_sender, None).await;
+
+        // when
+        let result = shared_sessions.kick(client_id, String::from("Spamming")).await;
+
+        // then
+        result.expect("Client should be kicked");
+        assert_eq!(dropped_receiver.try_recv(), Ok(client_id));
+    }
+
+    #[tokio::test]
+    async fn kick_client_with_full_queue() {
+        let (dropped_sender, mut dropped_receiver) = mpsc::unbounded_channel();
+        let shared_sessions = SharedSessions::notifying(dropped_sender);
+        let client_id = ClientId::new();
+        let (client_sender, _client_receiver) = client_channel();
+        for _ in 0..CLIENT_QUEUE_CAPACITY {
+            client_sender
+                .try_send(Frame::encode(&test_data::table_removed()).unwrap())
+                .expect("Frame should be queued");
+        }
+        shared_sessions.add(client_id, client_sender, None).await;
+
+        // when
+        let result = shared_sessions.kick(client_id, String::from("Spamming")).await;
+
+        // then
+        result.expect("Client should be kicked");
+        assert_eq!(dropped_receiver.try_recv(), Ok(client_id));
+        assert!(shared_sessions.list().await.is_empty(), "Session should be removed");
+    }
+
+    #[tokio::test]
+    async fn disconnect_lagging_client_id() {
+        let shared_sessions = SharedSessions::new();
+        let client_id_1 = ClientId::new();
+        let client_id_2 = ClientId::new();
+        let (client_sender_1, mut client_receiver_1) = client_channel();
+        let (client_sender_2, _client_receiver_2) = client_channel();
+        shared_sessions.add(client_id_1, client_sender_1, None).await;
+        shared_sessions.add(client_id_2, client_sender_2, None).await;
+        for client_id in [client_id_1, client_id_2] {
+            shared_sessions
+                .write_subscription(client_id, LobbyName::default(), Some(Subscription::default()))
+                .await
+                .expect("Client should be subscribed");
+        }
+        for _ in 0..CLIENT_QUEUE_CAPACITY {
+            shared_sessions.broadcast(test_data::table_removed(), &[]).await;
+            client_receiver_1.try_recv().expect("Frame should be received");
+        }
+
+        // when
+        let broadcast_result = shared_sessions.broadcast(test_data::table_removed(), &[]).await;
+
+        // then
+        assert_eq!(broadcast_result.success_client_ids, HashSet::from([client_id_1]));
+        assert_eq!(broadcast_result.failure_client_ids, HashSet::from([client_id_2]));
+        assert_eq!(broadcast_result.lagging_client_ids, HashSet::from([client_id_2]));
+        let client_ids: Vec<ClientId> = shared_sessions
+            .list()
+            .await
+            .iter()
+            .map(|session| session.client_id)
+            .collect();
+        assert_eq!(client_ids, vec![client_id_1], "Lagging session should be removed");
+    }
+
+    #[tokio::test]
+    async fn notify_of_dropped_lagging_client_id() {
+        let (dropped_sender, mut dropped_receiver) = mpsc::unbounded_channel();
+        let shared_sessions = SharedSessions::notifying(dropped_sender);
+        let client_id = ClientId::new();
+        let (client_sender, _client_receiver) = client_channel();
+        shared_sessions.add(client_id, client_sender, None).await;
+        shared_sessions
+            .write_subscription(client_id, LobbyName::default(), Some(Subscription::default()))
+            .await
+            .expect("Client should be subscribed");
+        for _ in 0..CLIENT_QUEUE_CAPACITY {
+            shared_sessions.broadcast(test_data::table_removed(), &[]).await;
+        }
+
+        // when
+        shared_sessions.broadcast(test_data::table_removed(), &[]).await;
+
+        // then
+        assert_eq!(dropped_receiver.try_recv(), Ok(client_id));
+        assert!(dropped_receiver.try_recv().is_err(), "Client should be dropped once");
+    }
+
+    /// Measures the cost of broadcasting to many subscribers, run it in release mode with:
+    ///
+    ///     cargo test --release benchmark_broadcast -- --ignored --nocapture
+    ///
+    /// Meanwhile, other clients keep connecting and disconnecting to measure how long they wait for broadcasts.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    #[ignore]
+    async fn benchmark_broadcast() {
+        const SUBSCRIBERS: usize = 10000;
+        const BROADCASTS: u32 = 100;
+
+        let shared_sessions = SharedSessions::new();
+        let mut client_receivers = vec![];
+        for _ in 0..SUBSCRIBERS {
+            let client_id = ClientId::new();
+            let (client_sender, client_receiver) = client_channel();
+            shared_sessions.add(client_id, client_sender, None).await;
+            shared_sessions
+                .write_subscription(client_id, LobbyName::default(), Some(Subscription::default()))
+                .await
+                .expect("Client should be subscribed");
+            client_receivers.push(client_receiver);
+        }
+        let output = test_data::table_updated();
+        let (churn_sender, mut churn_receiver) = tokio::sync::oneshot::channel::<()>();
+        let churn = tokio::task::spawn({
+            let shared_sessions = shared_sessions.clone();
+            async move {
+                let mut connections = 0;
+                let mut slowest = Duration::ZERO;
+                while churn_receiver.try_recv().is_err() {
+                    let client_id = ClientId::new();
+                    let (client_sender, _client_receiver) = client_channel();
+                    let started = Instant::now();
+                    shared_sessions.add(client_id, client_sender, None).await;
+                    shared_sessions.remove(client_id).await;
+                    slowest = slowest.max(started.elapsed());
+                    connections += 1;
+                    tokio::task::yield_now().await;
+                }
+                (connections, slowest)
+            }
+        });
+
+        // when
+        let started = Instant::now();
+        for _ in 0..BROADCASTS {
+            shared_sessions.broadcast(output.clone(), &[]).await;
+        }
+        let broadcast_elapsed = started.elapsed() / BROADCASTS;
+        churn_sender.send(()).expect("Churn should be running");
+        let (connections, slowest) = churn.await.expect("Churn should complete");
+
+        let started = Instant::now();
+        for _ in 0..SUBSCRIBERS {
+            Frame::encode(&output).expect("Output should be encoded");
+        }
+        let encode_elapsed = started.elapsed();
+
+        // Copy received frames into WebSocket messages the same way the connection of each client does
+        let started = Instant::now();
+        let mut messages = 0;
+        for client_receiver in client_receivers.iter_mut() {
+            while let Ok(frame) = client_receiver.try_recv() {
+                std::hint::black_box(Message::text(frame.as_ref()));
+                messages += 1;
+            }
+        }
+        let write_elapsed = started.elapsed() / BROADCASTS;
+
+        // then
+        println!(
+            "Broadcast to {} subscribers takes {:?} ({:?} per subscriber), while encoding per subscriber would add {:?}",
+            SUBSCRIBERS,
+            broadcast_elapsed,
+            broadcast_elapsed / SUBSCRIBERS as u32,
+            encode_elapsed,
+        );
+        println!("Copying the broadcast into WebSocket messages of all subscribers takes another {:?}", write_elapsed);
+        println!("Meanwhile {} clients connected and disconnected, the slowest taking {:?}", connections, slowest);
+        assert_eq!(messages, SUBSCRIBERS * BROADCASTS as usize, "All frames should be received");
+    }
 }
@@ -0,0 +1,315 @@
+use std::borrow::Cow;
+use std::sync::Arc;
+
+use crate::ordered_tables::TableSnapshot;
+use crate::protocol::{Output, Table, TableFilter, TableId, TablePage};
+
+/// Represents the table subscription of the client, which keeps track of tables visible to that client.
+#[derive(Clone, Debug, Default)]
+pub struct Subscription {
+    filter: TableFilter,
+    page: Option<TablePage>,
+    visible_ids: Vec<TableId>,
+}
+impl Subscription {
+    pub fn new(filter: TableFilter, page: Option<TablePage>) -> Self {
+        Subscription {
+            filter,
+            page,
+            visible_ids: vec![],
+        }
+    }
+
+    /// Returns whether the client can see only some of the tables, so that tables have to be selected for it.
+    pub fn is_restricted(&self) -> bool {
+        self.filter != TableFilter::default() || self.page.is_some()
+    }
+
+    /// Selects tables visible to the client among the given snapshot and remembers them as visible. Visible tables
+    /// are shared with the snapshot, which is returned as is, unless the subscription is restricted.
+    pub fn refresh(&mut self, tables: &TableSnapshot) -> TableSnapshot {
+        if !self.is_restricted() {
+            self.visible_ids.clear();
+            return tables.clone();
+        }
+        let visible_tables = self.select(tables);
+        self.visible_ids = visible_tables.iter().map(|table| table.id).collect();
+        visible_tables
+    }
+
+    fn select(&self, tables: &[Arc<Table>]) -> TableSnapshot {
+        self.visible(tables).cloned().collect()
+    }
+
+    fn visible<'a>(&'a self, tables: &'a [Arc<Table>]) -> impl Iterator<Item = &'a Arc<Table>> + 'a {
+        let (offset, limit) = match self.page {
+            Some(page) => (page.offset, page.limit),
+            None => (0, usize::MAX),
+        };
+        tables
+            .iter()
+            .filter(|table| self.filter.matches(table))
+            .skip(offset)
+            .take(limit)
+    }
+
+    /// Returns the output message, which should be sent to the client after the given change to tables.
+    /// The change itself is borrowed, so that it can be encoded once for all the clients.
+    ///
+    /// The change itself is sent only if it affects visible tables, while the set of visible tables remains
+    /// the same. Once the set of visible tables changes, the client receives them all in the table list.
+    /// Visible tables are compared by their ids, so that the table list is built only once it is sent.
+    ///
+    /// The batch is sent with changes to visible tables only, so that hidden tables never reach the client.
+    /// Once the table is put after the hidden one, the client receives the table list instead, as it cannot
+    /// find the place of the table.
+    pub fn track<'a>(&mut self, output: &'a Output, tables: &[Arc<Table>]) -> Option<Cow<'a, Output>> {
+        if !self.is_restricted() {
+            return Some(Cow::Borrowed(output));
+        }
+        let unchanged = self
+            .visible(tables)
+            .map(|table| table.id)
+            .eq(self.visible_ids.iter().copied());
+        if !unchanged {
+            return Some(Cow::Owned(self.table_list(output, tables)));
+        }
+        match output {
+            Output::BatchApplied { lobby, outputs } => {
+                let visible_outputs: Vec<&Output> = outputs.iter().filter(|output| self.is_visible(output)).collect();
+                if visible_outputs.is_empty() {
+                    None
+                } else if !visible_outputs.iter().all(|output| self.is_anchored_visibly(output)) {
+                    Some(Cow::Owned(self.table_list(output, tables)))
+                } else if visible_outputs.len() == outputs.len() {
+                    Some(Cow::Borrowed(output))
+                } else {
+                    Some(Cow::Owned(Output::BatchApplied {
+                        lobby: lobby.clone(),
+                        outputs: visible_outputs.into_iter().cloned().collect(),
+                    }))
+                }
+            }
+            _ if !self.is_visible(output) => None,
+            _ if !self.is_anchored_visibly(output) => Some(Cow::Owned(self.table_list(output, tables))),
+            _ => Some(Cow::Borrowed(output)),
+        }
+    }
+
+    fn table_list(&mut self, output: &Output, tables: &[Arc<Table>]) -> Output {
+        let visible_tables = self.select(tables);
+        self.visible_ids = visible_tables.iter().map(|table| table.id).collect();
+        Output::TableList {
+            lobby: output.lobby().cloned().unwrap_or_default(),
+            tables: visible_tables,
+        }
+    }
+
+    /// Returns whether the change affects any visible table.
+    fn is_visible(&self, output: &Output) -> bool {
+        output.table_ids().iter().any(|id| self.visible_ids.contains(id))
+    }
+
+    /// Returns whether the table is put either in front or after the visible table, if it is put anywhere.
+    fn is_anchored_visibly(&self, output: &Output) -> bool {
+        match output {
+            Output::TableAdded { after_id, .. } | Output::TableMoved { after_id, .. } => {
+                *after_id == TableId::ABSENT || self.visible_ids.contains(after_id)
+            }
+            _ => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::borrow::Cow;
+    use std::sync::Arc;
+
+    use crate::ordered_tables::TableSnapshot;
+    use crate::protocol::{test_data, LobbyName, Output, TableChanges, TableFilter, TablePage};
+
+    use super::Subscription;
+
+    fn tables() -> TableSnapshot {
+        Arc::from([
+            Arc::new(test_data::table_james_bond()),
+            Arc::new(test_data::table_mission_impossible()),
+            Arc::new(test_data::table_foo_fighters()),
+        ])
+    }
+
+    #[test]
+    fn refresh_filtered_and_paginated_tables() {
+        let filter = TableFilter {
+            participants_min: Some(5),
+            ..TableFilter::default()
+        };
+        let mut subscription = Subscription::new(filter, Some(TablePage { offset: 1, limit: 1 }));
+
+        // when
+        let visible_tables = subscription.refresh(&tables());
+
+        // then
+        assert_eq!(visible_tables.as_ref(), [Arc::new(test_data::table_mission_impossible())]);
+    }
+
+    #[test]
+    fn share_snapshot_when_unrestricted() {
+        let mut subscription = Subscription::default();
+        let tables = tables();
+
+        // when
+        let visible_tables = subscription.refresh(&tables);
+
+        // then
+        assert!(Arc::ptr_eq(&visible_tables, &tables), "Snapshot should be shared");
+    }
+
+    #[test]
+    fn track_all_changes_when_unrestricted() {
+        let mut subscription = Subscription::default();
+        subscription.refresh(&tables());
+
+        // when
+        let output = subscription
+            .track(&test_data::table_removed(), &tables())
+            .map(Cow::into_owned);
+
+        // then
+        assert_eq!(output, Some(test_data::table_removed()));
+    }
+
+    #[test]
+    fn track_changes_of_visible_tables() {
+        let filter = TableFilter {
+            name_prefix: Some(String::from("Foo")),
+            ..TableFilter::default()
+        };
+        let mut subscription = Subscription::new(filter, None);
+        subscription.refresh(&tables());
+
+        // when
+        let output = subscription
+            .track(&test_data::table_updated(), &tables())
+            .map(Cow::into_owned);
+
+        // then
+        assert_eq!(output, Some(test_data::table_updated()));
+    }
+
+    #[test]
+    fn not_track_changes_of_invisible_tables() {
+        let filter = TableFilter {
+            name_prefix: Some(String::from("James")),
+            ..TableFilter::default()
+        };
+        let mut subscription = Subscription::new(filter, None);
+        subscription.refresh(&tables());
+
+        // when
+        let output = subscription
+            .track(&test_data::table_updated(), &tables())
+            .map(Cow::into_owned);
+
+        // then
+        assert_eq!(output, None);
+    }
+
+    #[test]
+    fn track_change_itself_when_visible_tables_remain() {
+        let mut subscription = Subscription::new(TableFilter::default(), Some(TablePage { offset: 2, limit: 1 }));
+        subscription.refresh(&tables());
+        let output = test_data::table_updated();
+
+        // when
+        let tracked_output = subscription.track(&output, &tables());
+
+        // then
+        assert!(matches!(tracked_output, Some(Cow::Borrowed(_))), "Change itself should be sent without table list");
+    }
+
+    #[test]
+    fn track_only_changes_of_visible_tables_in_batch() {
+        let filter = TableFilter {
+            name_prefix: Some(String::from("Foo")),
+            ..TableFilter::default()
+        };
+        let mut subscription = Subscription::new(filter, None);
+        subscription.refresh(&tables());
+        let hidden_change = Output::TablePatched {
+            lobby: LobbyName::default(),
+            id: test_data::table_james_bond().id,
+            changes: TableChanges {
+                capacity: Some(Some(20)),
+                ..TableChanges::default()
+            },
+        };
+
+        // when
+        let output = subscription
+            .track(
+                &Output::BatchApplied {
+                    lobby: LobbyName::default(),
+                    outputs: vec![test_data::table_updated(), hidden_change],
+                },
+                &tables(),
+            )
+            .map(Cow::into_owned);
+
+        // then
+        let expected_output = Output::BatchApplied {
+            lobby: LobbyName::default(),
+            outputs: vec![test_data::table_updated()],
+        };
+        assert_eq!(output, Some(expected_output));
+    }
+
+    #[test]
+    fn track_table_list_when_table_moved_after_invisible_table() {
+        let filter = TableFilter {
+            name_prefix: Some(String::from("Foo")),
+            ..TableFilter::default()
+        };
+        let mut subscription = Subscription::new(filter, None);
+        subscription.refresh(&tables());
+
+        // when
+        let output = subscription
+            .track(
+                &Output::TableMoved {
+                    lobby: LobbyName::default(),
+                    id: test_data::table_foo_fighters().id,
+                    after_id: test_data::table_mission_impossible().id,
+                },
+                &tables(),
+            )
+            .map(Cow::into_owned);
+
+        // then
+        let expected_output = Output::TableList {
+            lobby: LobbyName::default(),
+            tables: Arc::from(&tables()[2..]),
+        };
+        assert_eq!(output, Some(expected_output));
+    }
+
+    #[test]
+    fn track_table_list_when_visible_tables_change() {
+        let mut subscription = Subscription::new(TableFilter::default(), Some(TablePage { offset: 0, limit: 2 }));
+        subscription.refresh(&Arc::from(&tables()[1..]));
+
+        // when
+        let output = subscription
+            .track(&test_data::table_added(), &tables())
+            .map(Cow::into_owned);
+
+        // then
+        let expected_output = Output::TableList {
+            lobby: LobbyName::default(),
+            tables: Arc::from(&tables()[..2]),
+        };
+        assert_eq!(output, Some(expected_output));
+    }
+}
@@ -0,0 +1,433 @@
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::protocol::TableId;
+
+/// Represents the generator of unique table ids.
+pub trait TableIdGenerator: Send + Sync {
+    fn generate(&self) -> Result<TableId, String>;
+}
+
+/// Represents the table id generator that can be shared among all the lobbies.
+pub type SharedTableIdGenerator = Arc<dyn TableIdGenerator>;
+
+/// Represents the configuration of table ids, which can be parsed from one of:
+///
+/// - `sequential` for numeric ids starting from 1 on every start, which is the default;
+/// - `persisted:<path>` for numeric ids continuing from the counter persisted in the given file;
+/// - `ulid` for ULIDs, which are unique across instances without any coordination;
+/// - `snowflake:<node>` for numeric ids prefixed with the given node between 0 and 1023, which is unique
+///   for each instance.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum TableIdConfig {
+    #[default]
+    Sequential,
+    Persisted(PathBuf),
+    Ulid,
+    Snowflake(u16),
+}
+impl TableIdConfig {
+    pub fn build(&self) -> Result<SharedTableIdGenerator, String> {
+        Ok(match self {
+            TableIdConfig::Sequential => Arc::new(SequentialIds::new()),
+            TableIdConfig::Persisted(path) => Arc::new(PersistedIds::open(path.clone())?),
+            TableIdConfig::Ulid => Arc::new(UlidIds),
+            TableIdConfig::Snowflake(node) => Arc::new(SnowflakeIds::new(*node)?),
+        })
+    }
+}
+impl FromStr for TableIdConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "sequential" => Ok(TableIdConfig::Sequential),
+            None if s == "ulid" => Ok(TableIdConfig::Ulid),
+            Some(("persisted", path)) if !path.is_empty() => Ok(TableIdConfig::Persisted(PathBuf::from(path))),
+            Some(("snowflake", node)) => match node.parse() {
+                Ok(node) if node <= SNOWFLAKE_MAX_NODE => Ok(TableIdConfig::Snowflake(node)),
+                _ => Err(format!("Snowflake node {:?} should be between 0 and {}", node, SNOWFLAKE_MAX_NODE)),
+            },
+            _ => Err(format!("Unknown table id configuration {:?}", s)),
+        }
+    }
+}
+
+/// Generates numeric ids starting from 1, which are unique only within the running instance.
+pub struct SequentialIds {
+    next: AtomicI64,
+}
+impl SequentialIds {
+    pub fn new() -> Self {
+        SequentialIds {
+            next: AtomicI64::new(1),
+        }
+    }
+}
+impl Default for SequentialIds {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl TableIdGenerator for SequentialIds {
+    fn generate(&self) -> Result<TableId, String> {
+        Ok(TableId::new(self.next.fetch_add(1, Ordering::Relaxed) as i128))
+    }
+}
+
+/// The number of ids reserved at once by persisting the counter.
+const PERSISTED_BLOCK: i64 = 1000;
+
+/// Generates numeric ids, which remain unique across restarts of the instance.
+///
+/// Ids are reserved in blocks by persisting the counter past the block, so that the file is written only once
+/// per block. The next block is reserved in the background once half of the current one is used, so that
+/// generating ids never waits for the file. Once ids are used faster than the file is written, generating fails
+/// until the next block is reserved, so that the table is rejected and can be added again. Ids of the block left
+/// unused are skipped after the restart.
+pub struct PersistedIds {
+    counter: Arc<PersistedCounter>,
+}
+impl PersistedIds {
+    /// Opens the counter and reserves the first block, so that the counter cannot roll back after the restart.
+    pub fn open(path: PathBuf) -> Result<Self, String> {
+        let next = match fs::read_to_string(&path) {
+            Ok(content) => content
+                .trim()
+                .parse()
+                .map_err(|e| format!("Failed to parse table id counter in {:?}: {}", path, e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => 1,
+            Err(e) => return Err(format!("Failed to read table id counter from {:?}: {}", path, e)),
+        };
+        let counter = PersistedCounter {
+            path,
+            state: Mutex::new(PersistedState {
+                next,
+                reserved_until: next,
+                reserving: false,
+                error: None,
+            }),
+        };
+        counter.persist(next + PERSISTED_BLOCK)?;
+        counter.state.lock().map_err(|e| e.to_string())?.reserved_until = next + PERSISTED_BLOCK;
+        Ok(PersistedIds {
+            counter: Arc::new(counter),
+        })
+    }
+}
+impl TableIdGenerator for PersistedIds {
+    fn generate(&self) -> Result<TableId, String> {
+        let (id, reserving_until) = {
+            let mut state = self.counter.state.lock().map_err(|e| e.to_string())?;
+            let reserving_until = if !state.reserving && state.reserved_until - state.next <= PERSISTED_BLOCK / 2 {
+                state.reserving = true;
+                Some(state.reserved_until + PERSISTED_BLOCK)
+            } else {
+                None
+            };
+            let id = if state.next < state.reserved_until {
+                state.next += 1;
+                Ok(TableId::new(state.next as i128 - 1))
+            } else {
+                // Ids are used up before the next block is reserved, so fail instead of waiting for the file
+                Err(state
+                    .error
+                    .take()
+                    .unwrap_or_else(|| String::from("Table ids are used up until the next block is reserved")))
+            };
+            (id, reserving_until)
+        };
+        if let Some(reserved_until) = reserving_until {
+            let counter = self.counter.clone();
+            thread::spawn(move || counter.reserve(reserved_until));
+        }
+        id
+    }
+}
+
+/// Represents the counter persisted in the file, which is shared with the thread reserving the next block.
+struct PersistedCounter {
+    path: PathBuf,
+    state: Mutex<PersistedState>,
+}
+impl PersistedCounter {
+    /// Persists the counter without holding the state, so that ids of the current block are still generated.
+    fn reserve(&self, reserved_until: i64) {
+        let result = self.persist(reserved_until);
+        if let Ok(mut state) = self.state.lock() {
+            state.reserving = false;
+            match result {
+                Ok(()) => {
+                    state.reserved_until = reserved_until;
+                    state.error = None;
+                }
+                Err(e) => {
+                    error!("{}", e);
+                    state.error = Some(e);
+                }
+            }
+        }
+    }
+
+    /// Writes the counter into the temporary file first, so that the counter is never left half-written.
+    /// Both the file and its directory are synced, so that the counter never rolls back after the crash.
+    fn persist(&self, counter: i64) -> Result<(), String> {
+        let temporary_path = self.path.with_extension("tmp");
+        let directory = match self.path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        File::create(&temporary_path)
+            .and_then(|mut file| {
+                file.write_all(counter.to_string().as_bytes())?;
+                file.sync_all()
+            })
+            .and_then(|_| fs::rename(&temporary_path, &self.path))
+            .and_then(|_| File::open(directory)?.sync_all())
+            .map_err(|e| format!("Failed to write table id counter to {:?}: {}", self.path, e))
+    }
+}
+
+struct PersistedState {
+    next: i64,
+    /// The end of the reserved block, which is exclusive.
+    reserved_until: i64,
+    /// Whether the next block is being reserved in the background.
+    reserving: bool,
+    /// The error of the last reservation, which is reported once ids are used up.
+    error: Option<String>,
+}
+
+/// Generates ULIDs, which consist of 48 bits of the current time in milliseconds and 80 random bits.
+pub struct UlidIds;
+impl TableIdGenerator for UlidIds {
+    fn generate(&self) -> Result<TableId, String> {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis()
+            & ((1 << 48) - 1);
+        let random = rand::random::<u128>() & ((1 << 80) - 1);
+        Ok(TableId::new((millis << 80 | random) as i128))
+    }
+}
+
+/// The start of snowflake time, which is 2023-01-01T00:00:00Z.
+const SNOWFLAKE_EPOCH: Duration = Duration::from_secs(1672531200);
+
+const SNOWFLAKE_NODE_BITS: u32 = 10;
+const SNOWFLAKE_SEQUENCE_BITS: u32 = 12;
+const SNOWFLAKE_MAX_NODE: u16 = (1 << SNOWFLAKE_NODE_BITS) - 1;
+const SNOWFLAKE_MAX_SEQUENCE: i64 = (1 << SNOWFLAKE_SEQUENCE_BITS) - 1;
+
+/// Generates numeric snowflake ids, which consist of 41 bits of milliseconds since snowflake epoch, 10 bits
+/// of the node and 12 bits of the sequence within the millisecond.
+///
+/// Once the sequence is exhausted or the clock goes backwards, ids continue from the last millisecond
+/// instead of waiting, so they always increase.
+pub struct SnowflakeIds {
+    node: u16,
+    /// The millisecond of the last id along with its sequence.
+    state: Mutex<(i64, i64)>,
+}
+impl SnowflakeIds {
+    pub fn new(node: u16) -> Result<Self, String> {
+        if node > SNOWFLAKE_MAX_NODE {
+            return Err(format!("Snowflake node {} should be between 0 and {}", node, SNOWFLAKE_MAX_NODE));
+        }
+        Ok(SnowflakeIds {
+            node,
+            state: Mutex::new((0, 0)),
+        })
+    }
+}
+impl TableIdGenerator for SnowflakeIds {
+    fn generate(&self) -> Result<TableId, String> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH + SNOWFLAKE_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as i64;
+        let mut state = self.state.lock().map_err(|e| e.to_string())?;
+        let (last, sequence) = *state;
+        *state = if now > last {
+            (now, 0)
+        } else if sequence < SNOWFLAKE_MAX_SEQUENCE {
+            (last, sequence + 1)
+        } else {
+            (last + 1, 0)
+        };
+        let (millis, sequence) = *state;
+        let id = millis << (SNOWFLAKE_NODE_BITS + SNOWFLAKE_SEQUENCE_BITS)
+            | (self.node as i64) << SNOWFLAKE_SEQUENCE_BITS
+            | sequence;
+        Ok(TableId::new(id as i128))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::collections::HashSet;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::protocol::TableId;
+
+    use super::{PersistedIds, SequentialIds, SnowflakeIds, TableIdConfig, TableIdGenerator, UlidIds, PERSISTED_BLOCK};
+
+    fn generate(generator: &dyn TableIdGenerator, count: usize) -> Vec<TableId> {
+        (0..count)
+            .map(|_| generator.generate().expect("Table id should be generated"))
+            .collect()
+    }
+
+    fn assert_unique(ids: &[TableId]) {
+        let unique_ids: HashSet<&TableId> = ids.iter().collect();
+        assert_eq!(unique_ids.len(), ids.len(), "Table ids should be unique");
+        assert!(!ids.contains(&TableId::ABSENT), "Table ids should not be absent");
+    }
+
+    #[test]
+    fn parse_table_id_configs() {
+        let configs: Vec<Result<TableIdConfig, String>> = [
+            "sequential",
+            "persisted:/var/lib/lobby/table_ids",
+            "ulid",
+            "snowflake:7",
+            "snowflake:1024",
+            "uuid",
+        ]
+        .into_iter()
+        .map(str::parse)
+        .collect();
+
+        // then
+        assert_eq!(configs[0], Ok(TableIdConfig::Sequential));
+        assert_eq!(configs[1], Ok(TableIdConfig::Persisted(PathBuf::from("/var/lib/lobby/table_ids"))));
+        assert_eq!(configs[2], Ok(TableIdConfig::Ulid));
+        assert_eq!(configs[3], Ok(TableIdConfig::Snowflake(7)));
+        assert!(configs[4].is_err(), "Snowflake node should be out of range");
+        assert!(configs[5].is_err(), "Config should be unknown");
+    }
+
+    #[test]
+    fn generate_sequential_ids() {
+        // when
+        let ids = generate(&SequentialIds::new(), 3);
+
+        // then
+        assert_eq!(ids, vec![TableId::new(1), TableId::new(2), TableId::new(3)]);
+    }
+
+    /// Generates persisted ids, retrying while ids are used up until the next block is reserved.
+    fn generate_persisted(generator: &PersistedIds, count: usize) -> Vec<TableId> {
+        (0..count)
+            .map(|_| loop {
+                match generator.generate() {
+                    Ok(id) => break id,
+                    Err(_) => thread::sleep(Duration::from_millis(1)),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn continue_persisted_ids_after_restart() {
+        let path = std::env::temp_dir().join(format!("lobby_table_ids_{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let ids_before_restart = generate(&PersistedIds::open(path.clone()).expect("Counter should open"), 3);
+
+        // when
+        let ids_after_restart = generate(&PersistedIds::open(path.clone()).expect("Counter should reopen"), 3);
+
+        // then
+        fs::remove_file(&path).expect("Counter should be persisted");
+        assert_eq!(ids_before_restart, vec![TableId::new(1), TableId::new(2), TableId::new(3)]);
+        assert_eq!(ids_after_restart[0], TableId::new(PERSISTED_BLOCK as i128 + 1));
+    }
+
+    #[test]
+    fn reserve_next_blocks_of_persisted_ids_ahead() {
+        let path = std::env::temp_dir().join(format!("lobby_table_ids_ahead_{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let generator = PersistedIds::open(path.clone()).expect("Counter should open");
+
+        // when
+        let ids = generate_persisted(&generator, 3 * PERSISTED_BLOCK as usize);
+
+        // then
+        while generator
+            .counter
+            .state
+            .lock()
+            .expect("State should be locked")
+            .reserving
+        {
+            thread::sleep(Duration::from_millis(1));
+        }
+        let ids_after_restart = generate(&PersistedIds::open(path.clone()).expect("Counter should reopen"), 1);
+        fs::remove_file(&path).expect("Counter should be persisted");
+        assert_unique(&ids);
+        assert_eq!(ids.last(), Some(&TableId::new(3 * PERSISTED_BLOCK as i128)));
+        assert!(!ids.contains(&ids_after_restart[0]), "Ids should not be reused after restart");
+    }
+
+    #[test]
+    fn reject_persisted_id_once_block_is_used_up_before_next_one_is_reserved() {
+        let path = std::env::temp_dir().join(format!("lobby_table_ids_used_up_{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let generator = PersistedIds::open(path.clone()).expect("Counter should open");
+        // Pretend the next block is still being reserved
+        generator
+            .counter
+            .state
+            .lock()
+            .expect("State should be locked")
+            .reserving = true;
+        let ids = generate(&generator, PERSISTED_BLOCK as usize);
+
+        // when
+        let id = generator.generate();
+
+        // then
+        fs::remove_file(&path).expect("Counter should be persisted");
+        assert_eq!(ids.last(), Some(&TableId::new(PERSISTED_BLOCK as i128)));
+        assert!(id.is_err(), "Id should not be generated, but was {:?}", id);
+    }
+
+    #[test]
+    fn generate_unique_ulids() {
+        // when
+        let ids = generate(&UlidIds, 1000);
+
+        // then
+        assert_unique(&ids);
+        let value = serde_json::to_value(ids[0]).expect("ULID should be serialized");
+        assert!(value.is_string(), "ULID should be sent as string, but was {}", value);
+    }
+
+    #[test]
+    fn generate_increasing_snowflake_ids_of_node() {
+        let generator = SnowflakeIds::new(7).expect("Node should be valid");
+
+        // when
+        let ids = generate(&generator, 10000);
+
+        // then
+        let values: Vec<i64> = ids
+            .iter()
+            .map(|id| serde_json::to_value(id).ok().and_then(|value| value.as_i64()))
+            .map(|value| value.expect("Snowflake id should be numeric"))
+            .collect();
+        assert!(values.windows(2).all(|pair| pair[0] < pair[1]), "Snowflake ids should increase");
+        assert!(values.iter().all(|value| value >> 12 & 1023 == 7), "Snowflake ids should contain node");
+    }
+}
@@ -0,0 +1,256 @@
+use serde::Deserialize;
+
+use crate::protocol::{FieldError, Table, TableField};
+
+/// Represents the configurable rules, which tables must satisfy to be added or updated.
+///
+/// Rules can be deserialized from JSON, e.g. `{"name_max_length": 32, "name_unique": false}`, where omitted
+/// rules keep their default values.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct TableRules {
+    pub name_min_length: usize,
+    pub name_max_length: usize,
+    /// Characters allowed in names, otherwise any character except control ones is allowed.
+    pub name_allowed_chars: Option<String>,
+    pub name_unique: bool,
+    pub capacity_min: Option<u64>,
+    /// The maximum capacity, which also rules out tables of unlimited capacity.
+    pub capacity_max: Option<u64>,
+}
+impl Default for TableRules {
+    fn default() -> Self {
+        TableRules {
+            name_min_length: 1,
+            name_max_length: 64,
+            name_allowed_chars: None,
+            name_unique: true,
+            capacity_min: None,
+            capacity_max: None,
+        }
+    }
+}
+impl TableRules {
+    /// Validates the table against these rules, taking into account whether any other table in the lobby
+    /// already has the same name.
+    pub fn validate(&self, table: &Table, name_taken: bool) -> Result<(), Vec<FieldError>> {
+        let mut errors = Vec::new();
+
+        let name = table.name.as_ref();
+        let name_length = name.chars().count();
+        if name_length < self.name_min_length || name_length > self.name_max_length {
+            errors.push(FieldError::new(
+                TableField::Name,
+                format!(
+                    "Name length should be between {} and {} characters",
+                    self.name_min_length, self.name_max_length
+                ),
+            ));
+        }
+        if let Some(c) = name.chars().find(|&c| !self.is_allowed_in_name(c)) {
+            errors.push(FieldError::new(TableField::Name, format!("Name should not contain character {:?}", c)));
+        }
+        if self.name_unique && name_taken {
+            errors.push(FieldError::new(TableField::Name, String::from("Name should be unique")));
+        }
+
+        if let Some(capacity) = table.capacity {
+            if table.participants > capacity {
+                errors.push(FieldError::new(
+                    TableField::Capacity,
+                    String::from("Capacity should not be less than participants"),
+                ));
+            }
+        }
+        if let (Some(capacity), Some(capacity_min)) = (table.capacity, self.capacity_min) {
+            if capacity < capacity_min {
+                errors.push(FieldError::new(
+                    TableField::Capacity,
+                    format!("Capacity should be at least {}", capacity_min),
+                ));
+            }
+        }
+        if let Some(capacity_max) = self.capacity_max {
+            if table.capacity.is_none_or(|capacity| capacity > capacity_max) {
+                errors.push(FieldError::new(
+                    TableField::Capacity,
+                    format!("Capacity should be at most {}", capacity_max),
+                ));
+            }
+        }
+
+        if let (Some(opens_at), Some(closes_at)) = (table.opens_at, table.closes_at) {
+            if closes_at <= opens_at {
+                errors
+                    .push(FieldError::new(TableField::ClosesAt, String::from("Close time should be after open time")));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn is_allowed_in_name(&self, c: char) -> bool {
+        match &self.name_allowed_chars {
+            Some(allowed_chars) => allowed_chars.contains(c),
+            None => !c.is_control(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::protocol::{test_data, Table, TableField, TableName};
+
+    use super::TableRules;
+
+    #[test]
+    fn accept_valid_table() {
+        let rules = TableRules::default();
+
+        // when
+        let result = rules.validate(&test_data::table_foo_fighters(), false);
+
+        // then
+        result.expect("Table should be valid");
+    }
+
+    #[test]
+    fn accept_table_with_duplicate_name_when_names_are_not_unique() {
+        let rules = TableRules {
+            name_unique: false,
+            ..TableRules::default()
+        };
+
+        // when
+        let result = rules.validate(&test_data::table_james_bond(), true);
+
+        // then
+        result.expect("Table should be valid");
+    }
+
+    #[test]
+    fn deserialize_rules_keeping_omitted_ones() {
+        // when
+        let rules: TableRules =
+            serde_json::from_str(r#"{"name_max_length": 3, "name_unique": false}"#).expect("Rules should be parsed");
+
+        // then
+        assert_eq!(rules.name_max_length, 3);
+        assert!(!rules.name_unique, "Names should not be unique");
+        assert_eq!(rules.name_min_length, TableRules::default().name_min_length);
+        let errors = rules
+            .validate(&test_data::table_foo_fighters(), true)
+            .expect_err("Table should be invalid");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].field, TableField::Name);
+    }
+
+    #[test]
+    fn reject_table_with_invalid_name() {
+        let rules = TableRules::default();
+        let table = Table {
+            name: TableName::new(String::from("")),
+            ..test_data::table_foo_fighters()
+        };
+
+        // when
+        let result = rules.validate(&table, false);
+
+        // then
+        let errors = result.expect_err("Table should be invalid");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].field, TableField::Name);
+    }
+
+    #[test]
+    fn reject_table_with_duplicate_name() {
+        let rules = TableRules::default();
+        let table = Table {
+            name: test_data::table_james_bond().name,
+            ..test_data::table_foo_fighters()
+        };
+
+        // when
+        let result = rules.validate(&table, true);
+
+        // then
+        let errors = result.expect_err("Table should be invalid");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].field, TableField::Name);
+    }
+
+    #[test]
+    fn reject_table_with_participants_over_capacity() {
+        let rules = TableRules::default();
+        let table = Table {
+            capacity: Some(3),
+            ..test_data::table_foo_fighters()
+        };
+
+        // when
+        let result = rules.validate(&table, false);
+
+        // then
+        let errors = result.expect_err("Table should be invalid");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].field, TableField::Capacity);
+    }
+
+    #[test]
+    fn reject_table_with_capacity_out_of_configured_bounds() {
+        let rules: TableRules =
+            serde_json::from_str(r#"{"capacity_min": 2, "capacity_max": 8}"#).expect("Rules should be parsed");
+        let tables = [Some(1), Some(9), None].map(|capacity| Table {
+            capacity,
+            participants: 0,
+            ..test_data::table_foo_fighters()
+        });
+
+        // when
+        let results = tables.map(|table| rules.validate(&table, false));
+
+        // then
+        for result in results {
+            let errors = result.expect_err("Table should be invalid");
+            assert_eq!(errors.len(), 1);
+            assert_eq!(errors[0].field, TableField::Capacity);
+        }
+    }
+
+    #[test]
+    fn reject_table_with_name_of_characters_not_configured_as_allowed() {
+        let rules: TableRules = serde_json::from_str(r#"{"name_allowed_chars": "abcdefghijklmnopqrstuvwxyz "}"#)
+            .expect("Rules should be parsed");
+
+        // when
+        let result = rules.validate(&test_data::table_foo_fighters(), false);
+
+        // then
+        let errors = result.expect_err("Table should be invalid");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].field, TableField::Name);
+    }
+
+    #[test]
+    fn reject_table_with_invalid_name_and_capacity() {
+        let rules = TableRules::default();
+        let table = Table {
+            name: TableName::new(String::from("Foo\nFighters")),
+            capacity: Some(3),
+            ..test_data::table_foo_fighters()
+        };
+
+        // when
+        let result = rules.validate(&table, false);
+
+        // then
+        let errors = result.expect_err("Table should be invalid");
+        let fields: Vec<TableField> = errors.into_iter().map(|error| error.field).collect();
+        assert_eq!(fields, vec![TableField::Name, TableField::Capacity]);
+    }
+}
@@ -1,64 +1,211 @@
 use futures_util::{SinkExt, StreamExt, TryFutureExt};
+use serde::Deserialize;
 use serde_json::Error as SerdeError;
+use std::future::Future;
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::mpsc;
-use tokio_stream::wrappers::UnboundedReceiverStream;
+use tokio_stream::wrappers::ReceiverStream;
+use warp::http::StatusCode;
+use warp::reply::{self, Json, WithStatus};
 use warp::ws::{Message, WebSocket, Ws};
 use warp::Filter;
 
-use crate::lobby::SharedLobby;
-use crate::protocol::{Input, Output};
+use crate::audit::SharedAuditLog;
+use crate::idempotency::SharedIdempotencyKeys;
+use crate::lobby::{Seating, SharedLobbies};
+use crate::ordered_tables::TableSnapshot;
+use crate::permission::Permissions;
+use crate::protocol::{
+    AnnouncementAudience, AnnouncementSeverity, AuditEntry, ClientId, Input, InputDiscriminants, LobbyName, Output,
+    Password, Timestamp, Username,
+};
 use crate::service::ClientSessionAction::*;
-use crate::service::{self, ClientId, ClientSessionAction};
-use crate::session::SharedSessions;
+use crate::service::{self, ClientSessionAction, ServiceContext};
+use crate::session::{self, SharedSessions};
+use crate::table_id::TableIdConfig;
+use crate::validation::TableRules;
 
 /// Starts WebSocket server at the given address and awaits indenifitely.
-pub async fn run(address: impl Into<SocketAddr>) {
-    // Keep track of all connected clients
-    let sessions = SharedSessions::new();
+pub async fn run(
+    address: impl Into<SocketAddr>,
+    table_ids: TableIdConfig,
+    rules: TableRules,
+    trash_retention: Duration,
+    permissions: Permissions,
+    idempotency_window: Duration,
+) {
+    let (_, server) = bind(address, table_ids, rules, trash_retention, permissions, idempotency_window);
+    server.await;
+}
+
+/// Binds WebSocket server to the given address and returns the actual address, which is useful once
+/// the port is `0`, along with the future that serves connections once awaited. Tables should satisfy
+/// the given rules, removed tables can be restored for the given trash retention, users are allowed to send
+/// input messages according to the given permissions and idempotency keys of changes are remembered
+/// for the given window.
+///
+/// Must be called within the Tokio runtime, as it spawns the background scheduler. Panics once the address
+/// cannot be bound or the table id generator cannot be built.
+pub fn bind(
+    address: impl Into<SocketAddr>,
+    table_ids: TableIdConfig,
+    rules: TableRules,
+    trash_retention: Duration,
+    permissions: Permissions,
+    idempotency_window: Duration,
+) -> (SocketAddr, impl Future<Output = ()>) {
+    // Keep track of all connected clients, freeing seats of the ones dropped by the server
+    let (dropped_sender, dropped_receiver) = mpsc::unbounded_channel();
+    let sessions = SharedSessions::notifying(dropped_sender);
+
+    // Keep track of the lobbies, validating tables and generating their ids as configured
+    let table_ids = table_ids
+        .build()
+        .unwrap_or_else(|e| panic!("Failed to build table id generator: {}", e));
+    let lobbies = SharedLobbies::prepopulated(rules, table_ids, trash_retention);
+
+    // Share input messages each user type is allowed to send
+    let permissions = Arc::new(permissions);
+
+    // Keep track of administrative actions
+    let audit_log = SharedAuditLog::new();
+
+    // Remember outputs of changes, so that retried changes are not applied twice
+    let idempotency_keys = SharedIdempotencyKeys::new(idempotency_window);
+
+    // Open and close scheduled tables in the background
+    tokio::task::spawn(schedule(sessions.clone(), lobbies.clone()));
+
+    // Free seats of dropped clients in the background, as their connections may remain open for a while
+    tokio::task::spawn(release_dropped_clients(dropped_receiver, sessions.clone(), lobbies.clone()));
+
+    let context = ServiceContext {
+        permissions: permissions.clone(),
+        sessions: sessions.clone(),
+        lobbies,
+        audit_log: audit_log.clone(),
+        idempotency_keys,
+    };
+
+    let context = warp::any().map(move || context.clone());
+    let permissions = warp::any().map(move || permissions.clone());
     let sessions = warp::any().map(move || sessions.clone());
+    let audit_log = warp::any().map(move || audit_log.clone());
 
-    // Keep track of the lobby
-    let lobby = SharedLobby::prepopulated();
-    let lobby = warp::any().map(move || lobby.clone());
+    let lobby_api = warp::path("lobby_api")
+        .and(warp::ws())
+        .and(warp::addr::remote())
+        .and(context)
+        .map(|ws: Ws, remote_address: Option<SocketAddr>, context: ServiceContext| {
+            ws.on_upgrade(move |ws| handle_connect(ws, remote_address, context))
+        });
 
-    let routes = warp::path("lobby_api").and(warp::ws()).and(sessions).and(lobby).map(
-        |ws: Ws, sessions: SharedSessions, lobby: SharedLobby| {
-            ws.on_upgrade(move |ws| handle_connect(ws, sessions, lobby))
-        },
-    );
-    warp::serve(routes).run(address).await;
+    // Let ops scripts send announcements over plain HTTP
+    let announcements = warp::path("announcements")
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(warp::addr::remote())
+        .and(permissions)
+        .and(sessions)
+        .and(audit_log)
+        .then(handle_announce);
+
+    let routes = lobby_api.or(announcements);
+    warp::serve(routes).bind_ephemeral(address)
+}
+
+/// Updates statuses of scheduled tables every second and broadcasts the changes just like admin updates.
+/// Removed tables, which can no longer be restored, are forgotten along the way.
+async fn schedule(sessions: SharedSessions, lobbies: SharedLobbies) {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        for (lobby, shared_lobby) in lobbies.read_all().await {
+            let _change_guard = shared_lobby.lock_changes().await;
+            let seatings = shared_lobby.update_statuses(Timestamp::now()).await;
+            process_seatings(&sessions, &lobbies, &lobby, seatings).await;
+        }
+        lobbies.purge_trash(Timestamp::now()).await;
+    }
+}
+
+/// Represents the announcement sent over HTTP, which carries credentials instead of relying on the session.
+#[derive(Deserialize)]
+struct AnnounceRequest {
+    username: Username,
+    password: Password,
+    text: String,
+    #[serde(default)]
+    severity: AnnouncementSeverity,
+    #[serde(default)]
+    audience: AnnouncementAudience,
+}
+
+async fn handle_announce(
+    request: AnnounceRequest,
+    remote_address: Option<SocketAddr>,
+    permissions: Arc<Permissions>,
+    sessions: SharedSessions,
+    audit_log: SharedAuditLog,
+) -> WithStatus<Json> {
+    let audited_request = Input::Announce {
+        text: request.text.clone(),
+        severity: request.severity,
+        audience: request.audience,
+    };
+    let (output, status_code) = match service::authenticate(&request.username, &request.password) {
+        Some(user_type) if permissions.allows(&user_type, InputDiscriminants::Announce) => {
+            let output = service::announce(request.text, request.severity, request.audience, &sessions).await;
+            (output, StatusCode::OK)
+        }
+        Some(_) => (Output::NotAuthorized, StatusCode::FORBIDDEN),
+        None => (Output::NotAuthenticated, StatusCode::UNAUTHORIZED),
+    };
+    let entry = AuditEntry {
+        timestamp: Timestamp::now(),
+        client_id: None,
+        username: Some(request.username),
+        remote_address,
+        request: audited_request,
+        outcome: Some(output.clone()),
+    };
+    audit_log.append(entry).await;
+    reply::with_status(reply::json(&output), status_code)
 }
 
-async fn handle_connect(ws: WebSocket, sessions: SharedSessions, lobby: SharedLobby) {
+async fn handle_connect(ws: WebSocket, remote_address: Option<SocketAddr>, context: ServiceContext) {
+    let sessions = &context.sessions;
     let client_id = ClientId::new();
-    debug!("Connected client {:?}", client_id);
+    debug!("Connected client {:?} from {:?}", client_id, remote_address);
 
     let (mut ws_sender, mut ws_receiver) = ws.split();
-    let (client_sender, client_receiver) = mpsc::unbounded_channel::<Output>();
-    let mut client_receiver = UnboundedReceiverStream::new(client_receiver);
+    let (client_sender, client_receiver) = session::client_channel();
+    let mut client_receiver = ReceiverStream::new(client_receiver);
 
-    // Spawn a task per client that serializes and sends outgoing messages
+    // Spawn a task per client that sends outgoing messages, which are already encoded by sessions, copying each
+    // of them into the message owned by warp, the connection is closed once the client session is removed
+    // (e.g. kicked by admin or lagging)
     tokio::task::spawn(async move {
-        while let Some(output) = client_receiver.next().await {
-            match serde_json::to_string(&output).map(Message::text) {
-                Ok(message) => {
-                    ws_sender
-                        .send(message)
-                        .unwrap_or_else(|e| {
-                            error!("Failed to send WebSocket message to client {:?}: {}", client_id, e);
-                        })
-                        .await;
-                }
-                Err(e) => {
-                    error!("Failed to serialize WebSocket message for client {:?}: {}", client_id, e);
-                }
-            };
+        while let Some(frame) = client_receiver.next().await {
+            ws_sender
+                .send(Message::text(frame.as_ref()))
+                .unwrap_or_else(|e| {
+                    error!("Failed to send WebSocket message to client {:?}: {}", client_id, e);
+                })
+                .await;
         }
+        ws_sender
+            .close()
+            .unwrap_or_else(|e| {
+                debug!("Failed to close WebSocket for client {:?}: {}", client_id, e);
+            })
+            .await;
     });
 
     // Add the new client session
-    sessions.add(client_id, client_sender).await;
+    sessions.add(client_id, client_sender, remote_address).await;
 
     // Receive, deserialize and process incoming messages
     while let Some(result) = ws_receiver.next().await {
@@ -66,7 +213,7 @@ async fn handle_connect(ws: WebSocket, sessions: SharedSessions, lobby: SharedLo
             Ok(message) => match message.to_str() {
                 Ok(string) => {
                     let input: Result<Input, SerdeError> = serde_json::from_str(string);
-                    process_input(client_id, &sessions, &lobby, input).await;
+                    process_input(client_id, &context, input).await;
                 }
                 Err(_) => {
                     debug!("Received non-text WebSocket message from client {:?}, ignoring", client_id);
@@ -78,29 +225,78 @@ async fn handle_connect(ws: WebSocket, sessions: SharedSessions, lobby: SharedLo
             }
         };
     }
-    handle_disconnect(client_id, &sessions).await;
+    handle_disconnect(client_id, sessions, &context.lobbies).await;
 }
 
-async fn handle_disconnect(client_id: ClientId, sessions: &SharedSessions) {
+/// Frees seats of clients dropped by the server as soon as they are dropped.
+async fn release_dropped_clients(
+    mut dropped_receiver: mpsc::UnboundedReceiver<ClientId>,
+    sessions: SharedSessions,
+    lobbies: SharedLobbies,
+) {
+    while let Some(client_id) = dropped_receiver.recv().await {
+        debug!("Client {:?} has been dropped", client_id);
+        release_seats(client_id, &sessions, &lobbies).await;
+    }
+}
+
+async fn handle_disconnect(client_id: ClientId, sessions: &SharedSessions, lobbies: &SharedLobbies) {
     debug!("Client {:?} has disconnected", client_id);
     sessions.remove(client_id).await;
+    release_seats(client_id, sessions, lobbies).await;
 }
 
-async fn process_input(
-    client_id: ClientId,
+/// Frees the seats taken by the client and notifies waiting clients, locking one lobby at a time and visiting only
+/// lobbies, where the client has been seated or waiting. Seats of dropped clients have been freed already, so that
+/// nothing is left to free once their connections close.
+async fn release_seats(client_id: ClientId, sessions: &SharedSessions, lobbies: &SharedLobbies) {
+    for (lobby, shared_lobby) in lobbies.take_client_lobbies(client_id).await {
+        let _change_guard = shared_lobby.lock_changes().await;
+        let seatings = shared_lobby.leave_all_tables(client_id).await;
+        process_seatings(sessions, lobbies, &lobby, seatings).await;
+    }
+}
+
+/// Sends changes to seats to the affected clients and broadcasts the changed tables to subscribers of the lobby.
+async fn process_seatings(
     sessions: &SharedSessions,
-    lobby: &SharedLobby,
-    input: Result<Input, SerdeError>,
+    lobbies: &SharedLobbies,
+    lobby: &LobbyName,
+    seatings: Vec<Seating>,
 ) {
+    for seating in seatings {
+        let (subscription_output, client_outputs) = service::seating_outputs(lobby, seating);
+        for (client_id, output) in client_outputs {
+            process_output(client_id, sessions, output).await;
+        }
+        if let Some(subscription_output) = subscription_output {
+            broadcast(sessions, lobbies, subscription_output).await;
+        }
+    }
+}
+
+async fn process_input(client_id: ClientId, context: &ServiceContext, input: Result<Input, SerdeError>) {
+    let sessions = &context.sessions;
+    let lobbies = &context.lobbies;
+    // Lock the lobby for changes to its tables and for subscriptions until they are broadcasted or registered,
+    // so that no change is made between taking the snapshot and registering the subscription
+    let _change_guard = match input.as_ref().ok().and_then(Input::locked_lobby) {
+        Some(lobby) => lobbies.lock_changes(lobby).await,
+        None => None,
+    };
+
     let action: ClientSessionAction = match input {
         Ok(input) => match sessions.read_user_type(client_id).await {
             Ok(user_type) => {
-                let process_result = service::process(input, &user_type, lobby).await;
+                let process_result = service::process(client_id, input, &user_type, context).await;
                 if let Some(output) = process_result.output {
-                    process_output(client_id, &sessions, output).await;
+                    process_output(client_id, sessions, output).await;
+                }
+                for (client_id, output) in process_result.client_outputs {
+                    process_output(client_id, sessions, output).await;
                 }
                 if let Some(subscription_output) = process_result.subscription_output {
-                    broadcast(&sessions, subscription_output).await;
+                    broadcast(sessions, lobbies, subscription_output).await;
                 }
                 process_result.action
             }
@@ -111,27 +307,27 @@ async fn process_input(
         },
         Err(e) => {
             error!("Failed to deserialize WebSocket message for client {:?}: {}", client_id, e);
-            process_output(client_id, &sessions, Output::InvalidMessage).await;
+            process_output(client_id, sessions, Output::InvalidMessage).await;
             DoNothing
         }
     };
 
     match action {
         DoNothing => {}
-        UpdateUserType { user_type } => {
+        UpdateUser { username, user_type } => {
             sessions
-                .write_user_type(client_id, user_type)
+                .write_user(client_id, username, user_type)
                 .await
                 .unwrap_or_else(|e| {
-                    error!("Failed to write user type for client {:?}: {}", client_id, e);
+                    error!("Failed to write user for client {:?}: {}", client_id, e);
                 });
         }
-        UpdateSubscribed { subscribed } => {
+        UpdateSubscription { lobby, subscription } => {
             sessions
-                .write_subscribed(client_id, subscribed)
+                .write_subscription(client_id, lobby, subscription)
                 .await
                 .unwrap_or_else(|e| {
-                    error!("Failed to write subscribed for client {:?}: {}", client_id, e);
+                    error!("Failed to write subscription for client {:?}: {}", client_id, e);
                 });
         }
     }
@@ -143,7 +339,16 @@ async fn process_output(client_id: ClientId, sessions: &SharedSessions, output:
     });
 }
 
-async fn broadcast(sessions: &SharedSessions, output: Output) {
-    let broadcast_result = sessions.broadcast(output).await;
+/// Broadcasts the change to subscribers of its lobby, taking the snapshot of tables of the lobby only once
+/// any subscriber can see only some of them, as the snapshot is rebuilt after each change.
+async fn broadcast(sessions: &SharedSessions, lobbies: &SharedLobbies, output: Output) {
+    let tables = match output.lobby() {
+        Some(lobby) if sessions.needs_tables(lobby).await => match lobbies.get(lobby).await {
+            Ok(shared_lobby) => shared_lobby.read_tables().await,
+            Err(_) => TableSnapshot::default(),
+        },
+        _ => TableSnapshot::default(),
+    };
+    let broadcast_result = sessions.broadcast(output, &tables).await;
     debug!("Broadcasted message: {:?}", broadcast_result);
 }
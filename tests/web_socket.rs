@@ -0,0 +1,474 @@
+use futures_util::stream::{SplitSink, SplitStream};
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+use lobby_api_rust::client::{ClientConfig, ClientStatus, LobbyClient};
+use lobby_api_rust::idempotency::IDEMPOTENCY_WINDOW;
+use lobby_api_rust::lobby::TRASH_RETENTION;
+use lobby_api_rust::permission::Permissions;
+use lobby_api_rust::protocol::{Password, Table, TableId, Username};
+use lobby_api_rust::table_id::TableIdConfig;
+use lobby_api_rust::validation::TableRules;
+use lobby_api_rust::web_socket;
+
+/// The time to wait for the expected message.
+const RECEIVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The time to wait before concluding that no message is coming.
+const SILENCE_TIMEOUT: Duration = Duration::from_millis(200);
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Starts the server on the ephemeral port and returns its address.
+fn start_server() -> SocketAddr {
+    let (address, server) = web_socket::bind(
+        ([127, 0, 0, 1], 0),
+        TableIdConfig::default(),
+        TableRules::default(),
+        TRASH_RETENTION,
+        Permissions::default(),
+        IDEMPOTENCY_WINDOW,
+    );
+    tokio::task::spawn(server);
+    address
+}
+
+/// Represents the test client, which sends and receives JSON messages over the real WebSocket connection.
+struct TestClient {
+    name: &'static str,
+    sender: SplitSink<WsStream, Message>,
+    receiver: SplitStream<WsStream>,
+}
+impl TestClient {
+    async fn connect(address: SocketAddr, name: &'static str) -> Self {
+        let url = format!("ws://{}/lobby_api", address);
+        let (ws, _) = tokio_tungstenite::connect_async(url)
+            .await
+            .expect("Client should connect");
+        let (sender, receiver) = ws.split();
+        TestClient { name, sender, receiver }
+    }
+
+    /// Connects and logs in with the given credentials, which are also used as the client name.
+    async fn login(address: SocketAddr, name: &'static str) -> Self {
+        let mut client = Self::connect(address, name).await;
+        client
+            .send(json!({
+                "$type": "login",
+                "username": name,
+                "password": name
+            }))
+            .await;
+        let output = client.receive().await;
+        assert_eq!(output["$type"], "login_successful", "Client {} should log in", name);
+        client
+    }
+
+    /// Logs in and subscribes to tables of the default lobby, returning the received table list.
+    async fn subscribe(address: SocketAddr, name: &'static str) -> (Self, Value) {
+        let mut client = Self::login(address, name).await;
+        client.send(json!({ "$type": "subscribe_tables" })).await;
+        let output = client.receive().await;
+        assert_eq!(output["$type"], "table_list", "Client {} should subscribe", name);
+        (client, output)
+    }
+
+    async fn send(&mut self, input: Value) {
+        self.sender
+            .send(Message::Text(input.to_string()))
+            .await
+            .unwrap_or_else(|e| panic!("Client {} should send message: {}", self.name, e));
+    }
+
+    /// Receives the next text message as JSON, failing if it does not arrive in time.
+    async fn receive(&mut self) -> Value {
+        self.try_receive(RECEIVE_TIMEOUT)
+            .await
+            .unwrap_or_else(|| panic!("Client {} should receive message", self.name))
+    }
+
+    async fn expect_silence(&mut self) {
+        if let Some(output) = self.try_receive(SILENCE_TIMEOUT).await {
+            panic!("Client {} should not receive message, but received {}", self.name, output);
+        }
+    }
+
+    async fn try_receive(&mut self, timeout: Duration) -> Option<Value> {
+        loop {
+            match tokio::time::timeout(timeout, self.receiver.next()).await {
+                Ok(Some(Ok(Message::Text(text)))) => {
+                    return Some(serde_json::from_str(&text).expect("Message should be JSON"));
+                }
+                Ok(Some(Ok(Message::Close(_)))) | Ok(None) | Err(_) => return None,
+                Ok(Some(Ok(_))) => continue,
+                Ok(Some(Err(e))) => panic!("Client {} should receive message: {}", self.name, e),
+            }
+        }
+    }
+}
+
+/// Posts the announcement with the given credentials over plain HTTP and returns the response status code.
+async fn post_announcement(address: SocketAddr, username: &str, password: &str) -> u16 {
+    let body = json!({ "username": username, "password": password, "text": "Maintenance" }).to_string();
+    let request = format!(
+        "POST /announcements HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        address,
+        body.len(),
+        body
+    );
+    let mut stream = TcpStream::connect(address).await.expect("Client should connect");
+    stream
+        .write_all(request.as_bytes())
+        .await
+        .expect("Client should send request");
+    let mut response = String::new();
+    stream
+        .read_to_string(&mut response)
+        .await
+        .expect("Client should receive response");
+    response
+        .split(' ')
+        .nth(1)
+        .and_then(|status_code| status_code.parse().ok())
+        .expect("Response should have status code")
+}
+
+fn table_id(table: &Value) -> i64 {
+    table["id"].as_i64().expect("Table should have id")
+}
+
+#[tokio::test]
+async fn reject_unauthenticated_and_unauthorized_messages() {
+    let address = start_server();
+    let mut anonymous = TestClient::connect(address, "anonymous").await;
+    let mut user = TestClient::login(address, "user").await;
+
+    // when
+    anonymous.send(json!({ "$type": "subscribe_tables" })).await;
+    user.send(json!({ "$type": "remove_table", "id": 1 })).await;
+    user.send(json!({ "$type": "unknown" })).await;
+
+    // then
+    assert_eq!(anonymous.receive().await, json!({ "$type": "not_authenticated" }));
+    assert_eq!(user.receive().await, json!({ "$type": "not_authorized" }));
+    assert_eq!(user.receive().await, json!({ "$type": "invalid_message" }));
+}
+
+#[tokio::test]
+async fn reject_unauthenticated_and_unauthorized_announcements() {
+    let address = start_server();
+
+    // when
+    let unauthenticated_status_code = post_announcement(address, "operator", "wrong").await;
+    let unauthorized_status_code = post_announcement(address, "user", "user").await;
+    let announced_status_code = post_announcement(address, "operator", "operator").await;
+
+    // then
+    assert_eq!(unauthenticated_status_code, 401);
+    assert_eq!(unauthorized_status_code, 403);
+    assert_eq!(announced_status_code, 200);
+}
+
+#[tokio::test]
+async fn broadcast_admin_changes_to_subscribers_in_order() {
+    let address = start_server();
+    let mut admin = TestClient::login(address, "admin").await;
+    let (mut user_1, table_list) = TestClient::subscribe(address, "user").await;
+    let (mut user_2, _) = TestClient::subscribe(address, "viewer").await;
+    let mut unsubscribed = TestClient::login(address, "user").await;
+    let first_id = table_id(&table_list["tables"][0]);
+
+    // when
+    admin
+        .send(json!({
+            "$type": "add_table",
+            "after_id": -1,
+            "table": { "name": "Foo Fighters" }
+        }))
+        .await;
+    admin
+        .send(json!({
+            "$type": "patch_table",
+            "id": first_id,
+            "changes": { "capacity": 5 }
+        }))
+        .await;
+    admin.send(json!({ "$type": "remove_table", "id": first_id })).await;
+
+    // then
+    for subscriber in [&mut user_1, &mut user_2] {
+        let added = subscriber.receive().await;
+        assert_eq!(added["$type"], "table_added");
+        assert_eq!(added["table"]["name"], "Foo Fighters");
+        assert_eq!(
+            subscriber.receive().await,
+            json!({ "$type": "table_patched", "id": first_id, "changes": { "capacity": 5 } })
+        );
+        assert_eq!(subscriber.receive().await, json!({ "$type": "table_removed", "id": first_id }));
+    }
+    admin.expect_silence().await;
+    unsubscribed.expect_silence().await;
+}
+
+#[tokio::test]
+async fn audit_successful_change_with_its_outcome() {
+    let address = start_server();
+    let mut admin = TestClient::login(address, "admin").await;
+    admin
+        .send(json!({
+            "$type": "add_table",
+            "placement": "last",
+            "table": { "name": "Foo Fighters" }
+        }))
+        .await;
+
+    // when
+    admin.send(json!({ "$type": "query_audit_log", "limit": 1 })).await;
+
+    // then
+    let audit_log = admin.receive().await;
+    assert_eq!(audit_log["$type"], "audit_log");
+    let outcome = &audit_log["entries"][0]["outcome"];
+    assert_eq!(outcome["$type"], "table_added");
+    assert_eq!(outcome["table"]["name"], "Foo Fighters");
+    assert!(outcome["table"]["id"].is_i64(), "Outcome should contain id of the added table");
+}
+
+#[tokio::test]
+async fn apply_retried_change_with_same_idempotency_key_once() {
+    let address = start_server();
+    let (mut user, _) = TestClient::subscribe(address, "user").await;
+    let add_table = json!({
+        "$type": "add_table",
+        "placement": "last",
+        "idempotency_key": "add-foo-fighters",
+        "table": { "name": "Foo Fighters" }
+    });
+    let mut admin = TestClient::login(address, "admin").await;
+    admin.send(add_table.clone()).await;
+    let added = user.receive().await;
+    drop(admin);
+
+    // when
+    let mut admin = TestClient::login(address, "admin").await;
+    admin.send(add_table).await;
+
+    // then
+    assert_eq!(admin.receive().await, added, "Retrying admin should receive the original output");
+    user.expect_silence().await;
+    admin.send(json!({ "$type": "subscribe_tables" })).await;
+    let table_list = admin.receive().await;
+    assert_eq!(table_list["tables"].as_array().map(Vec::len), Some(3), "Table should be added once");
+}
+
+#[tokio::test]
+async fn broadcast_restored_table_as_added() {
+    let address = start_server();
+    let mut admin = TestClient::login(address, "admin").await;
+    let (mut user, table_list) = TestClient::subscribe(address, "user").await;
+    let first_table = table_list["tables"][0].clone();
+    let first_id = table_id(&first_table);
+
+    // when
+    admin.send(json!({ "$type": "remove_table", "id": first_id })).await;
+    admin.send(json!({ "$type": "restore_table", "id": first_id })).await;
+
+    // then
+    assert_eq!(user.receive().await, json!({ "$type": "table_removed", "id": first_id }));
+    assert_eq!(user.receive().await, json!({ "$type": "table_added", "after_id": -1, "table": first_table }));
+    admin.expect_silence().await;
+}
+
+#[tokio::test]
+async fn stop_broadcasting_after_unsubscribe() {
+    let address = start_server();
+    let mut admin = TestClient::login(address, "admin").await;
+    let (mut user, table_list) = TestClient::subscribe(address, "user").await;
+
+    // when
+    user.send(json!({ "$type": "unsubscribe_tables" })).await;
+    user.send(json!({ "$type": "ping", "seq": 1 })).await;
+    assert_eq!(user.receive().await, json!({ "$type": "pong", "seq": 1 }));
+    admin
+        .send(json!({ "$type": "remove_table", "id": table_id(&table_list["tables"][0]) }))
+        .await;
+
+    // then
+    user.expect_silence().await;
+}
+
+#[tokio::test]
+async fn promote_waiting_client_once_seated_client_disconnects() {
+    let address = start_server();
+    let mut admin = TestClient::login(address, "admin").await;
+    admin
+        .send(json!({
+            "$type": "add_table",
+            "after_id": -1,
+            "table": { "name": "Foo Fighters", "capacity": 1 }
+        }))
+        .await;
+    admin.send(json!({ "$type": "subscribe_tables" })).await;
+    let table_list = admin.receive().await;
+    let id = table_id(&table_list["tables"][0]);
+
+    let mut seated = TestClient::login(address, "user").await;
+    seated.send(json!({ "$type": "join_table", "id": id })).await;
+    assert_eq!(seated.receive().await, json!({ "$type": "table_joined", "id": id }));
+
+    let mut waiting = TestClient::login(address, "user").await;
+    waiting.send(json!({ "$type": "join_waitlist", "id": id })).await;
+    assert_eq!(waiting.receive().await, json!({ "$type": "waitlist_position", "id": id, "position": 1 }));
+
+    // when
+    drop(seated);
+
+    // then
+    assert_eq!(waiting.receive().await, json!({ "$type": "table_joined", "id": id }));
+}
+
+#[tokio::test]
+async fn disconnect_kicked_client() {
+    let address = start_server();
+    let mut admin = TestClient::login(address, "admin").await;
+    let mut user = TestClient::login(address, "user").await;
+    admin.send(json!({ "$type": "list_sessions" })).await;
+    let session_list = admin.receive().await;
+    let client_id = session_list["sessions"]
+        .as_array()
+        .expect("Sessions should be listed")
+        .iter()
+        .find(|session| session["username"] == "user")
+        .expect("User session should be listed")["client_id"]
+        .clone();
+
+    // when
+    admin
+        .send(json!({ "$type": "kick_session", "client_id": client_id, "reason": "Spamming" }))
+        .await;
+
+    // then
+    assert_eq!(admin.receive().await, json!({ "$type": "session_kicked", "client_id": client_id }));
+    assert_eq!(user.receive().await, json!({ "$type": "kicked", "reason": "Spamming" }));
+    assert_eq!(user.try_receive(RECEIVE_TIMEOUT).await, None, "Connection should be closed");
+}
+
+#[tokio::test]
+async fn promote_waiting_client_once_seated_client_is_kicked() {
+    let address = start_server();
+    let mut admin = TestClient::login(address, "admin").await;
+    admin
+        .send(json!({
+            "$type": "add_table",
+            "after_id": -1,
+            "table": { "name": "Foo Fighters", "capacity": 1 }
+        }))
+        .await;
+    admin.send(json!({ "$type": "subscribe_tables" })).await;
+    let table_list = admin.receive().await;
+    let id = table_id(&table_list["tables"][0]);
+
+    let mut seated = TestClient::login(address, "user").await;
+    admin.send(json!({ "$type": "list_sessions" })).await;
+    let session_list = admin.receive().await;
+    let client_id = session_list["sessions"]
+        .as_array()
+        .expect("Sessions should be listed")
+        .iter()
+        .find(|session| session["username"] == "user")
+        .expect("User session should be listed")["client_id"]
+        .clone();
+    seated.send(json!({ "$type": "join_table", "id": id })).await;
+    assert_eq!(seated.receive().await, json!({ "$type": "table_joined", "id": id }));
+
+    let mut waiting = TestClient::login(address, "user").await;
+    waiting.send(json!({ "$type": "join_waitlist", "id": id })).await;
+    assert_eq!(waiting.receive().await, json!({ "$type": "waitlist_position", "id": id, "position": 1 }));
+
+    // when
+    admin
+        .send(json!({ "$type": "kick_session", "client_id": client_id, "reason": "Spamming" }))
+        .await;
+
+    // then
+    assert_eq!(waiting.receive().await, json!({ "$type": "table_joined", "id": id }));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn converge_subscribers_during_concurrent_changes() {
+    let address = start_server();
+    let url = format!("ws://{}/lobby_api", address);
+
+    // Keep changing tables, while clients subscribe
+    let mut admin = TestClient::login(address, "admin").await;
+    let changes = tokio::task::spawn(async move {
+        for index in 0..300 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+            admin
+                .send(json!({
+                    "$type": "add_table",
+                    "after_id": -1,
+                    "table": { "name": format!("Table {}", index) }
+                }))
+                .await;
+        }
+        admin
+    });
+    let clients: Vec<LobbyClient> = (0..50)
+        .map(|_| {
+            LobbyClient::connect(ClientConfig::new(
+                url.clone(),
+                Username::new(String::from("user")),
+                Password::new(String::from("user")),
+            ))
+        })
+        .collect();
+    let mut admin = changes.await.expect("Changes should be made");
+
+    // Wait until the last change is processed
+    admin.send(json!({ "$type": "subscribe_tables" })).await;
+    let table_list = admin.receive().await;
+    let expected_tables: Vec<Table> =
+        serde_json::from_value(table_list["tables"].clone()).expect("Tables should be deserialized");
+
+    for client in clients {
+        let mut snapshots = client.snapshots();
+        let converged = tokio::time::timeout(RECEIVE_TIMEOUT, async {
+            while client.tables() != expected_tables {
+                snapshots.next().await;
+            }
+        })
+        .await;
+        let ids: Vec<TableId> = client.tables().iter().map(|table| table.id).collect();
+        assert!(converged.is_ok(), "Client should converge, but has tables {:?}", ids);
+    }
+}
+
+#[tokio::test]
+async fn report_failed_login_to_client() {
+    let address = start_server();
+    let client = LobbyClient::connect(ClientConfig::new(
+        format!("ws://{}/lobby_api", address),
+        Username::new(String::from("user")),
+        Password::new(String::from("wrong")),
+    ));
+    let mut statuses = client.statuses();
+
+    // when
+    let login_failed = tokio::time::timeout(RECEIVE_TIMEOUT, async {
+        while client.status() != ClientStatus::LoginFailed {
+            statuses.next().await;
+        }
+    })
+    .await;
+
+    // then
+    assert!(login_failed.is_ok(), "Client should report failed login, but is {:?}", client.status());
+}